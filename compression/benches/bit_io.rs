@@ -0,0 +1,113 @@
+use compression::test_util::{ReferenceBitReader, ReferenceBitWriter};
+use compression::{compress_block, decompress_block, BitReader, BitWriter};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tsdb_core::DataPoint;
+
+/// A mix of widths resembling a Gorilla stream: mostly short control codes
+/// with the occasional long XOR payload.
+fn sample_writes(count: usize) -> Vec<(u64, usize)> {
+    let widths = [1, 2, 7, 1, 1, 5, 6, 23, 1, 2, 9, 1, 38, 4, 12, 64];
+    (0..count)
+        .map(|i| {
+            let value = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            (value, widths[i % widths.len()])
+        })
+        .collect()
+}
+
+fn sample_points(count: usize) -> Vec<DataPoint> {
+    (0..count as u64)
+        .map(|i| {
+            let jitter = (i * 7919) % 5;
+            let value = 30.0 + 20.0 * (i as f64 * 0.01).sin() + (i % 17) as f64 * 0.5;
+            DataPoint::new(1_600_000_000_000 + i * 1000 + jitter, value)
+        })
+        .collect()
+}
+
+fn bench_bit_writer(c: &mut Criterion) {
+    let writes = sample_writes(100_000);
+    let mut group = c.benchmark_group("bit_writer");
+    group.throughput(Throughput::Elements(writes.len() as u64));
+    
+    group.bench_function("naive", |b| {
+        b.iter(|| {
+            let mut writer = ReferenceBitWriter::new();
+            for &(value, num_bits) in &writes {
+                writer.write_bits(value, num_bits);
+            }
+            black_box(writer.finish())
+        })
+    });
+    
+    group.bench_function("word", |b| {
+        b.iter(|| {
+            let mut writer = BitWriter::new();
+            for &(value, num_bits) in &writes {
+                writer.write_bits(value, num_bits).unwrap();
+            }
+            black_box(writer.finish())
+        })
+    });
+    
+    group.finish();
+}
+
+fn bench_bit_reader(c: &mut Criterion) {
+    let writes = sample_writes(100_000);
+    let mut writer = BitWriter::new();
+    for &(value, num_bits) in &writes {
+        writer.write_bits(value, num_bits).unwrap();
+    }
+    let data = writer.finish();
+    
+    let mut group = c.benchmark_group("bit_reader");
+    group.throughput(Throughput::Elements(writes.len() as u64));
+    
+    group.bench_function("naive", |b| {
+        b.iter(|| {
+            let mut reader = ReferenceBitReader::new(data.clone());
+            let mut checksum = 0u64;
+            for &(_, num_bits) in &writes {
+                checksum ^= reader.read_bits(num_bits);
+            }
+            black_box(checksum)
+        })
+    });
+    
+    group.bench_function("word", |b| {
+        b.iter(|| {
+            let mut reader = BitReader::new(&data);
+            let mut checksum = 0u64;
+            for &(_, num_bits) in &writes {
+                checksum ^= reader.read_bits(num_bits).unwrap();
+            }
+            black_box(checksum)
+        })
+    });
+    
+    group.finish();
+}
+
+fn bench_block(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block");
+    
+    // 7200 points is a 2-hour block at one point per second.
+    for count in [7_200, 100_000] {
+        let points = sample_points(count);
+        let block = compress_block(&points).unwrap();
+        group.throughput(Throughput::Elements(count as u64));
+        
+        group.bench_with_input(BenchmarkId::new("compress", count), &points, |b, points| {
+            b.iter(|| black_box(compress_block(points).unwrap()))
+        });
+        group.bench_with_input(BenchmarkId::new("decompress", count), &block, |b, block| {
+            b.iter(|| black_box(decompress_block(block).unwrap()))
+        });
+    }
+    
+    group.finish();
+}
+
+criterion_group!(benches, bench_bit_writer, bench_bit_reader, bench_block);
+criterion_main!(benches);
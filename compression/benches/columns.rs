@@ -0,0 +1,49 @@
+use compression::{compress_block, decompress_block, decompress_block_columns, kernels};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use tsdb_core::DataPoint;
+
+fn sample_points(count: usize) -> Vec<DataPoint> {
+    (0..count as u64)
+        .map(|i| DataPoint::new(1_600_000_000_000 + i * 1000, 30.0 + 20.0 * (i as f64 * 0.01).sin()))
+        .collect()
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let block = compress_block(&sample_points(100_000)).unwrap();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Elements(block.count as u64));
+    
+    group.bench_function("points", |b| {
+        b.iter(|| black_box(decompress_block(&block).unwrap()))
+    });
+    group.bench_function("columns", |b| {
+        b.iter(|| black_box(decompress_block_columns(&block).unwrap()))
+    });
+    
+    group.finish();
+}
+
+fn bench_aggregate(c: &mut Criterion) {
+    let points = sample_points(1_000_000);
+    let values: Vec<f64> = points.iter().filter_map(|p| p.value.as_f64()).collect();
+    let mut group = c.benchmark_group("aggregate");
+    group.throughput(Throughput::Elements(points.len() as u64));
+    
+    group.bench_function("sum_points", |b| {
+        b.iter(|| black_box(points.iter().filter_map(|p| p.value.as_f64()).sum::<f64>()))
+    });
+    group.bench_function("sum_kernel", |b| {
+        b.iter(|| black_box(kernels::sum(&values)))
+    });
+    group.bench_function("max_points", |b| {
+        b.iter(|| black_box(points.iter().filter_map(|p| p.value.as_f64()).fold(f64::NEG_INFINITY, f64::max)))
+    });
+    group.bench_function("max_kernel", |b| {
+        b.iter(|| black_box(kernels::max(&values)))
+    });
+    
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_aggregate);
+criterion_main!(benches);
@@ -0,0 +1,176 @@
+use compression::{BitWriter, Chimp128Compressor, ChimpCompressor, ValueCompressor};
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput};
+use std::time::Duration;
+
+// The generators below mirror the values of the sample data loaded by
+// `src/bin/client.rs`, so the numbers here match what the demo reports.
+
+fn generate_cpu_values() -> Vec<f64> {
+    (0..3600)
+        .map(|i| {
+            let wave = 20.0 * (i as f64 * 0.01).sin();
+            let noise = (i % 17) as f64 * 0.5;
+            30.0 + wave + noise
+        })
+        .collect()
+}
+
+fn generate_memory_values() -> Vec<f64> {
+    (0..3600)
+        .map(|i| {
+            let growth = i as f64 * 1000.0;
+            let spike = if i % 300 == 0 { 500_000_000.0 } else { 0.0 };
+            4_000_000_000.0 + growth + spike
+        })
+        .collect()
+}
+
+fn generate_network_values() -> Vec<f64> {
+    (0..3600)
+        .map(|i| {
+            let burst = if i % 60 < 10 { 10_000_000.0 } else { 0.0 };
+            let variation = (i as f64 * 0.05).cos() * 500_000.0;
+            1_000_000.0 + burst + variation
+        })
+        .collect()
+}
+
+fn datasets() -> Vec<(&'static str, Vec<f64>)> {
+    vec![
+        ("cpu", generate_cpu_values()),
+        ("memory", generate_memory_values()),
+        ("network", generate_network_values()),
+    ]
+}
+
+/// Writes every value after the first, which each encoder is seeded with.
+type Encode = fn(&[f64], &mut BitWriter);
+
+fn encoders() -> [(&'static str, Encode); 3] {
+    [
+        ("gorilla", |values, writer| {
+            let mut compressor = ValueCompressor::new(values[0]);
+            for &value in &values[1..] {
+                compressor.compress(value, writer).unwrap();
+            }
+        }),
+        ("chimp", |values, writer| {
+            let mut compressor = ChimpCompressor::new(values[0]);
+            for &value in &values[1..] {
+                compressor.compress(value, writer).unwrap();
+            }
+        }),
+        ("chimp128", |values, writer| {
+            let mut compressor = Chimp128Compressor::new(values[0]);
+            for &value in &values[1..] {
+                compressor.compress(value, writer).unwrap();
+            }
+        }),
+    ]
+}
+
+/// Measures the bits an encoder writes per value rather than time, so
+/// criterion reports and compares encoded sizes like any other result.
+struct BitsPerValue;
+
+impl Measurement for BitsPerValue {
+    type Intermediate = ();
+    type Value = f64;
+    
+    fn start(&self) {}
+    
+    fn end(&self, _: ()) -> f64 {
+        0.0
+    }
+    
+    fn add(&self, v1: &f64, v2: &f64) -> f64 {
+        v1 + v2
+    }
+    
+    fn zero(&self) -> f64 {
+        0.0
+    }
+    
+    fn to_f64(&self, value: &f64) -> f64 {
+        *value
+    }
+    
+    fn formatter(&self) -> &dyn ValueFormatter {
+        &BitsFormatter
+    }
+}
+
+struct BitsFormatter;
+
+impl ValueFormatter for BitsFormatter {
+    fn scale_values(&self, _typical_value: f64, _values: &mut [f64]) -> &'static str {
+        "bits/value"
+    }
+    
+    fn scale_throughputs(&self, _typical_value: f64, _throughput: &Throughput, _values: &mut [f64]) -> &'static str {
+        "bits/value"
+    }
+    
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "bits/value"
+    }
+}
+
+fn bench_value_bits(c: &mut Criterion<BitsPerValue>) {
+    let mut group = c.benchmark_group("value_bits");
+    // Identical samples; a few are plenty.
+    group.sampling_mode(SamplingMode::Flat);
+    group.sample_size(10);
+    for (name, values) in datasets() {
+        for (encoder, encode) in encoders() {
+            group.bench_with_input(BenchmarkId::new(encoder, name), &values, |b, values| {
+                b.iter_custom(|iters| {
+                    let mut bits = 0.0;
+                    for _ in 0..iters {
+                        let mut writer = BitWriter::new();
+                        encode(values, &mut writer);
+                        bits += writer.bit_count() as f64 / (values.len() - 1) as f64;
+                    }
+                    bits
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_value_encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("value_encode");
+    for (name, values) in datasets() {
+        group.throughput(Throughput::Elements(values.len() as u64 - 1));
+        for (encoder, encode) in encoders() {
+            group.bench_with_input(BenchmarkId::new(encoder, name), &values, |b, values| {
+                b.iter(|| {
+                    let mut writer = BitWriter::new();
+                    encode(black_box(values), &mut writer);
+                    writer
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Encoded sizes do not vary between runs, and the density plots cannot be
+/// drawn from identical samples.
+fn bits_config() -> Criterion<BitsPerValue> {
+    Criterion::default()
+        .with_measurement(BitsPerValue)
+        .without_plots()
+        .warm_up_time(Duration::from_millis(100))
+        .measurement_time(Duration::from_millis(500))
+}
+
+criterion_group! {
+    name = bits;
+    config = bits_config();
+    targets = bench_value_bits
+}
+criterion_group!(timing, bench_value_encode);
+criterion_main!(bits, timing);
@@ -0,0 +1,17 @@
+#![no_main]
+
+//! Feeds arbitrary bytes to the block decoder, as if read from a damaged
+//! file. Most inputs are rejected by the header or checksum checks.
+
+use compression::BlockDecoder;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(decoder) = BlockDecoder::from_bytes(data) {
+        for point in decoder {
+            if point.is_err() {
+                break;
+            }
+        }
+    }
+});
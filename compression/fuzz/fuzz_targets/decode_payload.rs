@@ -0,0 +1,29 @@
+#![no_main]
+
+//! Wraps arbitrary bytes in a valid header and checksum so they reach the
+//! codec decoders themselves. The first byte picks a built-in codec and the
+//! next four the point count.
+
+use compression::{encode_block, BlockDecoder, BlockHeader, CodecRegistry};
+use libfuzzer_sys::fuzz_target;
+use tsdb_core::DataPoint;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 5 {
+        return;
+    }
+    
+    let ids = CodecRegistry::global().ids();
+    let codec_id = ids[data[0] as usize % ids.len()];
+    let count = u32::from_be_bytes([data[1], data[2], data[3], data[4]]).max(1);
+    
+    let header = BlockHeader::new(codec_id, &DataPoint::new(1_600_000_000_000, 1.0), count);
+    let block = encode_block(&header, &data[5..]);
+    
+    let decoder = BlockDecoder::from_bytes(&block).expect("header and checksum are valid");
+    for point in decoder {
+        if point.is_err() {
+            break;
+        }
+    }
+});
@@ -0,0 +1,405 @@
+use crate::error::CompressionError;
+
+/// Writes bits most-significant first into a byte buffer.
+///
+/// Bits are collected in a 64-bit accumulator and flushed a whole word at a
+/// time, so `write_bits` costs a couple of shifts regardless of width. The
+/// output is identical to writing the same bits one at a time.
+#[derive(Debug, Clone)]
+pub struct BitWriter {
+    buffer: Vec<u8>,
+    accumulator: u64,
+    pending_bits: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self {
+            buffer: Vec::new(),
+            accumulator: 0,
+            pending_bits: 0,
+        }
+    }
+    
+    pub fn with_capacity(bytes: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(bytes),
+            accumulator: 0,
+            pending_bits: 0,
+        }
+    }
+    
+    pub fn write_bit(&mut self, bit: u8) -> Result<(), CompressionError> {
+        self.write_bits((bit != 0) as u64, 1)
+    }
+    
+    /// Writes the low `num_bits` bits of `value`; higher bits are ignored.
+    pub fn write_bits(&mut self, value: u64, num_bits: usize) -> Result<(), CompressionError> {
+        if num_bits > 64 {
+            return Err(CompressionError::InvalidBitLength(num_bits));
+        }
+        if num_bits == 0 {
+            return Ok(());
+        }
+        
+        let value = value & low_mask(num_bits);
+        let free_bits = 64 - self.pending_bits;
+        
+        if num_bits < free_bits {
+            self.accumulator = (self.accumulator << num_bits) | value;
+            self.pending_bits += num_bits;
+        } else {
+            // Top up the accumulator, flush it, and keep whatever is left over.
+            let spill = num_bits - free_bits;
+            let word = if free_bits == 64 {
+                value
+            } else {
+                (self.accumulator << free_bits) | (value >> spill)
+            };
+            self.buffer.extend_from_slice(&word.to_be_bytes());
+            self.accumulator = value & low_mask(spill);
+            self.pending_bits = spill;
+        }
+        
+        Ok(())
+    }
+    
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.pending_bits > 0 {
+            let aligned = self.accumulator << (64 - self.pending_bits);
+            let bytes = self.pending_bits.div_ceil(8);
+            self.buffer.extend_from_slice(&aligned.to_be_bytes()[..bytes]);
+        }
+        self.buffer
+    }
+    
+    /// The bytes [`finish`](Self::finish) would return now, leaving the
+    /// writer open for more bits.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.clone().finish()
+    }
+    
+    pub fn bit_count(&self) -> usize {
+        self.buffer.len() * 8 + self.pending_bits
+    }
+    
+    /// Reads back the bits written so far, borrowing the stream rather than
+    /// copying it.
+    pub fn reader(&self) -> BitReader<'_> {
+        let tail = match self.pending_bits {
+            0 => 0,
+            bits => self.accumulator << (64 - bits),
+        };
+        BitReader::with_tail(&self.buffer, tail.to_be_bytes(), self.pending_bits)
+    }
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads bits most-significant first, loading a 64-bit word per call.
+///
+/// The reader borrows its input, so it can run directly over a stored block,
+/// a shared buffer or a memory-mapped file without copying.
+pub struct BitReader<'a> {
+    buffer: &'a [u8],
+    /// Bits that follow `buffer`, most-significant first, for reading a
+    /// [`BitWriter`] whose last word has not been flushed.
+    tail: [u8; 8],
+    bit_len: usize,
+    bit_position: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self::with_tail(buffer, [0; 8], 0)
+    }
+    
+    fn with_tail(buffer: &'a [u8], tail: [u8; 8], tail_bits: usize) -> Self {
+        Self {
+            buffer,
+            tail,
+            bit_len: buffer.len() * 8 + tail_bits,
+            bit_position: 0,
+        }
+    }
+    
+    pub fn read_bit(&mut self) -> Result<u8, CompressionError> {
+        if self.bit_position >= self.bit_len {
+            return Err(CompressionError::InsufficientData);
+        }
+        
+        let byte_index = self.bit_position / 8;
+        let bit_index = 7 - (self.bit_position % 8);
+        
+        let bit = (self.byte(byte_index) >> bit_index) & 1;
+        self.bit_position += 1;
+        
+        Ok(bit)
+    }
+    
+    pub fn read_bits(&mut self, num_bits: usize) -> Result<u64, CompressionError> {
+        let value = self.peek_bits(num_bits)?;
+        self.bit_position += num_bits;
+        Ok(value)
+    }
+    
+    pub fn peek_bits(&self, num_bits: usize) -> Result<u64, CompressionError> {
+        if num_bits > 64 {
+            return Err(CompressionError::InvalidBitLength(num_bits));
+        }
+        if num_bits == 0 {
+            return Ok(0);
+        }
+        if self.bit_position + num_bits > self.bit_len {
+            return Err(CompressionError::InsufficientData);
+        }
+        
+        let byte_index = self.bit_position / 8;
+        let bit_offset = self.bit_position % 8;
+        
+        let mut window = self.load_word(byte_index) << bit_offset;
+        if bit_offset + num_bits > 64 {
+            // The read straddles nine bytes; pull the tail from the ninth.
+            window |= (self.byte(byte_index + 8) as u64) >> (8 - bit_offset);
+        }
+        
+        Ok(window >> (64 - num_bits))
+    }
+    
+    pub fn bit_position(&self) -> usize {
+        self.bit_position
+    }
+    
+    /// Moves to an absolute bit position, which may be the very end of the
+    /// buffer but not past it.
+    pub fn seek(&mut self, bit_position: u64) -> Result<(), CompressionError> {
+        match usize::try_from(bit_position) {
+            Ok(position) if position <= self.bit_len => {
+                self.bit_position = position;
+                Ok(())
+            }
+            _ => Err(CompressionError::InsufficientData),
+        }
+    }
+    
+    pub fn remaining_bits(&self) -> usize {
+        self.bit_len - self.bit_position
+    }
+    
+    fn load_word(&self, byte_index: usize) -> u64 {
+        match self.buffer.get(byte_index..byte_index + 8) {
+            Some(bytes) => u64::from_be_bytes(bytes.try_into().unwrap()),
+            None => {
+                let mut bytes = [0u8; 8];
+                for (offset, byte) in bytes.iter_mut().enumerate() {
+                    *byte = self.byte(byte_index + offset);
+                }
+                u64::from_be_bytes(bytes)
+            }
+        }
+    }
+    
+    /// The byte at `index`, reading on into the tail past the end of the
+    /// buffer, and zero past that.
+    fn byte(&self, index: usize) -> u8 {
+        match self.buffer.get(index) {
+            Some(&byte) => byte,
+            None => self.tail.get(index - self.buffer.len()).copied().unwrap_or(0),
+        }
+    }
+}
+
+fn low_mask(num_bits: usize) -> u64 {
+    if num_bits >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << num_bits) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ReferenceBitWriter;
+    use proptest::prelude::*;
+
+    fn writes() -> impl Strategy<Value = Vec<(u64, usize)>> {
+        prop::collection::vec((any::<u64>(), 0usize..=64), 0..200)
+    }
+
+    #[test]
+    fn test_bit_writer_single_bit() {
+        let mut writer = BitWriter::new();
+        writer.write_bit(1).unwrap();
+        writer.write_bit(0).unwrap();
+        writer.write_bit(1).unwrap();
+        
+        let data = writer.finish();
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0], 0b10100000);
+    }
+
+    #[test]
+    fn test_bit_writer_to_bytes_keeps_writing() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3).unwrap();
+        assert_eq!(writer.to_bytes(), vec![0b10100000]);
+        
+        writer.write_bits(0x1FF, 9).unwrap();
+        assert_eq!(writer.to_bytes(), vec![0b10111111, 0b11110000]);
+        assert_eq!(writer.finish(), vec![0b10111111, 0b11110000]);
+    }
+
+    #[test]
+    fn test_bit_writer_multiple_bytes() {
+        let mut writer = BitWriter::new();
+        for i in 0..16 {
+            writer.write_bit(i % 2).unwrap();
+        }
+        
+        let data = writer.finish();
+        assert_eq!(data.len(), 2);
+        assert_eq!(data[0], 0b01010101);
+        assert_eq!(data[1], 0b01010101);
+    }
+
+    #[test]
+    fn test_bit_writer_bits() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3).unwrap();
+        writer.write_bits(0b11, 2).unwrap();
+        
+        let data = writer.finish();
+        assert_eq!(data[0], 0b10111000);
+    }
+
+    #[test]
+    fn test_bit_writer_word_boundary() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b1, 1).unwrap();
+        writer.write_bits(u64::MAX, 64).unwrap();
+        writer.write_bits(0, 7).unwrap();
+        
+        assert_eq!(writer.bit_count(), 72);
+        let data = writer.finish();
+        assert_eq!(data, vec![0xFF; 8].into_iter().chain([0x80]).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn test_bit_writer_invalid_length() {
+        let mut writer = BitWriter::new();
+        assert!(matches!(writer.write_bits(0, 65), Err(CompressionError::InvalidBitLength(65))));
+    }
+
+    #[test]
+    fn test_bit_reader_single_bit() {
+        let data = vec![0b10100000];
+        let mut reader = BitReader::new(&data);
+        
+        assert_eq!(reader.read_bit().unwrap(), 1);
+        assert_eq!(reader.read_bit().unwrap(), 0);
+        assert_eq!(reader.read_bit().unwrap(), 1);
+        assert_eq!(reader.read_bit().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_bit_reader_bits() {
+        let data = vec![0b10111000];
+        let mut reader = BitReader::new(&data);
+        
+        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
+        assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+    }
+
+    #[test]
+    fn test_bit_reader_straddles_nine_bytes() {
+        let data = vec![0x0F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xF0];
+        let mut reader = BitReader::new(&data);
+        
+        assert_eq!(reader.read_bits(4).unwrap(), 0);
+        assert_eq!(reader.read_bits(64).unwrap(), u64::MAX);
+        assert_eq!(reader.read_bits(4).unwrap(), 0);
+        assert!(reader.read_bit().is_err());
+    }
+
+    #[test]
+    fn test_bit_reader_past_end() {
+        let mut reader = BitReader::new(&[0xAB]);
+        
+        assert!(matches!(reader.read_bits(9), Err(CompressionError::InsufficientData)));
+        assert_eq!(reader.bit_position(), 0);
+        assert_eq!(reader.read_bits(8).unwrap(), 0xAB);
+        assert_eq!(reader.remaining_bits(), 0);
+    }
+
+    #[test]
+    fn test_bit_reader_seek() {
+        let mut reader = BitReader::new(&[0b1010_0000, 0xFF]);
+        
+        reader.seek(2).unwrap();
+        assert_eq!(reader.read_bits(2).unwrap(), 0b10);
+        reader.seek(16).unwrap();
+        assert_eq!(reader.remaining_bits(), 0);
+        assert!(matches!(reader.seek(17), Err(CompressionError::InsufficientData)));
+        assert_eq!(reader.bit_position(), 16);
+    }
+
+    #[test]
+    fn test_empty_data_error() {
+        let mut reader = BitReader::new(&[]);
+        
+        assert!(reader.read_bit().is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_writer_matches_reference(ops in writes()) {
+            let mut writer = BitWriter::new();
+            let mut reference = ReferenceBitWriter::new();
+            
+            for &(value, num_bits) in &ops {
+                writer.write_bits(value, num_bits).unwrap();
+                reference.write_bits(value, num_bits);
+            }
+            
+            prop_assert_eq!(writer.bit_count(), reference.bit_count());
+            prop_assert_eq!(writer.finish(), reference.finish());
+        }
+        
+        #[test]
+        fn prop_reader_round_trip(ops in writes()) {
+            let mut writer = BitWriter::new();
+            for &(value, num_bits) in &ops {
+                writer.write_bits(value, num_bits).unwrap();
+            }
+            
+            let encoded = writer.finish();
+            let mut reader = BitReader::new(&encoded);
+            for &(value, num_bits) in &ops {
+                prop_assert_eq!(reader.peek_bits(num_bits).unwrap(), value & low_mask(num_bits));
+                prop_assert_eq!(reader.read_bits(num_bits).unwrap(), value & low_mask(num_bits));
+            }
+            prop_assert!(reader.remaining_bits() < 8);
+        }
+        
+        #[test]
+        fn prop_writer_reader_reads_unflushed_bits(ops in writes()) {
+            let mut writer = BitWriter::new();
+            for &(value, num_bits) in &ops {
+                writer.write_bits(value, num_bits).unwrap();
+            }
+            
+            let mut reader = writer.reader();
+            for &(value, num_bits) in &ops {
+                prop_assert_eq!(reader.peek_bits(num_bits).unwrap(), value & low_mask(num_bits));
+                prop_assert_eq!(reader.read_bits(num_bits).unwrap(), value & low_mask(num_bits));
+            }
+            prop_assert_eq!(reader.remaining_bits(), 0);
+            prop_assert!(reader.read_bit().is_err());
+        }
+    }
+}
@@ -0,0 +1,970 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use crate::codec::{Codec, CodecId, CodecRegistry, GorillaCodec, PointDecoder};
+use crate::diagnostics::BlockStats;
+use crate::typed::{check_value_type, verbatim_bits, verbatim_value};
+use std::borrow::Cow;
+use std::sync::Arc;
+use byteorder::{BigEndian, ReadBytesExt};
+use tsdb_core::{CompressedBlock, DataPoint, TimestampPrecision, ValueType};
+
+/// Marks the start of every encoded block ("GRLA").
+pub const BLOCK_MAGIC: [u8; 4] = *b"GRLA";
+
+/// Layout of [`BlockHeader`] and the payload that follows it.
+///
+/// Version 2 added the 64-bit delta-of-delta escape and moved the 7-bit
+/// bucket from -63..=64 to -64..=63.
+pub const BLOCK_FORMAT_VERSION: u8 = 2;
+
+/// Oldest format version this crate can decode, which is the one it
+/// writes. Version 1 read its 7-bit delta-of-delta bucket differently and
+/// was never released, so it is not supported.
+pub const MIN_BLOCK_FORMAT_VERSION: u8 = BLOCK_FORMAT_VERSION;
+
+/// Position of the checksum field in the header.
+const CHECKSUM_OFFSET: usize = 26;
+const CHECKSUM_END: usize = CHECKSUM_OFFSET + 4;
+
+/// Fixed-size header written at the front of `CompressedBlock::compressed_data`.
+///
+/// The first point of a block seeds both compressors, so it is stored here
+/// verbatim; the bit stream after the header only carries the remaining
+/// `count - 1` points. All fields are big-endian. The first value is kept as
+/// the 64 bits given by [`verbatim_bits`], and its type is the codec's.
+///
+/// The header carries a CRC32C of every other byte of the block, header
+/// included, so any flipped bit is caught before decoding. It also records
+/// the restart interval: when non-zero, the header is followed by a
+/// [`RestartPoint`] for every `restart_interval`th point and then the bit
+/// stream; see [`compress_block_indexed`]. Last comes the
+/// [`TimestampPrecision`] the block's timestamps are counted in, which sizes
+/// their delta-of-delta buckets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockHeader {
+    pub version: u8,
+    pub codec_id: CodecId,
+    pub first_timestamp: u64,
+    pub first_value_bits: u64,
+    pub count: u32,
+    pub checksum: u32,
+    /// Points between restart points; 0 when the block has no index.
+    pub restart_interval: u32,
+    pub precision: TimestampPrecision,
+}
+
+impl BlockHeader {
+    /// magic (4) + version (1) + codec id (1) + first timestamp (8) + first value (8) + count (4)
+    /// + checksum (4) + restart interval (4) + precision (1)
+    pub const SIZE: usize = 35;
+    
+    /// A header with no restart index, for millisecond timestamps. The
+    /// checksum is filled in by [`encode_block`] once the payload is known.
+    pub fn new(codec_id: CodecId, first_point: &DataPoint, count: u32) -> Self {
+        Self {
+            version: BLOCK_FORMAT_VERSION,
+            codec_id,
+            first_timestamp: first_point.timestamp,
+            first_value_bits: verbatim_bits(&first_point.value),
+            count,
+            checksum: 0,
+            restart_interval: 0,
+            precision: TimestampPrecision::Milliseconds,
+        }
+    }
+    
+    /// Number of restart points that follow the header.
+    pub fn restart_count(&self) -> usize {
+        match self.restart_interval {
+            0 => 0,
+            interval => (self.count.saturating_sub(1) / interval) as usize,
+        }
+    }
+    
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&BLOCK_MAGIC);
+        buffer.push(self.version);
+        buffer.push(self.codec_id);
+        buffer.extend_from_slice(&self.first_timestamp.to_be_bytes());
+        buffer.extend_from_slice(&self.first_value_bits.to_be_bytes());
+        buffer.extend_from_slice(&self.count.to_be_bytes());
+        buffer.extend_from_slice(&self.checksum.to_be_bytes());
+        buffer.extend_from_slice(&self.restart_interval.to_be_bytes());
+        buffer.push(precision_to_byte(self.precision));
+    }
+    
+    pub fn read_from(data: &[u8]) -> Result<Self, CompressionError> {
+        if data.len() < Self::SIZE {
+            return Err(CompressionError::InsufficientData);
+        }
+        if data[..4] != BLOCK_MAGIC {
+            return Err(CompressionError::InvalidFormat);
+        }
+        
+        let mut cursor = &data[4..];
+        let version = cursor.read_u8().map_err(|_| CompressionError::InsufficientData)?;
+        if version != BLOCK_FORMAT_VERSION {
+            return Err(CompressionError::UnsupportedVersion(version));
+        }
+        
+        let codec_id = cursor.read_u8().map_err(|_| CompressionError::InsufficientData)?;
+        let first_timestamp = cursor.read_u64::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let first_value_bits = cursor.read_u64::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let count = cursor.read_u32::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let checksum = cursor.read_u32::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let restart_interval = cursor.read_u32::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let byte = cursor.read_u8().map_err(|_| CompressionError::InsufficientData)?;
+        let precision = precision_from_byte(byte).ok_or(CompressionError::InvalidFormat)?;
+        
+        Ok(Self {
+            version,
+            codec_id,
+            first_timestamp,
+            first_value_bits,
+            count,
+            checksum,
+            restart_interval,
+            precision,
+        })
+    }
+}
+
+fn precision_to_byte(precision: TimestampPrecision) -> u8 {
+    match precision {
+        TimestampPrecision::Milliseconds => 0,
+        TimestampPrecision::Microseconds => 1,
+        TimestampPrecision::Nanoseconds => 2,
+    }
+}
+
+fn precision_from_byte(byte: u8) -> Option<TimestampPrecision> {
+    match byte {
+        0 => Some(TimestampPrecision::Milliseconds),
+        1 => Some(TimestampPrecision::Microseconds),
+        2 => Some(TimestampPrecision::Nanoseconds),
+        _ => None,
+    }
+}
+
+/// Where decoding can resume without the points before it: the bit offset
+/// into the stream at which a fresh encoder took over, and the point that
+/// seeded it, with its value kept like the header's first value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RestartPoint {
+    pub bit_offset: u64,
+    pub timestamp: u64,
+    pub value_bits: u64,
+}
+
+impl RestartPoint {
+    /// bit offset (8) + timestamp (8) + value (8)
+    pub const SIZE: usize = 24;
+    
+    pub fn write_to(&self, buffer: &mut Vec<u8>) {
+        buffer.extend_from_slice(&self.bit_offset.to_be_bytes());
+        buffer.extend_from_slice(&self.timestamp.to_be_bytes());
+        buffer.extend_from_slice(&self.value_bits.to_be_bytes());
+    }
+    
+    pub fn read_from(mut data: &[u8]) -> Result<Self, CompressionError> {
+        let bit_offset = data.read_u64::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let timestamp = data.read_u64::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        let value_bits = data.read_u64::<BigEndian>().map_err(|_| CompressionError::InsufficientData)?;
+        Ok(Self { bit_offset, timestamp, value_bits })
+    }
+    
+    fn point(&self, value_type: ValueType) -> DataPoint {
+        DataPoint::new(self.timestamp, verbatim_value(value_type, self.value_bits))
+    }
+}
+
+/// Lays out a block: the header followed by `body`, which is the restart
+/// index, if any, and then the bit stream. The checksum stored in `header`
+/// is replaced by the block's actual checksum.
+pub fn encode_block(header: &BlockHeader, body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(BlockHeader::SIZE + body.len());
+    header.write_to(&mut data);
+    data.extend_from_slice(body);
+    
+    let checksum = block_checksum(&data);
+    data[CHECKSUM_OFFSET..CHECKSUM_END].copy_from_slice(&checksum.to_be_bytes());
+    data
+}
+
+/// CRC32C of an encoded block, skipping the checksum field itself.
+pub fn block_checksum(data: &[u8]) -> u32 {
+    let header = &data[..CHECKSUM_OFFSET.min(data.len())];
+    let rest = data.get(CHECKSUM_END..).unwrap_or(&[]);
+    crc32c::crc32c_append(crc32c::crc32c(header), rest)
+}
+
+/// Checks the checksum of an encoded block. Only the magic and version are
+/// looked at first, so damage to the fields after them is reported as a
+/// checksum mismatch rather than a bad field.
+pub fn verify_checksum(data: &[u8]) -> Result<(), CompressionError> {
+    if data.len() < BlockHeader::SIZE {
+        return Err(CompressionError::InsufficientData);
+    }
+    if data[..4] != BLOCK_MAGIC {
+        return Err(CompressionError::InvalidFormat);
+    }
+    if data[4] != BLOCK_FORMAT_VERSION {
+        return Err(CompressionError::UnsupportedVersion(data[4]));
+    }
+    
+    let expected = u32::from_be_bytes(data[CHECKSUM_OFFSET..CHECKSUM_END].try_into().unwrap());
+    let actual = block_checksum(data);
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(CompressionError::ChecksumMismatch { expected, actual })
+    }
+}
+
+/// Encodes `points` into a self-describing block with the default Gorilla
+/// codec. The block can be decoded with [`decompress_block`] without any
+/// outside context.
+pub fn compress_block(points: &[DataPoint]) -> Result<CompressedBlock, CompressionError> {
+    compress_block_with(points, &GorillaCodec)
+}
+
+/// Encodes `points` with `codec`, recording its id in the block header.
+/// Every point must hold a value of the codec's
+/// [`value_type`](Codec::value_type).
+pub fn compress_block_with(points: &[DataPoint], codec: &dyn Codec) -> Result<CompressedBlock, CompressionError> {
+    compress_block_indexed(points, codec, 0)
+}
+
+/// Encodes `points` with `codec`, starting a fresh encoder every
+/// `restart_interval` points and recording where in an index after the
+/// header. [`BlockDecoder::seek`] uses the index to skip to the part of the
+/// block it needs instead of decoding from the first bit.
+///
+/// Each restart costs an index entry and the compression context the fresh
+/// encoder loses. A `restart_interval` of 0 writes no index.
+pub fn compress_block_indexed(points: &[DataPoint], codec: &dyn Codec, restart_interval: usize) -> Result<CompressedBlock, CompressionError> {
+    compress_block_with_precision(points, codec, restart_interval, TimestampPrecision::Milliseconds)
+}
+
+/// Like [`compress_block_indexed`], for timestamps counted in `precision`.
+/// The precision is recorded in the header, so decoding needs nothing more.
+pub fn compress_block_with_precision(
+    points: &[DataPoint],
+    codec: &dyn Codec,
+    restart_interval: usize,
+    precision: TimestampPrecision,
+) -> Result<CompressedBlock, CompressionError> {
+    let (first_point, last_point) = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(CompressionError::InsufficientData),
+    };
+    let count = u32::try_from(points.len()).map_err(|_| CompressionError::BufferOverflow)?;
+    let interval = u32::try_from(restart_interval).map_err(|_| CompressionError::BufferOverflow)?;
+    
+    check_value_type(first_point, codec.value_type())?;
+    
+    let mut encoder = codec.encoder(first_point, precision);
+    let mut writer = BitWriter::new();
+    encoder.write_first(&mut writer)?;
+    let mut restarts = Vec::new();
+    
+    for (index, point) in points.iter().enumerate().skip(1) {
+        if restart_interval > 0 && index.is_multiple_of(restart_interval) {
+            check_value_type(point, codec.value_type())?;
+            encoder.finish(&mut writer)?;
+            restarts.push(RestartPoint {
+                bit_offset: writer.bit_count() as u64,
+                timestamp: point.timestamp,
+                value_bits: verbatim_bits(&point.value),
+            });
+            encoder = codec.encoder(point, precision);
+            encoder.write_first(&mut writer)?;
+        } else {
+            encoder.encode(point, &mut writer)?;
+        }
+    }
+    encoder.finish(&mut writer)?;
+    
+    let payload = writer.finish();
+    let mut body = Vec::with_capacity(restarts.len() * RestartPoint::SIZE + payload.len());
+    for restart in &restarts {
+        restart.write_to(&mut body);
+    }
+    body.extend_from_slice(&payload);
+    
+    let mut header = BlockHeader::new(codec.id(), first_point, count);
+    header.restart_interval = interval;
+    header.precision = precision;
+    let compressed_data = encode_block(&header, &body);
+    
+    Ok(CompressedBlock {
+        start_timestamp: first_point.timestamp,
+        end_timestamp: last_point.timestamp,
+        count: points.len(),
+        value_type: codec.value_type(),
+        compressed_data,
+    })
+}
+
+/// Decodes a block with the built-in codecs. Blocks with no points and no
+/// data decode to an empty vector.
+pub fn decompress_block(block: &CompressedBlock) -> Result<Vec<DataPoint>, CompressionError> {
+    BlockDecoder::new(block)?.collect()
+}
+
+/// Upper bound on how many points [`BlockDecoder::decode_into`] reserves
+/// room for before decoding.
+const MAX_COLUMN_RESERVE: usize = 1 << 16;
+
+/// Lazily decodes the points of a block in the order they were written.
+///
+/// Nothing past the header is decoded until `next` is called, so callers can
+/// stop as soon as they have what they need. After the first error the
+/// decoder is exhausted. The decoder borrows the block's bytes rather than
+/// copying them.
+///
+/// The checksum is verified when the decoder is created, so a corrupt block
+/// fails with [`CompressionError::ChecksumMismatch`] before any point is
+/// returned.
+pub struct BlockDecoder<'a> {
+    reader: BitReader<'a>,
+    codec: Option<Arc<dyn Codec>>,
+    value_type: ValueType,
+    decoder: Option<Box<dyn PointDecoder>>,
+    first_point: Option<DataPoint>,
+    precision: TimestampPrecision,
+    restart_interval: usize,
+    restarts: Cow<'a, [RestartPoint]>,
+    header_len: usize,
+    /// Index within the block of the next point to return.
+    position: usize,
+    remaining: usize,
+}
+
+impl<'a> BlockDecoder<'a> {
+    /// Decodes `block` with the codecs in [`CodecRegistry::global`].
+    pub fn new(block: &'a CompressedBlock) -> Result<Self, CompressionError> {
+        Self::with_registry(block, CodecRegistry::global())
+    }
+    
+    pub fn with_registry(block: &'a CompressedBlock, registry: &CodecRegistry) -> Result<Self, CompressionError> {
+        if block.count == 0 && block.compressed_data.is_empty() {
+            return Ok(Self {
+                reader: BitReader::new(&[]),
+                codec: None,
+                value_type: block.value_type,
+                decoder: None,
+                first_point: None,
+                precision: TimestampPrecision::Milliseconds,
+                restart_interval: 0,
+                restarts: Cow::Borrowed(&[]),
+                header_len: 0,
+                position: 0,
+                remaining: 0,
+            });
+        }
+        
+        let decoder = Self::from_bytes_with_registry(&block.compressed_data, registry)?;
+        if decoder.remaining != block.count {
+            return Err(CompressionError::InvalidFormat);
+        }
+        Ok(decoder)
+    }
+    
+    /// Decodes an encoded block straight from its bytes, e.g. a slice of a
+    /// larger buffer or a memory-mapped file.
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, CompressionError> {
+        Self::from_bytes_with_registry(data, CodecRegistry::global())
+    }
+    
+    pub fn from_bytes_with_registry(data: &'a [u8], registry: &CodecRegistry) -> Result<Self, CompressionError> {
+        verify_checksum(data)?;
+        let header = BlockHeader::read_from(data)?;
+        let codec = registry.get(header.codec_id)?;
+        if header.count == 0 {
+            return Err(CompressionError::InvalidFormat);
+        }
+        
+        let body = &data[BlockHeader::SIZE..];
+        let index_len = header.restart_count()
+            .checked_mul(RestartPoint::SIZE)
+            .filter(|&len| len <= body.len())
+            .ok_or(CompressionError::InsufficientData)?;
+        let restarts = body[..index_len]
+            .chunks_exact(RestartPoint::SIZE)
+            .map(RestartPoint::read_from)
+            .collect::<Result<Vec<_>, _>>()?;
+        
+        let value_type = codec.value_type();
+        let first_point = DataPoint::new(header.first_timestamp, verbatim_value(value_type, header.first_value_bits));
+        Ok(Self {
+            reader: BitReader::new(&body[index_len..]),
+            decoder: Some(codec.decoder(&first_point, header.precision)),
+            codec: Some(codec.clone()),
+            value_type,
+            first_point: Some(first_point),
+            precision: header.precision,
+            restart_interval: header.restart_interval as usize,
+            restarts: Cow::Owned(restarts),
+            header_len: BlockHeader::SIZE,
+            position: 0,
+            remaining: header.count as usize,
+        })
+    }
+    
+    /// Decodes the `count` points of a stream that has no header or restart
+    /// index of its own, such as the one a
+    /// [`BlockBuilder`](crate::BlockBuilder) is still writing.
+    pub(crate) fn from_stream(
+        reader: BitReader<'a>,
+        codec: Arc<dyn Codec>,
+        first_point: DataPoint,
+        precision: TimestampPrecision,
+        restart_interval: usize,
+        restarts: &'a [RestartPoint],
+        count: usize,
+    ) -> Self {
+        Self {
+            reader,
+            decoder: Some(codec.decoder(&first_point, precision)),
+            value_type: codec.value_type(),
+            codec: Some(codec),
+            first_point: Some(first_point),
+            precision,
+            restart_interval,
+            restarts: Cow::Borrowed(restarts),
+            header_len: 0,
+            position: 0,
+            remaining: count,
+        }
+    }
+    
+    /// The unit the block's timestamps are counted in.
+    pub fn precision(&self) -> TimestampPrecision {
+        self.precision
+    }
+    
+    /// The type of every value in the block, as given by its codec.
+    pub fn value_type(&self) -> ValueType {
+        self.value_type
+    }
+    
+    /// Number of points not yet returned.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+    
+    /// Skips ahead to the last restart point before `timestamp`, so the next
+    /// point returned is at most one restart interval before the first point
+    /// at or after `timestamp`. Assumes the block is in timestamp order, as
+    /// sealed blocks are. Does nothing for blocks without an index, or if the
+    /// decoder is already past that point.
+    pub fn seek(&mut self, timestamp: u64) {
+        let skipped = self.restarts.partition_point(|restart| restart.timestamp < timestamp);
+        let position = skipped * self.restart_interval;
+        if position > self.position && self.remaining > 0 {
+            self.remaining -= position - self.position;
+            self.position = position;
+        }
+    }
+    
+    /// Decodes every remaining point, appending timestamps and values to
+    /// the two column buffers. Returns how many points were appended. On
+    /// error the buffers keep the points decoded before it and the decoder
+    /// is exhausted. Only float blocks can be decoded into columns; any
+    /// other type fails with [`CompressionError::TypeMismatch`] before a
+    /// point is decoded.
+    pub fn decode_into(&mut self, timestamps: &mut Vec<u64>, values: &mut Vec<f64>) -> Result<usize, CompressionError> {
+        if self.remaining > 0 && self.value_type != ValueType::Float {
+            self.advance(false);
+            return Err(CompressionError::TypeMismatch { expected: ValueType::Float, actual: self.value_type });
+        }
+        
+        // The count comes from the header; cap the up-front reservation so a
+        // corrupt count cannot allocate more than the data could hold.
+        let reserve = self.remaining.min(MAX_COLUMN_RESERVE);
+        timestamps.reserve(reserve);
+        values.reserve(reserve);
+        
+        let mut decoded = 0;
+        while self.remaining > 0 {
+            match self.decode_next() {
+                Ok(point) => {
+                    timestamps.push(point.timestamp);
+                    values.push(point.value.as_f64().unwrap_or_default());
+                    self.advance(true);
+                    decoded += 1;
+                }
+                Err(e) => {
+                    self.advance(false);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(decoded)
+    }
+    
+    /// The block's codec id and the bytes taken by its header and restart
+    /// index.
+    pub(crate) fn layout(&self) -> (CodecId, usize, usize) {
+        let codec_id = self.codec.as_ref().map_or(0, |codec| codec.id());
+        (codec_id, self.header_len, self.restarts.len() * RestartPoint::SIZE)
+    }
+    
+    /// Decodes the next point like `next`, recording in `stats` how it was
+    /// stored.
+    pub(crate) fn next_traced(&mut self, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let result = self.decode_next_traced(Some(stats));
+        self.advance(result.is_ok());
+        result
+    }
+    
+    fn advance(&mut self, decoded: bool) {
+        if decoded {
+            self.position += 1;
+            self.remaining -= 1;
+        } else {
+            self.remaining = 0;
+        }
+    }
+    
+    fn decode_next(&mut self) -> Result<DataPoint, CompressionError> {
+        self.decode_next_traced(None)
+    }
+    
+    fn decode_next_traced(&mut self, stats: Option<&mut BlockStats>) -> Result<DataPoint, CompressionError> {
+        if self.position == 0 {
+            if let Some(stats) = stats {
+                stats.verbatim_points += 1;
+            }
+            let point = self.first_point.take().ok_or(CompressionError::InsufficientData)?;
+            let decoder = self.decoder.as_mut().ok_or(CompressionError::InsufficientData)?;
+            return decoder.read_first(&mut self.reader, point);
+        }
+        
+        if self.restart_interval > 0 && self.position.is_multiple_of(self.restart_interval) {
+            let restart = self.restarts.get(self.position / self.restart_interval - 1)
+                .ok_or(CompressionError::InvalidFormat)?;
+            let codec = self.codec.as_ref().ok_or(CompressionError::InsufficientData)?;
+            
+            let point = restart.point(self.value_type);
+            
+            self.reader.seek(restart.bit_offset)?;
+            let decoder = self.decoder.insert(codec.decoder(&point, self.precision));
+            if let Some(stats) = stats {
+                stats.verbatim_points += 1;
+            }
+            return decoder.read_first(&mut self.reader, point);
+        }
+        
+        match (self.decoder.as_mut(), stats) {
+            (Some(decoder), Some(stats)) => decoder.decode_traced(&mut self.reader, stats),
+            (Some(decoder), None) => decoder.decode(&mut self.reader),
+            (None, _) => Err(CompressionError::InsufficientData),
+        }
+    }
+}
+
+impl Iterator for BlockDecoder<'_> {
+    type Item = Result<DataPoint, CompressionError>;
+    
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        
+        let result = self.decode_next();
+        self.advance(result.is_ok());
+        Some(result)
+    }
+    
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::GORILLA_CODEC_ID;
+    use crate::test_util::{RawCodec, RAW_CODEC_ID};
+    use tsdb_core::Value;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    /// Recomputes the checksum after a test has edited a block on purpose.
+    fn reseal(block: &mut CompressedBlock) {
+        let checksum = block_checksum(&block.compressed_data);
+        block.compressed_data[CHECKSUM_OFFSET..CHECKSUM_END].copy_from_slice(&checksum.to_be_bytes());
+    }
+
+    fn sample_points() -> Vec<DataPoint> {
+        vec![
+            DataPoint::new(1500, 42.5),
+            DataPoint::new(1600, 43.0),
+            DataPoint::new(1700, 43.0),
+            DataPoint::new(1850, -1.25),
+        ]
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = BlockHeader::new(GORILLA_CODEC_ID, &DataPoint::new(1000, 3.5), 7);
+        let mut buffer = Vec::new();
+        header.write_to(&mut buffer);
+        
+        assert_eq!(buffer.len(), BlockHeader::SIZE);
+        assert_eq!(&buffer[..4], &BLOCK_MAGIC);
+        assert_eq!(BlockHeader::read_from(&buffer).unwrap(), header);
+    }
+
+    #[test]
+    fn test_header_bad_magic() {
+        let mut buffer = Vec::new();
+        BlockHeader::new(GORILLA_CODEC_ID, &DataPoint::new(1000, 3.5), 1).write_to(&mut buffer);
+        buffer[0] = b'X';
+        
+        assert!(matches!(BlockHeader::read_from(&buffer), Err(CompressionError::InvalidFormat)));
+    }
+
+    #[test]
+    fn test_header_unsupported_version() {
+        let mut buffer = Vec::new();
+        BlockHeader::new(GORILLA_CODEC_ID, &DataPoint::new(1000, 3.5), 1).write_to(&mut buffer);
+        buffer[4] = BLOCK_FORMAT_VERSION + 1;
+        
+        assert!(matches!(
+            BlockHeader::read_from(&buffer),
+            Err(CompressionError::UnsupportedVersion(v)) if v == BLOCK_FORMAT_VERSION + 1
+        ));
+        
+        buffer[4] = 1;
+        assert!(matches!(BlockHeader::read_from(&buffer), Err(CompressionError::UnsupportedVersion(1))));
+    }
+
+    #[test]
+    fn test_header_truncated() {
+        assert!(matches!(BlockHeader::read_from(&[0u8; 10]), Err(CompressionError::InsufficientData)));
+    }
+
+    #[test]
+    fn test_block_round_trip() {
+        let points = sample_points();
+        let block = compress_block(&points).unwrap();
+        
+        assert_eq!(block.start_timestamp, 1500);
+        assert_eq!(block.end_timestamp, 1850);
+        assert_eq!(block.count, 4);
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_block_single_point() {
+        let points = vec![DataPoint::new(1500, 42.5)];
+        let block = compress_block(&points).unwrap();
+        
+        assert_eq!(block.compressed_data.len(), BlockHeader::SIZE);
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_block_decodes_from_bytes_alone() {
+        let block = compress_block(&sample_points()).unwrap();
+        
+        // Only the payload and count survive the trip; the timestamps on the
+        // block are recovered from the header.
+        let shipped = CompressedBlock {
+            start_timestamp: 0,
+            end_timestamp: 0,
+            count: block.count,
+            value_type: block.value_type,
+            compressed_data: block.compressed_data.clone(),
+        };
+        assert_eq!(decompress_block(&shipped).unwrap(), sample_points());
+    }
+
+    #[test]
+    fn test_block_empty_points() {
+        assert!(compress_block(&[]).is_err());
+        
+        let empty = CompressedBlock {
+            start_timestamp: 1000,
+            end_timestamp: 1000,
+            count: 0,
+            value_type: ValueType::Float,
+            compressed_data: Vec::new(),
+        };
+        assert!(decompress_block(&empty).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_block_decoder_lazy() {
+        let points: Vec<DataPoint> = (0..100)
+            .map(|i| DataPoint::new(1000 + i * 10, i as f64))
+            .collect();
+        let block = compress_block(&points).unwrap();
+        
+        let mut decoder = BlockDecoder::new(&block).unwrap();
+        assert_eq!(decoder.remaining(), 100);
+        
+        let head: Vec<DataPoint> = decoder.by_ref().take(3).map(|p| p.unwrap()).collect();
+        assert_eq!(head, points[..3]);
+        assert_eq!(decoder.remaining(), 97);
+        
+        let rest: Vec<DataPoint> = decoder.map(|p| p.unwrap()).collect();
+        assert_eq!(rest, points[3..]);
+    }
+
+    #[test]
+    fn test_block_decoder_stops_after_error() {
+        let mut block = compress_block(&sample_points()).unwrap();
+        block.compressed_data.truncate(BlockHeader::SIZE);
+        reseal(&mut block);
+        
+        let mut decoder = BlockDecoder::new(&block).unwrap();
+        assert_eq!(decoder.next().unwrap().unwrap(), sample_points()[0]);
+        assert!(decoder.next().unwrap().is_err());
+        assert!(decoder.next().is_none());
+    }
+
+    #[test]
+    fn test_block_decoder_from_shared_buffer() {
+        let first = compress_block(&sample_points()).unwrap();
+        let second = compress_block(&[DataPoint::new(5000, 1.0), DataPoint::new(5010, 2.0)]).unwrap();
+        
+        let mut buffer = first.compressed_data.clone();
+        buffer.extend_from_slice(&second.compressed_data);
+        let (head, tail) = buffer.split_at(first.compressed_data.len());
+        
+        let decoded: Vec<DataPoint> = BlockDecoder::from_bytes(head).unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(decoded, sample_points());
+        
+        let decoded: Vec<DataPoint> = BlockDecoder::from_bytes(tail).unwrap().map(|p| p.unwrap()).collect();
+        assert_eq!(decoded, vec![DataPoint::new(5000, 1.0), DataPoint::new(5010, 2.0)]);
+    }
+
+    #[test]
+    fn test_block_custom_codec() {
+        let mut registry = CodecRegistry::default();
+        registry.register(Arc::new(RawCodec));
+        
+        let raw = compress_block_with(&sample_points(), &RawCodec).unwrap();
+        let gorilla = compress_block(&sample_points()).unwrap();
+        assert_eq!(raw.compressed_data[5], RAW_CODEC_ID);
+        assert_eq!(raw.compressed_data.len(), BlockHeader::SIZE + 3 * 16);
+        
+        for block in [&raw, &gorilla] {
+            let decoded: Vec<DataPoint> = BlockDecoder::with_registry(block, &registry)
+                .unwrap()
+                .map(|p| p.unwrap())
+                .collect();
+            assert_eq!(decoded, sample_points());
+        }
+        
+        assert!(matches!(decompress_block(&raw), Err(CompressionError::UnsupportedCodec(RAW_CODEC_ID))));
+    }
+
+    #[test]
+    fn test_block_unsupported_codec() {
+        let mut block = compress_block(&sample_points()).unwrap();
+        block.compressed_data[5] = 0xEE;
+        reseal(&mut block);
+        
+        assert!(matches!(decompress_block(&block), Err(CompressionError::UnsupportedCodec(0xEE))));
+    }
+
+    #[test]
+    fn test_block_count_mismatch() {
+        let mut block = compress_block(&sample_points()).unwrap();
+        block.count = 2;
+        
+        assert!(matches!(decompress_block(&block), Err(CompressionError::InvalidFormat)));
+    }
+
+    #[test]
+    fn test_block_checksum_mismatch() {
+        let points = sample_points();
+        let mut block = compress_block(&points).unwrap();
+        let last = block.compressed_data.len() - 1;
+        block.compressed_data[last] ^= 0x01;
+        
+        assert!(matches!(
+            BlockDecoder::new(&block),
+            Err(CompressionError::ChecksumMismatch { expected, actual }) if expected != actual
+        ));
+        assert!(matches!(verify_checksum(&block.compressed_data), Err(CompressionError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn test_block_indexed_round_trip() {
+        for id in CodecRegistry::global().ids() {
+            let codec = CodecRegistry::global().get(id).unwrap();
+            let points: Vec<DataPoint> = (0..1000)
+                .map(|i| {
+                    let value = i * i % 113;
+                    let value = match codec.value_type() {
+                        ValueType::Float => Value::Float(value as f64),
+                        ValueType::Integer => Value::Integer(value as i64),
+                        ValueType::Boolean => Value::Boolean(value % 2 == 0),
+                        ValueType::String => Value::String(format!("s{}", value % 20)),
+                        ValueType::Histogram => Value::from(tsdb_core::Histogram::from_values([value as f64, 1.0])),
+                    };
+                    DataPoint::new(1_000_000 + i * 1000 + i % 7, value)
+                })
+                .collect();
+            for interval in [1, 7, 100, 999, 1000, 5000] {
+                let block = compress_block_indexed(&points, codec.as_ref(), interval).unwrap();
+                let header = BlockHeader::read_from(&block.compressed_data).unwrap();
+                assert_eq!(header.restart_interval as usize, interval);
+                assert_eq!(header.restart_count(), 999 / interval);
+                assert_eq!(decompress_block(&block).unwrap(), points, "codec {} interval {}", id, interval);
+            }
+        }
+    }
+
+    #[test]
+    fn test_block_seek() {
+        let points: Vec<DataPoint> = (0..7200)
+            .map(|i| DataPoint::new(1_600_000_000_000 + i * 1000, 20.0 + (i as f64 * 0.01).sin()))
+            .collect();
+        let block = compress_block_indexed(&points, &GorillaCodec, 1024).unwrap();
+        
+        let mut decoder = BlockDecoder::new(&block).unwrap();
+        decoder.seek(points[7000].timestamp);
+        assert_eq!(decoder.remaining(), 7200 - 6144);
+        
+        let rest: Vec<DataPoint> = decoder.map(|p| p.unwrap()).collect();
+        assert_eq!(rest, points[6144..]);
+    }
+
+    #[test]
+    fn test_block_seek_only_moves_forward() {
+        let points: Vec<DataPoint> = (0..100).map(|i| DataPoint::new(i * 10, i as f64)).collect();
+        let block = compress_block_indexed(&points, &GorillaCodec, 10).unwrap();
+        
+        let mut decoder = BlockDecoder::new(&block).unwrap();
+        decoder.seek(555);
+        assert_eq!(decoder.next().unwrap().unwrap(), points[50]);
+        decoder.seek(0);
+        assert_eq!(decoder.next().unwrap().unwrap(), points[51]);
+        decoder.seek(10_000);
+        assert_eq!(decoder.next().unwrap().unwrap(), points[90]);
+        
+        let unindexed = compress_block(&points).unwrap();
+        let mut decoder = BlockDecoder::new(&unindexed).unwrap();
+        decoder.seek(555);
+        assert_eq!(decoder.remaining(), 100);
+    }
+
+    #[test]
+    fn test_block_records_precision() {
+        // Readings every second with up to a millisecond of jitter, in
+        // nanoseconds.
+        let points: Vec<DataPoint> = (0..1000u64)
+            .map(|i| DataPoint::new(1_600_000_000_000_000_000 + i * 1_000_000_000 + (i * 7919 % 1000) * 1000, i as f64))
+            .collect();
+        let nanos = compress_block_with_precision(&points, &GorillaCodec, 100, TimestampPrecision::Nanoseconds).unwrap();
+        let millis = compress_block_indexed(&points, &GorillaCodec, 100).unwrap();
+        
+        let header = BlockHeader::read_from(&nanos.compressed_data).unwrap();
+        assert_eq!(header.precision, TimestampPrecision::Nanoseconds);
+        let mut decoder = BlockDecoder::new(&nanos).unwrap();
+        assert_eq!(decoder.precision(), TimestampPrecision::Nanoseconds);
+        decoder.seek(points[550].timestamp);
+        assert_eq!(decoder.map(|p| p.unwrap()).collect::<Vec<_>>(), points[500..]);
+        assert_eq!(decompress_block(&nanos).unwrap(), points);
+        assert!(nanos.compressed_data.len() < millis.compressed_data.len());
+    }
+
+    #[test]
+    fn test_block_truncated_index() {
+        let points: Vec<DataPoint> = (0..100).map(|i| DataPoint::new(i * 10, i as f64)).collect();
+        let block = compress_block_indexed(&points, &GorillaCodec, 10).unwrap();
+        let header = BlockHeader::read_from(&block.compressed_data).unwrap();
+        
+        let truncated = encode_block(&header, &block.compressed_data[BlockHeader::SIZE..BlockHeader::SIZE + 100]);
+        assert!(matches!(BlockDecoder::from_bytes(&truncated), Err(CompressionError::InsufficientData)));
+    }
+
+    /// Decodes every point a block claims to hold, returning how many came
+    /// back before the first error.
+    fn drain(decoder: Result<BlockDecoder<'_>, CompressionError>) -> usize {
+        decoder.map(|decoder| decoder.take_while(|p| p.is_ok()).count()).unwrap_or(0)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_random_bytes_never_panic(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            drain(BlockDecoder::from_bytes(&data));
+        }
+        
+        #[test]
+        fn prop_random_payload_never_panics(
+            codec_id in prop::sample::select(CodecRegistry::global().ids()),
+            first_timestamp in any::<u64>(),
+            first_value in any::<f64>(),
+            count in prop_oneof![1u32..64, 1u32..=u32::MAX],
+            restart_interval in prop_oneof![Just(0u32), 1u32..16, any::<u32>()],
+            payload in prop::collection::vec(any::<u8>(), 0..512),
+        ) {
+            // A valid header and checksum around random bits, so the codec's
+            // decoder is reached instead of the checksum rejecting the block.
+            let first_point = DataPoint::new(first_timestamp, first_value);
+            let mut header = BlockHeader::new(codec_id, &first_point, count);
+            header.restart_interval = restart_interval;
+            let data = encode_block(&header, &payload);
+            
+            if let Ok(mut decoder) = BlockDecoder::from_bytes(&data) {
+                decoder.seek(first_timestamp / 2);
+                drain(Ok(decoder));
+            }
+            let decoded = drain(BlockDecoder::from_bytes(&data));
+            prop_assert!(decoded <= count as usize);
+        }
+        
+        #[test]
+        fn prop_seek_skips_only_earlier_points(
+            gaps in prop::collection::vec(0u64..5000, 1..500),
+            interval in 0usize..64,
+            target in 0u64..2_000_000,
+        ) {
+            let mut timestamp = 0;
+            let points: Vec<DataPoint> = gaps.iter()
+                .enumerate()
+                .map(|(i, gap)| {
+                    timestamp += gap;
+                    DataPoint::new(timestamp, i as f64 * 0.5)
+                })
+                .collect();
+            let block = compress_block_indexed(&points, &GorillaCodec, interval).unwrap();
+            
+            let mut decoder = BlockDecoder::new(&block).unwrap();
+            decoder.seek(target);
+            let skipped = points.len() - decoder.remaining();
+            let rest: Vec<DataPoint> = decoder.map(|p| p.unwrap()).collect();
+            
+            prop_assert_eq!(&rest[..], &points[skipped..]);
+            prop_assert!(points[..skipped].iter().all(|p| p.timestamp < target));
+        }
+        
+        #[test]
+        fn prop_any_bit_flip_is_detected(bit in 0usize..(8 * 200), seed in any::<u64>()) {
+            let points: Vec<DataPoint> = (0..60u64)
+                .map(|i| DataPoint::new(1000 + i * 10 + seed % 3, ((i ^ seed) % 97) as f64 * 0.25))
+                .collect();
+            let mut block = compress_block(&points).unwrap();
+            let bit = bit % (block.compressed_data.len() * 8);
+            block.compressed_data[bit / 8] ^= 1 << (bit % 8);
+            
+            // A flip in the magic or version is reported as a bad header;
+            // anywhere else it must be caught by the checksum.
+            prop_assert!(BlockDecoder::new(&block).is_err());
+            if bit / 8 > 4 {
+                prop_assert!(
+                    matches!(BlockDecoder::new(&block), Err(CompressionError::ChecksumMismatch { .. })),
+                    "bit {} not caught by checksum", bit
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::error::CompressionError;
+use crate::bits::BitWriter;
+use crate::block::{encode_block, BlockDecoder, BlockHeader, RestartPoint};
+use crate::codec::{Codec, GorillaCodec, PointEncoder};
+use crate::integer::is_exact_integer;
+use crate::typed::{check_value_type, verbatim_bits};
+use std::fmt;
+use std::sync::Arc;
+use tsdb_core::{CompressedBlock, DataPoint, TimestampPrecision, Value};
+
+/// Builds a block one point at a time.
+///
+/// Each appended point goes straight through the codec's encoder into the
+/// bit stream, so an open block costs a couple of bytes per point instead of
+/// a whole [`DataPoint`]. [`finish`](Self::finish) only has to put a header
+/// in front of the stream. The points can be read back at any time through
+/// [`points`](Self::points), and the block through [`snapshot`](Self::snapshot).
+///
+/// Points are encoded in the order they arrive. The output is the same as
+/// [`compress_block_indexed`](crate::compress_block_indexed) with the same
+/// codec over the same points; use [`is_sorted`](Self::is_sorted) to tell
+/// whether the block is in timestamp order, which [`BlockDecoder::seek`]
+/// relies on.
+pub struct BlockBuilder {
+    codec: Arc<dyn Codec>,
+    restart_interval: usize,
+    precision: TimestampPrecision,
+    first_point: Option<DataPoint>,
+    encoder: Option<Box<dyn PointEncoder>>,
+    writer: BitWriter,
+    restarts: Vec<RestartPoint>,
+    count: usize,
+    min_timestamp: u64,
+    max_timestamp: u64,
+    last_timestamp: u64,
+    sorted: bool,
+    integer_valued: bool,
+}
+
+impl BlockBuilder {
+    /// A Gorilla builder that starts a fresh encoder every
+    /// `restart_interval` points; 0 writes no restart index.
+    pub fn new(restart_interval: usize) -> Self {
+        Self::with_codec(Arc::new(GorillaCodec), restart_interval)
+    }
+    
+    /// A builder for `codec`. Snapshots only see what the encoder has
+    /// written, so the codec must write each point as it is encoded, as
+    /// every built-in codec but [`IntegerCodec`](crate::IntegerCodec) does.
+    pub fn with_codec(codec: Arc<dyn Codec>, restart_interval: usize) -> Self {
+        Self::with_precision(codec, restart_interval, TimestampPrecision::Milliseconds)
+    }
+    
+    /// A builder for `codec` whose timestamps are counted in `precision`;
+    /// see [`compress_block_with_precision`](crate::compress_block_with_precision).
+    pub fn with_precision(codec: Arc<dyn Codec>, restart_interval: usize, precision: TimestampPrecision) -> Self {
+        Self {
+            codec,
+            restart_interval,
+            precision,
+            first_point: None,
+            encoder: None,
+            writer: BitWriter::new(),
+            restarts: Vec::new(),
+            count: 0,
+            min_timestamp: u64::MAX,
+            max_timestamp: 0,
+            last_timestamp: 0,
+            sorted: true,
+            integer_valued: true,
+        }
+    }
+    
+    /// Encodes `point` onto the end of the block. On error the builder is
+    /// left as it was.
+    pub fn append(&mut self, point: &DataPoint) -> Result<(), CompressionError> {
+        if self.count >= u32::MAX as usize {
+            return Err(CompressionError::BufferOverflow);
+        }
+        check_value_type(point, self.codec.value_type())?;
+        
+        let restart = self.first_point.is_none() || self.restart_interval_reached();
+        match self.encoder.as_mut() {
+            Some(encoder) if !restart => encoder.encode(point, &mut self.writer)?,
+            _ => {
+                // The first point lives in the header and each restart point
+                // in the index; both seed a fresh encoder.
+                // Streaming encoders write nothing when finished, and
+                // `write_first` checks the value before writing any of it.
+                let mut encoder = self.codec.encoder(point, self.precision);
+                if let Some(previous) = self.encoder.as_mut() {
+                    previous.finish(&mut self.writer)?;
+                }
+                let bit_offset = self.writer.bit_count() as u64;
+                encoder.write_first(&mut self.writer)?;
+                
+                if self.first_point.is_none() {
+                    self.first_point = Some(point.clone());
+                } else {
+                    self.restarts.push(RestartPoint {
+                        bit_offset,
+                        timestamp: point.timestamp,
+                        value_bits: verbatim_bits(&point.value),
+                    });
+                }
+                self.encoder = Some(encoder);
+            }
+        }
+        
+        self.sorted &= self.count == 0 || point.timestamp >= self.last_timestamp;
+        self.integer_valued &= matches!(point.value, Value::Float(value) if is_exact_integer(value));
+        self.min_timestamp = self.min_timestamp.min(point.timestamp);
+        self.max_timestamp = self.max_timestamp.max(point.timestamp);
+        self.last_timestamp = point.timestamp;
+        self.count += 1;
+        Ok(())
+    }
+    fn restart_interval_reached(&self) -> bool {
+        self.restart_interval > 0 && self.count.is_multiple_of(self.restart_interval)
+    }
+    
+    pub fn len(&self) -> usize {
+        self.count
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+    
+    pub fn codec(&self) -> &Arc<dyn Codec> {
+        &self.codec
+    }
+    
+    pub fn restart_interval(&self) -> usize {
+        self.restart_interval
+    }
+    
+    pub fn precision(&self) -> TimestampPrecision {
+        self.precision
+    }
+    
+    /// Whether every point was appended at or after the one before it.
+    pub fn is_sorted(&self) -> bool {
+        self.sorted
+    }
+    
+    /// Whether every value is a float that satisfies [`is_exact_integer`],
+    /// so the points could also be stored by [`IntegerCodec`](crate::IntegerCodec).
+    pub fn is_integer_valued(&self) -> bool {
+        self.integer_valued
+    }
+    
+    /// Bytes the block would take if finished now.
+    pub fn encoded_size(&self) -> usize {
+        BlockHeader::SIZE + self.restarts.len() * RestartPoint::SIZE + self.writer.bit_count().div_ceil(8)
+    }
+    
+    /// The block as it would be finished now, leaving the builder open.
+    /// Costs a copy of the stream.
+    pub fn snapshot(&self) -> Result<CompressedBlock, CompressionError> {
+        self.encode(self.writer.to_bytes())
+    }
+    
+    /// Finishes the block. Fails with [`CompressionError::InsufficientData`]
+    /// if no point was appended.
+    pub fn finish(mut self) -> Result<CompressedBlock, CompressionError> {
+        let mut writer = std::mem::take(&mut self.writer);
+        if let Some(encoder) = self.encoder.as_mut() {
+            encoder.finish(&mut writer)?;
+        }
+        self.encode(writer.finish())
+    }
+    
+    /// Decodes the points appended so far, in the order they arrived,
+    /// straight from the stream being written.
+    pub fn points(&self) -> Result<Vec<DataPoint>, CompressionError> {
+        let Some(first_point) = &self.first_point else {
+            return Ok(Vec::new());
+        };
+        BlockDecoder::from_stream(
+            self.writer.reader(),
+            self.codec.clone(),
+            first_point.clone(),
+            self.precision,
+            self.restart_interval,
+            &self.restarts,
+            self.count,
+        )
+        .collect()
+    }
+    
+    fn encode(&self, payload: Vec<u8>) -> Result<CompressedBlock, CompressionError> {
+        let first_point = self.first_point.as_ref().ok_or(CompressionError::InsufficientData)?;
+        let interval = u32::try_from(self.restart_interval).map_err(|_| CompressionError::BufferOverflow)?;
+        
+        let mut body = Vec::with_capacity(self.restarts.len() * RestartPoint::SIZE + payload.len());
+        for restart in &self.restarts {
+            restart.write_to(&mut body);
+        }
+        body.extend_from_slice(&payload);
+        
+        let mut header = BlockHeader::new(self.codec.id(), first_point, self.count as u32);
+        header.restart_interval = interval;
+        header.precision = self.precision;
+        
+        Ok(CompressedBlock {
+            start_timestamp: self.min_timestamp,
+            end_timestamp: self.max_timestamp,
+            count: self.count,
+            value_type: self.codec.value_type(),
+            compressed_data: encode_block(&header, &body),
+        })
+    }
+}
+
+impl fmt::Debug for BlockBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockBuilder")
+            .field("codec", &self.codec.name())
+            .field("restart_interval", &self.restart_interval)
+            .field("precision", &self.precision)
+            .field("count", &self.count)
+            .field("encoded_size", &self.encoded_size())
+            .field("sorted", &self.sorted)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block_indexed, decompress_block};
+    use crate::typed::{BooleanCodec, Int64Codec, StringCodec};
+    use tsdb_core::ValueType;
+    use proptest::prelude::*;
+
+    fn build(points: &[DataPoint], restart_interval: usize) -> BlockBuilder {
+        let mut builder = BlockBuilder::new(restart_interval);
+        for point in points {
+            builder.append(point).unwrap();
+        }
+        builder
+    }
+
+    #[test]
+    fn test_builder_empty() {
+        let builder = BlockBuilder::new(0);
+        assert!(builder.is_empty());
+        assert!(builder.points().unwrap().is_empty());
+        assert!(matches!(builder.finish(), Err(CompressionError::InsufficientData)));
+    }
+
+    #[test]
+    fn test_builder_matches_compress_block() {
+        let points: Vec<DataPoint> = (0..5000u64)
+            .map(|i| DataPoint::new(1_600_000_000_000 + i * 1000, 20.0 + (i as f64 * 0.01).sin()))
+            .collect();
+        
+        for restart_interval in [0, 1, 7, 1024] {
+            let builder = build(&points, restart_interval);
+            let expected = compress_block_indexed(&points, &GorillaCodec, restart_interval).unwrap();
+            let snapshot = builder.snapshot().unwrap();
+            assert_eq!(snapshot.compressed_data, expected.compressed_data);
+            assert_eq!(builder.encoded_size(), expected.compressed_data.len());
+            
+            let block = builder.finish().unwrap();
+            assert_eq!(block.compressed_data, expected.compressed_data);
+            assert_eq!((block.start_timestamp, block.end_timestamp, block.count), (expected.start_timestamp, expected.end_timestamp, expected.count));
+        }
+    }
+
+    #[test]
+    fn test_builder_reads_while_open() {
+        let mut builder = BlockBuilder::new(3);
+        let mut points = Vec::new();
+        // Enough points that the stream spans several flushed words.
+        for i in 0..100u64 {
+            let point = DataPoint::new(1000 + i * 10, i as f64 * 1.5);
+            builder.append(&point).unwrap();
+            points.push(point);
+            assert_eq!(builder.points().unwrap(), points);
+        }
+    }
+
+    #[test]
+    fn test_builder_tracks_order_and_range() {
+        let points = [DataPoint::new(1000, 1.0), DataPoint::new(900, 2.0), DataPoint::new(1100, 3.0)];
+        let builder = build(&points, 0);
+        
+        assert!(!builder.is_sorted());
+        assert!(builder.is_integer_valued());
+        let block = builder.snapshot().unwrap();
+        assert_eq!((block.start_timestamp, block.end_timestamp), (900, 1100));
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_builder_append_error_leaves_state() {
+        let mut builder = build(&[DataPoint::new(0, 1.0), DataPoint::new(10, 2.0)], 0);
+        assert!(matches!(
+            builder.append(&DataPoint::new(u64::MAX, 3.0)),
+            Err(CompressionError::TimestampOverflow(_))
+        ));
+        builder.append(&DataPoint::new(20, 4.0)).unwrap();
+        assert_eq!(builder.len(), 3);
+        assert_eq!(builder.points().unwrap(), vec![DataPoint::new(0, 1.0), DataPoint::new(10, 2.0), DataPoint::new(20, 4.0)]);
+    }
+
+    #[test]
+    fn test_builder_typed_codecs() {
+        let integers: Vec<DataPoint> = (0..50u64).map(|i| DataPoint::new(i * 10, i as i64 * 3 - 70)).collect();
+        let flags: Vec<DataPoint> = (0..50u64).map(|i| DataPoint::new(i * 10, i % 4 == 0)).collect();
+        let states: Vec<DataPoint> = (0..50u64).map(|i| DataPoint::new(i * 10, ["ok", "warn", "down"][i as usize % 3])).collect();
+        let cases: [(&[DataPoint], Arc<dyn Codec>); 3] = [
+            (&integers, Arc::new(Int64Codec)),
+            (&flags, Arc::new(BooleanCodec)),
+            (&states, Arc::new(StringCodec)),
+        ];
+        
+        for (points, codec) in cases {
+            let mut builder = BlockBuilder::with_codec(codec.clone(), 8);
+            for point in points {
+                builder.append(point).unwrap();
+            }
+            assert!(!builder.is_integer_valued());
+            assert_eq!(builder.points().unwrap(), points);
+            
+            let expected = compress_block_indexed(points, codec.as_ref(), 8).unwrap();
+            let block = builder.finish().unwrap();
+            assert_eq!(block.compressed_data, expected.compressed_data);
+            assert_eq!(block.value_type, codec.value_type());
+        }
+    }
+
+    #[test]
+    fn test_builder_rejects_other_types() {
+        let mut builder = BlockBuilder::new(0);
+        builder.append(&DataPoint::new(0, 1.0)).unwrap();
+        assert!(matches!(
+            builder.append(&DataPoint::new(10, 2i64)),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Float, actual: ValueType::Integer })
+        ));
+        assert_eq!(builder.len(), 1);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_builder_round_trip(
+            points in prop::collection::vec((0u64..1 << 40, any::<f64>()), 1..300),
+            restart_interval in 0usize..20,
+        ) {
+            let points: Vec<DataPoint> = points.into_iter()
+                .map(|(timestamp, value)| DataPoint::new(timestamp, value))
+                .collect();
+            let builder = build(&points, restart_interval);
+            let decoded = decompress_block(&builder.finish().unwrap()).unwrap();
+            
+            prop_assert_eq!(decoded.len(), points.len());
+            for (decoded, original) in decoded.iter().zip(&points) {
+                prop_assert_eq!(decoded.timestamp, original.timestamp);
+                prop_assert_eq!(decoded.value.as_f64().map(f64::to_bits), original.value.as_f64().map(f64::to_bits));
+            }
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+
+/// Leading-zero counts Chimp can represent, indexed by their 3-bit code.
+const LEADING_ZEROS: [usize; 8] = [0, 8, 12, 16, 18, 20, 22, 24];
+
+/// A trailing-zero run longer than this is worth spelling out the centre bits.
+const CHIMP_TRAILING_THRESHOLD: usize = 6;
+
+/// Sentinel that never matches a rounded leading-zero count.
+const NO_LEADING_ZEROS: usize = 65;
+
+/// Number of earlier values Chimp128 can reference.
+const CHIMP128_WINDOW: usize = 128;
+const CHIMP128_INDEX_BITS: usize = 7;
+const CHIMP128_TRAILING_THRESHOLD: usize = CHIMP_TRAILING_THRESHOLD + CHIMP128_INDEX_BITS;
+
+/// Low bits used to look up a candidate earlier value with a matching tail.
+const CHIMP128_KEY_BITS: usize = CHIMP128_TRAILING_THRESHOLD + 1;
+
+fn leading_zeros_code(xor: u64) -> usize {
+    match xor.leading_zeros() {
+        0..=7 => 0,
+        8..=11 => 1,
+        12..=15 => 2,
+        16..=17 => 3,
+        18..=19 => 4,
+        20..=21 => 5,
+        22..=23 => 6,
+        _ => 7,
+    }
+}
+
+/// Writes the XOR of two values using Chimp's four cases. Shared by both
+/// variants; Chimp128 adds the index of the referenced value to the two
+/// cases that can use an older value, the other two always use the last one.
+fn write_xor(
+    xor: u64,
+    trailing_threshold: usize,
+    stored_leading_zeros: &mut usize,
+    index: Option<u64>,
+    writer: &mut BitWriter,
+) -> Result<(), CompressionError> {
+    let index_bits = if index.is_some() { CHIMP128_INDEX_BITS } else { 0 };
+    let index = index.unwrap_or(0);
+    
+    if xor == 0 {
+        writer.write_bits(0b00, 2)?;
+        writer.write_bits(index, index_bits)?;
+        *stored_leading_zeros = NO_LEADING_ZEROS;
+        return Ok(());
+    }
+    
+    let code = leading_zeros_code(xor);
+    let leading_zeros = LEADING_ZEROS[code];
+    let trailing_zeros = xor.trailing_zeros() as usize;
+    
+    if trailing_zeros > trailing_threshold {
+        let significant_bits = 64 - leading_zeros - trailing_zeros;
+        writer.write_bits(0b01, 2)?;
+        writer.write_bits(index, index_bits)?;
+        writer.write_bits(code as u64, 3)?;
+        writer.write_bits(significant_bits as u64, 6)?;
+        writer.write_bits(xor >> trailing_zeros, significant_bits)?;
+        *stored_leading_zeros = NO_LEADING_ZEROS;
+    } else if leading_zeros == *stored_leading_zeros {
+        writer.write_bits(0b10, 2)?;
+        writer.write_bits(xor, 64 - leading_zeros)?;
+    } else {
+        writer.write_bits(0b11, 2)?;
+        writer.write_bits(code as u64, 3)?;
+        writer.write_bits(xor, 64 - leading_zeros)?;
+        *stored_leading_zeros = leading_zeros;
+    }
+    
+    Ok(())
+}
+
+/// Chimp value compression (Liakos et al., VLDB 2022).
+///
+/// Like Gorilla it XORs each value with the previous one, but it rounds the
+/// leading-zero count to one of eight values and only spends bits on the
+/// trailing zeros when there are enough of them, which suits floats whose low
+/// bits are noisy.
+pub struct ChimpCompressor {
+    last_bits: u64,
+    stored_leading_zeros: usize,
+}
+
+impl ChimpCompressor {
+    pub fn new(first_value: f64) -> Self {
+        Self {
+            last_bits: first_value.to_bits(),
+            stored_leading_zeros: NO_LEADING_ZEROS,
+        }
+    }
+    
+    pub fn compress(&mut self, value: f64, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let bits = value.to_bits();
+        write_xor(bits ^ self.last_bits, CHIMP_TRAILING_THRESHOLD, &mut self.stored_leading_zeros, None, writer)?;
+        self.last_bits = bits;
+        Ok(())
+    }
+}
+
+pub struct ChimpDecompressor {
+    last_bits: u64,
+    stored_leading_zeros: usize,
+}
+
+impl ChimpDecompressor {
+    pub fn new(first_value: f64) -> Self {
+        Self {
+            last_bits: first_value.to_bits(),
+            stored_leading_zeros: NO_LEADING_ZEROS,
+        }
+    }
+    
+    pub fn decompress(&mut self, reader: &mut BitReader<'_>) -> Result<f64, CompressionError> {
+        let xor = match reader.read_bits(2)? {
+            0b00 => 0,
+            0b01 => read_centre_bits(reader, &mut self.stored_leading_zeros)?,
+            0b10 => read_stored_window(reader, self.stored_leading_zeros)?,
+            _ => read_new_window(reader, &mut self.stored_leading_zeros)?,
+        };
+        
+        self.last_bits ^= xor;
+        Ok(f64::from_bits(self.last_bits))
+    }
+}
+
+/// Chimp128: Chimp, but each value may be XORed with any of the previous 128
+/// values instead of only the last one, picked by matching low bits.
+pub struct Chimp128Compressor {
+    window: [u64; CHIMP128_WINDOW],
+    /// Position of each value in the stream, keyed by its low bits; 0 means
+    /// no value with that key has been seen.
+    positions: Vec<usize>,
+    current: usize,
+    position: usize,
+    stored_leading_zeros: usize,
+}
+
+impl Chimp128Compressor {
+    pub fn new(first_value: f64) -> Self {
+        let bits = first_value.to_bits();
+        let mut compressor = Self {
+            window: [0; CHIMP128_WINDOW],
+            positions: vec![0; 1 << CHIMP128_KEY_BITS],
+            current: 0,
+            position: 1,
+            stored_leading_zeros: NO_LEADING_ZEROS,
+        };
+        compressor.window[0] = bits;
+        compressor.positions[Self::key(bits)] = 1;
+        compressor
+    }
+    
+    fn key(bits: u64) -> usize {
+        (bits & ((1 << CHIMP128_KEY_BITS) - 1)) as usize
+    }
+    
+    pub fn compress(&mut self, value: f64, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let bits = value.to_bits();
+        let key = Self::key(bits);
+        
+        let mut reference = self.current;
+        let candidate = self.positions[key];
+        if candidate != 0 && self.position - candidate < CHIMP128_WINDOW {
+            let slot = (candidate - 1) % CHIMP128_WINDOW;
+            if (bits ^ self.window[slot]).trailing_zeros() as usize > CHIMP128_TRAILING_THRESHOLD {
+                reference = slot;
+            }
+        }
+        
+        let xor = bits ^ self.window[reference];
+        write_xor(xor, CHIMP128_TRAILING_THRESHOLD, &mut self.stored_leading_zeros, Some(reference as u64), writer)?;
+        
+        self.current = (self.current + 1) % CHIMP128_WINDOW;
+        self.window[self.current] = bits;
+        self.position += 1;
+        self.positions[key] = self.position;
+        Ok(())
+    }
+}
+
+pub struct Chimp128Decompressor {
+    window: [u64; CHIMP128_WINDOW],
+    current: usize,
+    stored_leading_zeros: usize,
+}
+
+impl Chimp128Decompressor {
+    pub fn new(first_value: f64) -> Self {
+        let mut window = [0; CHIMP128_WINDOW];
+        window[0] = first_value.to_bits();
+        Self {
+            window,
+            current: 0,
+            stored_leading_zeros: NO_LEADING_ZEROS,
+        }
+    }
+    
+    pub fn decompress(&mut self, reader: &mut BitReader<'_>) -> Result<f64, CompressionError> {
+        let last_bits = self.window[self.current];
+        let bits = match reader.read_bits(2)? {
+            0b00 => self.window[reader.read_bits(CHIMP128_INDEX_BITS)? as usize],
+            0b01 => {
+                let reference = self.window[reader.read_bits(CHIMP128_INDEX_BITS)? as usize];
+                reference ^ read_centre_bits(reader, &mut self.stored_leading_zeros)?
+            },
+            0b10 => last_bits ^ read_stored_window(reader, self.stored_leading_zeros)?,
+            _ => last_bits ^ read_new_window(reader, &mut self.stored_leading_zeros)?,
+        };
+        
+        self.current = (self.current + 1) % CHIMP128_WINDOW;
+        self.window[self.current] = bits;
+        Ok(f64::from_bits(bits))
+    }
+}
+
+fn read_centre_bits(reader: &mut BitReader<'_>, stored_leading_zeros: &mut usize) -> Result<u64, CompressionError> {
+    let leading_zeros = LEADING_ZEROS[reader.read_bits(3)? as usize];
+    let significant_bits = reader.read_bits(6)? as usize;
+    let trailing_zeros = 64usize
+        .checked_sub(leading_zeros + significant_bits)
+        .filter(|_| significant_bits > 0)
+        .ok_or(CompressionError::InvalidFormat)?;
+    
+    *stored_leading_zeros = NO_LEADING_ZEROS;
+    Ok(reader.read_bits(significant_bits)? << trailing_zeros)
+}
+
+fn read_stored_window(reader: &mut BitReader<'_>, stored_leading_zeros: usize) -> Result<u64, CompressionError> {
+    if stored_leading_zeros == NO_LEADING_ZEROS {
+        return Err(CompressionError::InvalidFormat);
+    }
+    reader.read_bits(64 - stored_leading_zeros)
+}
+
+fn read_new_window(reader: &mut BitReader<'_>, stored_leading_zeros: &mut usize) -> Result<u64, CompressionError> {
+    let leading_zeros = LEADING_ZEROS[reader.read_bits(3)? as usize];
+    *stored_leading_zeros = leading_zeros;
+    reader.read_bits(64 - leading_zeros)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn chimp_round_trip(values: &[f64]) -> (Vec<f64>, usize) {
+        let mut compressor = ChimpCompressor::new(values[0]);
+        let mut writer = BitWriter::new();
+        for &value in &values[1..] {
+            compressor.compress(value, &mut writer).unwrap();
+        }
+        
+        let bit_count = writer.bit_count();
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = ChimpDecompressor::new(values[0]);
+        
+        let mut decoded = vec![values[0]];
+        for _ in 1..values.len() {
+            decoded.push(decompressor.decompress(&mut reader).unwrap());
+        }
+        (decoded, bit_count)
+    }
+
+    fn chimp128_round_trip(values: &[f64]) -> (Vec<f64>, usize) {
+        let mut compressor = Chimp128Compressor::new(values[0]);
+        let mut writer = BitWriter::new();
+        for &value in &values[1..] {
+            compressor.compress(value, &mut writer).unwrap();
+        }
+        
+        let bit_count = writer.bit_count();
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = Chimp128Decompressor::new(values[0]);
+        
+        let mut decoded = vec![values[0]];
+        for _ in 1..values.len() {
+            decoded.push(decompressor.decompress(&mut reader).unwrap());
+        }
+        (decoded, bit_count)
+    }
+
+    fn to_bits(values: &[f64]) -> Vec<u64> {
+        values.iter().map(|v| v.to_bits()).collect()
+    }
+
+    #[test]
+    fn test_chimp_same_value() {
+        let values = [42.5, 42.5, 42.5];
+        let (decoded, bit_count) = chimp_round_trip(&values);
+        
+        assert_eq!(decoded, values);
+        assert_eq!(bit_count, 4);
+    }
+
+    #[test]
+    fn test_chimp_different_values() {
+        let values = [42.5, 43.0, 44.5, 45.0, 1.1, 1.2, 1.3, -7.25, 0.0];
+        assert_eq!(chimp_round_trip(&values).0, values);
+    }
+
+    #[test]
+    fn test_chimp_special_values() {
+        let values = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0, 0.0, f64::MIN_POSITIVE, f64::MAX];
+        assert_eq!(to_bits(&chimp_round_trip(&values).0), to_bits(&values));
+    }
+
+    #[test]
+    fn test_chimp128_references_older_values() {
+        let pattern = [10.1, 23.7, 31.3, 47.9, 55.5];
+        let values: Vec<f64> = pattern.iter().cycle().take(100).copied().collect();
+        
+        let (decoded, chimp128_bits) = chimp128_round_trip(&values);
+        assert_eq!(decoded, values);
+        
+        // Every repeat matches a value five steps back, which plain Chimp
+        // cannot see.
+        let (_, chimp_bits) = chimp_round_trip(&values);
+        assert!(chimp128_bits < chimp_bits / 2);
+    }
+
+    #[test]
+    fn test_chimp128_window_wraps() {
+        let values: Vec<f64> = (0..1000).map(|i| ((i * 37) % 300) as f64 * 0.25).collect();
+        assert_eq!(chimp128_round_trip(&values).0, values);
+    }
+
+    #[test]
+    fn test_chimp128_special_values() {
+        let values = [f64::NAN, f64::INFINITY, f64::NEG_INFINITY, -0.0, 0.0, f64::NAN, f64::MIN_POSITIVE, f64::MAX];
+        assert_eq!(to_bits(&chimp128_round_trip(&values).0), to_bits(&values));
+    }
+
+    #[test]
+    fn test_chimp_rejects_zero_significant_bits() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b01, 2).unwrap();
+        writer.write_bits(0, 3).unwrap();
+        writer.write_bits(0, 6).unwrap();
+        let data = writer.finish();
+        
+        let mut decompressor = ChimpDecompressor::new(1.0);
+        assert!(matches!(
+            decompressor.decompress(&mut BitReader::new(&data)),
+            Err(CompressionError::InvalidFormat)
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_chimp_round_trip(bits in prop::collection::vec(any::<u64>(), 1..200)) {
+            let values: Vec<f64> = bits.iter().map(|&b| f64::from_bits(b)).collect();
+            prop_assert_eq!(to_bits(&chimp_round_trip(&values).0), bits);
+        }
+        
+        #[test]
+        fn prop_chimp128_round_trip(bits in prop::collection::vec(any::<u64>(), 1..400)) {
+            let values: Vec<f64> = bits.iter().map(|&b| f64::from_bits(b)).collect();
+            prop_assert_eq!(to_bits(&chimp128_round_trip(&values).0), bits);
+        }
+        
+        #[test]
+        fn prop_chimp128_round_trip_repeating(
+            pool in prop::collection::vec(any::<f64>(), 1..300),
+            picks in prop::collection::vec(any::<prop::sample::Index>(), 1..500),
+        ) {
+            let values: Vec<f64> = picks.iter().map(|i| pool[i.index(pool.len())]).collect();
+            prop_assert_eq!(to_bits(&chimp128_round_trip(&values).0), to_bits(&values));
+        }
+    }
+}
@@ -0,0 +1,450 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use crate::diagnostics::BlockStats;
+use crate::timestamp::{TimestampCompressor, TimestampDecompressor};
+use crate::value::{ValueCompressor, ValueDecompressor};
+use crate::chimp::{ChimpCompressor, ChimpDecompressor, Chimp128Compressor, Chimp128Decompressor};
+use crate::integer::IntegerCodec;
+use crate::lossy::LossyCodec;
+use crate::histogram::HistogramCodec;
+use crate::typed::{float_seed, float_value, BooleanCodec, Int64Codec, StringCodec};
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use tsdb_core::{DataPoint, TimestampPrecision, ValueType};
+
+/// Identifies the codec a block was written with. Stored in every
+/// [`BlockHeader`](crate::BlockHeader), so ids must never be reused for a
+/// different encoding.
+pub type CodecId = u8;
+
+/// Delta-of-delta timestamps interleaved with Gorilla XOR values.
+pub const GORILLA_CODEC_ID: CodecId = 1;
+
+/// Delta-of-delta timestamps interleaved with Chimp values.
+pub const CHIMP_CODEC_ID: CodecId = 2;
+
+/// Delta-of-delta timestamps interleaved with Chimp128 values.
+pub const CHIMP128_CODEC_ID: CodecId = 3;
+
+/// Delta-of-delta timestamps with zigzag integer deltas packed by Simple-8b.
+pub const INTEGER_CODEC_ID: CodecId = 4;
+
+/// Gorilla encoding of values quantised to within an error bound.
+pub const LOSSY_CODEC_ID: CodecId = 5;
+
+/// Delta-of-delta timestamps with delta-of-delta `i64` values.
+pub const INT64_CODEC_ID: CodecId = 6;
+
+/// Delta-of-delta timestamps with one bit per boolean value.
+pub const BOOLEAN_CODEC_ID: CodecId = 7;
+
+/// Delta-of-delta timestamps with dictionary-encoded strings.
+pub const STRING_CODEC_ID: CodecId = 8;
+
+/// Delta-of-delta timestamps with each histogram written against the last.
+pub const HISTOGRAM_CODEC_ID: CodecId = 9;
+
+/// Appends points to a block's bit stream. The first point of the block is
+/// stored in the header and is never passed to the encoder.
+pub trait PointEncoder: Send + Sync {
+    /// Called once before the first [`encode`](Self::encode), and again
+    /// after each restart. Encoders whose first value does not fit in the
+    /// header write it to the stream here.
+    fn write_first(&mut self, _writer: &mut BitWriter) -> Result<(), CompressionError> {
+        Ok(())
+    }
+    
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError>;
+    
+    /// Called once after the last point. Encoders that buffer points to pack
+    /// them together write them out here.
+    fn finish(&mut self, _writer: &mut BitWriter) -> Result<(), CompressionError> {
+        Ok(())
+    }
+}
+
+/// Reads back, one at a time, the points written by the matching encoder.
+pub trait PointDecoder: Send + Sync {
+    /// Completes the first point of a block or restart segment, as seeded
+    /// from the header, with whatever [`PointEncoder::write_first`] wrote.
+    fn read_first(&mut self, _reader: &mut BitReader<'_>, point: DataPoint) -> Result<DataPoint, CompressionError> {
+        Ok(point)
+    }
+    
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError>;
+    
+    /// Decodes like [`decode`](Self::decode), recording in `stats` how the
+    /// point was stored. By default the bits read are counted as
+    /// `other_bits`; decoders override this to split them between
+    /// timestamps and values.
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let point = self.decode(reader)?;
+        stats.other_bits += reader.bit_position() - start;
+        Ok(point)
+    }
+}
+
+/// A block encoding. Encoders and decoders are seeded with the block's first
+/// point and timestamp precision, mirroring how the header is laid out.
+pub trait Codec: Send + Sync {
+    fn id(&self) -> CodecId;
+    
+    fn name(&self) -> &'static str;
+    
+    /// The type of values the codec stores. Blocks record it, and encoders
+    /// reject points of any other type.
+    fn value_type(&self) -> ValueType {
+        ValueType::Float
+    }
+    
+    /// Creates an encoder seeded with `first_point`, which must hold a value
+    /// of [`value_type`](Self::value_type). Timestamps are counted in
+    /// `precision`, which codecs may use to size their encoding.
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder>;
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder>;
+}
+
+/// The original Gorilla scheme: delta-of-delta timestamps and XOR values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GorillaCodec;
+
+impl Codec for GorillaCodec {
+    fn id(&self) -> CodecId {
+        GORILLA_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "gorilla"
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(GorillaEncoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            values: ValueCompressor::new(float_seed(first_point)),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(GorillaDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            values: ValueDecompressor::new(float_seed(first_point)),
+        })
+    }
+}
+
+struct GorillaEncoder {
+    timestamps: TimestampCompressor,
+    values: ValueCompressor,
+}
+
+impl PointEncoder for GorillaEncoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let value = float_value(point)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        self.values.compress(value, writer)
+    }
+}
+
+struct GorillaDecoder {
+    timestamps: TimestampDecompressor,
+    values: ValueDecompressor,
+}
+
+impl PointDecoder for GorillaDecoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let value = self.values.decompress(reader)?;
+        Ok(DataPoint::new(timestamp, value))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let (value, case) = self.values.decompress_case(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.record(case, reader.bit_position() - middle);
+        Ok(DataPoint::new(timestamp, value))
+    }
+}
+
+/// Gorilla timestamps with Chimp values, for floats with noisy low bits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChimpCodec;
+
+impl Codec for ChimpCodec {
+    fn id(&self) -> CodecId {
+        CHIMP_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "chimp"
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(ChimpEncoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            values: ChimpCompressor::new(float_seed(first_point)),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(ChimpDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            values: ChimpDecompressor::new(float_seed(first_point)),
+        })
+    }
+}
+
+struct ChimpEncoder {
+    timestamps: TimestampCompressor,
+    values: ChimpCompressor,
+}
+
+impl PointEncoder for ChimpEncoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let value = float_value(point)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        self.values.compress(value, writer)
+    }
+}
+
+struct ChimpDecoder {
+    timestamps: TimestampDecompressor,
+    values: ChimpDecompressor,
+}
+
+impl PointDecoder for ChimpDecoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let value = self.values.decompress(reader)?;
+        Ok(DataPoint::new(timestamp, value))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let value = self.values.decompress(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.bits += reader.bit_position() - middle;
+        Ok(DataPoint::new(timestamp, value))
+    }
+}
+
+/// Gorilla timestamps with Chimp128 values, for series that revisit a small
+/// set of values.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Chimp128Codec;
+
+impl Codec for Chimp128Codec {
+    fn id(&self) -> CodecId {
+        CHIMP128_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "chimp128"
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(Chimp128Encoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            values: Chimp128Compressor::new(float_seed(first_point)),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(Chimp128Decoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            values: Chimp128Decompressor::new(float_seed(first_point)),
+        })
+    }
+}
+
+struct Chimp128Encoder {
+    timestamps: TimestampCompressor,
+    values: Chimp128Compressor,
+}
+
+impl PointEncoder for Chimp128Encoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let value = float_value(point)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        self.values.compress(value, writer)
+    }
+}
+
+struct Chimp128Decoder {
+    timestamps: TimestampDecompressor,
+    values: Chimp128Decompressor,
+}
+
+impl PointDecoder for Chimp128Decoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let value = self.values.decompress(reader)?;
+        Ok(DataPoint::new(timestamp, value))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let value = self.values.decompress(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.bits += reader.bit_position() - middle;
+        Ok(DataPoint::new(timestamp, value))
+    }
+}
+
+/// Maps the codec id stored in a block header to the codec that decodes it.
+///
+/// Registering a new codec does not affect blocks written with another one,
+/// so the default encoding can change while old blocks stay readable.
+#[derive(Clone)]
+pub struct CodecRegistry {
+    codecs: HashMap<CodecId, Arc<dyn Codec>>,
+}
+
+impl CodecRegistry {
+    /// A registry with no codecs at all; see [`CodecRegistry::default`] for
+    /// one with the built-in codecs.
+    pub fn empty() -> Self {
+        Self {
+            codecs: HashMap::new(),
+        }
+    }
+    
+    /// The built-in codecs, shared by everything that does not bring its own
+    /// registry.
+    pub fn global() -> &'static CodecRegistry {
+        static GLOBAL: OnceLock<CodecRegistry> = OnceLock::new();
+        GLOBAL.get_or_init(CodecRegistry::default)
+    }
+    
+    /// Adds `codec`, returning the codec previously registered under its id.
+    pub fn register(&mut self, codec: Arc<dyn Codec>) -> Option<Arc<dyn Codec>> {
+        self.codecs.insert(codec.id(), codec)
+    }
+    
+    pub fn get(&self, id: CodecId) -> Result<&Arc<dyn Codec>, CompressionError> {
+        self.codecs.get(&id).ok_or(CompressionError::UnsupportedCodec(id))
+    }
+    
+    pub fn contains(&self, id: CodecId) -> bool {
+        self.codecs.contains_key(&id)
+    }
+    
+    pub fn ids(&self) -> Vec<CodecId> {
+        let mut ids: Vec<CodecId> = self.codecs.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Arc::new(GorillaCodec));
+        registry.register(Arc::new(ChimpCodec));
+        registry.register(Arc::new(Chimp128Codec));
+        registry.register(Arc::new(IntegerCodec));
+        registry.register(Arc::new(LossyCodec::default()));
+        registry.register(Arc::new(Int64Codec));
+        registry.register(Arc::new(BooleanCodec));
+        registry.register(Arc::new(StringCodec));
+        registry.register(Arc::new(HistogramCodec));
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{RawCodec, RAW_CODEC_ID};
+
+    #[test]
+    fn test_gorilla_codec_round_trip() {
+        let points = [
+            DataPoint::new(1000, 1.5),
+            DataPoint::new(1010, 1.5),
+            DataPoint::new(1020, 2.25),
+            DataPoint::new(1031, -8.0),
+        ];
+        
+        let mut writer = BitWriter::new();
+        let mut encoder = GorillaCodec.encoder(&points[0], TimestampPrecision::Milliseconds);
+        for point in &points[1..] {
+            encoder.encode(point, &mut writer).unwrap();
+        }
+        
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decoder = GorillaCodec.decoder(&points[0], TimestampPrecision::Milliseconds);
+        for point in &points[1..] {
+            assert_eq!(&decoder.decode(&mut reader).unwrap(), point);
+        }
+    }
+
+    #[test]
+    fn test_chimp_codecs_round_trip() {
+        let points: Vec<DataPoint> = (0..500)
+            .map(|i| DataPoint::new(1000 + i * 10, 20.0 + (i as f64 * 0.37).sin() * 3.3))
+            .collect();
+        
+        for codec in [&ChimpCodec as &dyn Codec, &Chimp128Codec] {
+            let mut writer = BitWriter::new();
+            let mut encoder = codec.encoder(&points[0], TimestampPrecision::Milliseconds);
+            for point in &points[1..] {
+                encoder.encode(point, &mut writer).unwrap();
+            }
+            
+            let data = writer.finish();
+            let mut reader = BitReader::new(&data);
+            let mut decoder = codec.decoder(&points[0], TimestampPrecision::Milliseconds);
+            for point in &points[1..] {
+                assert_eq!(&decoder.decode(&mut reader).unwrap(), point);
+            }
+        }
+    }
+
+    #[test]
+    fn test_registry_defaults() {
+        let registry = CodecRegistry::default();
+        assert_eq!(registry.get(GORILLA_CODEC_ID).unwrap().name(), "gorilla");
+        assert_eq!(registry.get(CHIMP_CODEC_ID).unwrap().name(), "chimp");
+        assert_eq!(registry.get(CHIMP128_CODEC_ID).unwrap().name(), "chimp128");
+        assert_eq!(registry.get(INTEGER_CODEC_ID).unwrap().name(), "integer");
+        assert_eq!(registry.get(LOSSY_CODEC_ID).unwrap().name(), "lossy");
+        assert_eq!(registry.get(INT64_CODEC_ID).unwrap().value_type(), ValueType::Integer);
+        assert_eq!(registry.get(BOOLEAN_CODEC_ID).unwrap().value_type(), ValueType::Boolean);
+        assert_eq!(registry.get(STRING_CODEC_ID).unwrap().value_type(), ValueType::String);
+        assert_eq!(registry.get(HISTOGRAM_CODEC_ID).unwrap().value_type(), ValueType::Histogram);
+        assert!(CodecRegistry::global().contains(GORILLA_CODEC_ID));
+        assert!(CodecRegistry::empty().ids().is_empty());
+    }
+
+    #[test]
+    fn test_registry_unknown_codec() {
+        let registry = CodecRegistry::default();
+        assert!(matches!(registry.get(200), Err(CompressionError::UnsupportedCodec(200))));
+    }
+
+    #[test]
+    fn test_registry_register() {
+        let mut registry = CodecRegistry::default();
+        assert!(registry.register(Arc::new(RawCodec)).is_none());
+        assert_eq!(registry.ids(), vec![GORILLA_CODEC_ID, CHIMP_CODEC_ID, CHIMP128_CODEC_ID, INTEGER_CODEC_ID, LOSSY_CODEC_ID, INT64_CODEC_ID, BOOLEAN_CODEC_ID, STRING_CODEC_ID, HISTOGRAM_CODEC_ID, RAW_CODEC_ID]);
+        assert!(registry.register(Arc::new(RawCodec)).is_some());
+        
+        let points = vec![DataPoint::new(1000, 1.5), DataPoint::new(1010, -2.0)];
+        let block = crate::compress_block_with(&points, &RawCodec).unwrap();
+        let decoded: Result<Vec<_>, _> = crate::BlockDecoder::with_registry(&block, &registry).unwrap().collect();
+        assert_eq!(decoded.unwrap(), points);
+    }
+}
@@ -0,0 +1,189 @@
+use crate::error::CompressionError;
+use crate::block::BlockDecoder;
+use crate::codec::CodecRegistry;
+use tsdb_core::{CompressedBlock, DataPoint, Value};
+
+/// Decoded points stored as two parallel columns rather than a slice of
+/// [`DataPoint`]s, so the values can be handed straight to the slice kernels
+/// in [`crate::kernels`].
+///
+/// Integer values are converted to `f64`; booleans and strings have no
+/// numeric value and become NaN, which the kernels other than `sum` skip.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PointColumns {
+    pub timestamps: Vec<u64>,
+    pub values: Vec<f64>,
+}
+
+impl PointColumns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            timestamps: Vec::with_capacity(capacity),
+            values: Vec::with_capacity(capacity),
+        }
+    }
+    
+    pub fn from_points(points: &[DataPoint]) -> Self {
+        Self {
+            timestamps: points.iter().map(|p| p.timestamp).collect(),
+            values: points.iter().map(|p| numeric_value(&p.value)).collect(),
+        }
+    }
+    
+    pub fn len(&self) -> usize {
+        self.timestamps.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.timestamps.is_empty()
+    }
+    
+    pub fn push(&mut self, point: &DataPoint) {
+        self.timestamps.push(point.timestamp);
+        self.values.push(numeric_value(&point.value));
+    }
+    
+    pub fn clear(&mut self) {
+        self.timestamps.clear();
+        self.values.clear();
+    }
+    
+    pub fn truncate(&mut self, len: usize) {
+        self.timestamps.truncate(len);
+        self.values.truncate(len);
+    }
+    
+    /// Sorts both columns by timestamp, keeping points with equal
+    /// timestamps in the order they were added.
+    pub fn sort_by_timestamp(&mut self) {
+        if self.timestamps.is_sorted() {
+            return;
+        }
+        let mut order: Vec<usize> = (0..self.len()).collect();
+        order.sort_by_key(|&index| self.timestamps[index]);
+        self.timestamps = order.iter().map(|&index| self.timestamps[index]).collect();
+        self.values = order.iter().map(|&index| self.values[index]).collect();
+    }
+    
+    pub fn to_points(&self) -> Vec<DataPoint> {
+        self.timestamps.iter()
+            .zip(&self.values)
+            .map(|(&timestamp, &value)| DataPoint::new(timestamp, value))
+            .collect()
+    }
+}
+
+fn numeric_value(value: &Value) -> f64 {
+    value.to_f64().unwrap_or(f64::NAN)
+}
+
+/// Decodes a float block with the built-in codecs into column buffers.
+pub fn decompress_block_columns(block: &CompressedBlock) -> Result<PointColumns, CompressionError> {
+    let mut columns = PointColumns::new();
+    decompress_block_into(block, CodecRegistry::global(), &mut columns)?;
+    Ok(columns)
+}
+
+/// Appends the points of `block` to `columns`, so one pair of buffers can be
+/// reused across many blocks. Returns how many points were appended.
+pub fn decompress_block_into(
+    block: &CompressedBlock,
+    registry: &CodecRegistry,
+    columns: &mut PointColumns,
+) -> Result<usize, CompressionError> {
+    BlockDecoder::with_registry(block, registry)?
+        .decode_into(&mut columns.timestamps, &mut columns.values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block, compress_block_indexed, compress_block_with, decompress_block, encode_block, BlockHeader};
+    use crate::codec::GorillaCodec;
+    use crate::typed::Int64Codec;
+    use tsdb_core::ValueType;
+
+    fn test_points(count: u64) -> Vec<DataPoint> {
+        (0..count).map(|i| DataPoint::new(1000 + i * 10, (i as f64 * 0.1).sin())).collect()
+    }
+
+    #[test]
+    fn test_columns_match_points() {
+        let points = test_points(5000);
+        let block = compress_block_indexed(&points, &GorillaCodec, 64).unwrap();
+        
+        let columns = decompress_block_columns(&block).unwrap();
+        assert_eq!(columns.len(), points.len());
+        assert_eq!(columns.to_points(), decompress_block(&block).unwrap());
+        assert_eq!(columns, PointColumns::from_points(&points));
+    }
+
+    #[test]
+    fn test_columns_sort_by_timestamp() {
+        let points = [DataPoint::new(30, 1.0), DataPoint::new(10, 2.0), DataPoint::new(30, 3.0), DataPoint::new(20, 4.0)];
+        let mut columns = PointColumns::from_points(&points);
+        columns.sort_by_timestamp();
+        
+        let mut sorted = points.to_vec();
+        sorted.sort_by_key(|p| p.timestamp);
+        assert_eq!(columns.to_points(), sorted);
+    }
+
+    #[test]
+    fn test_decompress_into_appends() {
+        let first = compress_block(&test_points(10)).unwrap();
+        let second = compress_block(&test_points(20)).unwrap();
+        
+        let mut columns = PointColumns::new();
+        assert_eq!(decompress_block_into(&first, CodecRegistry::global(), &mut columns).unwrap(), 10);
+        assert_eq!(decompress_block_into(&second, CodecRegistry::global(), &mut columns).unwrap(), 20);
+        assert_eq!(columns.len(), 30);
+        assert_eq!(columns.values[10..], PointColumns::from_points(&test_points(20)).values[..]);
+    }
+
+    #[test]
+    fn test_decode_into_after_seek() {
+        let points = test_points(1000);
+        let block = compress_block_indexed(&points, &GorillaCodec, 100).unwrap();
+        
+        let mut decoder = BlockDecoder::new(&block).unwrap();
+        decoder.seek(points[550].timestamp);
+        
+        let mut columns = PointColumns::new();
+        let decoded = decoder.decode_into(&mut columns.timestamps, &mut columns.values).unwrap();
+        assert_eq!(decoded, 500);
+        assert_eq!(columns.to_points(), points[500..]);
+        assert_eq!(decoder.remaining(), 0);
+    }
+
+    #[test]
+    fn test_decode_into_keeps_points_before_error() {
+        let points = test_points(100);
+        let block = compress_block(&points).unwrap();
+        let mut header = BlockHeader::read_from(&block.compressed_data).unwrap();
+        let body = &block.compressed_data[BlockHeader::SIZE..];
+        header.count = 1000;
+        let data = encode_block(&header, body);
+        
+        let mut columns = PointColumns::new();
+        let mut decoder = BlockDecoder::from_bytes(&data).unwrap();
+        assert!(decoder.decode_into(&mut columns.timestamps, &mut columns.values).is_err());
+        assert_eq!(decoder.remaining(), 0);
+        assert_eq!(columns.to_points()[..100], points[..]);
+    }
+
+    #[test]
+    fn test_columns_reject_non_float_blocks() {
+        let points: Vec<DataPoint> = (0..10u64).map(|i| DataPoint::new(i, i as i64)).collect();
+        let block = compress_block_with(&points, &Int64Codec).unwrap();
+        assert!(matches!(
+            decompress_block_columns(&block),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Float, actual: ValueType::Integer })
+        ));
+        assert_eq!(PointColumns::from_points(&points).values[3], 3.0);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::error::CompressionError;
+use crate::block::BlockDecoder;
+use crate::codec::{CodecId, CodecRegistry};
+use crate::timestamp::DodBucket;
+use crate::value::XorCase;
+use tsdb_core::CompressedBlock;
+
+/// How many timestamps were written in each delta-of-delta bucket, and the
+/// bits they took.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimestampStats {
+    pub zero: usize,
+    pub bits_7: usize,
+    pub bits_9: usize,
+    pub bits_12: usize,
+    pub bits_32: usize,
+    pub bits_64: usize,
+    pub bits: usize,
+}
+
+impl TimestampStats {
+    pub fn record(&mut self, bucket: DodBucket, bits: usize) {
+        match bucket {
+            DodBucket::Zero => self.zero += 1,
+            DodBucket::Bits7 => self.bits_7 += 1,
+            DodBucket::Bits9 => self.bits_9 += 1,
+            DodBucket::Bits12 => self.bits_12 += 1,
+            DodBucket::Bits32 => self.bits_32 += 1,
+            DodBucket::Bits64 => self.bits_64 += 1,
+        }
+        self.bits += bits;
+    }
+    
+    pub fn count(&self) -> usize {
+        self.zero + self.bits_7 + self.bits_9 + self.bits_12 + self.bits_32 + self.bits_64
+    }
+    
+    pub fn merge(&mut self, other: &TimestampStats) {
+        self.zero += other.zero;
+        self.bits_7 += other.bits_7;
+        self.bits_9 += other.bits_9;
+        self.bits_12 += other.bits_12;
+        self.bits_32 += other.bits_32;
+        self.bits_64 += other.bits_64;
+        self.bits += other.bits;
+    }
+}
+
+/// How many Gorilla values fell into each [`XorCase`], and the bits all
+/// values took. Codecs with other value encodings only add to `bits`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValueStats {
+    pub repeats: usize,
+    pub reused_window: usize,
+    pub new_window: usize,
+    pub bits: usize,
+}
+
+impl ValueStats {
+    pub fn record(&mut self, case: XorCase, bits: usize) {
+        match case {
+            XorCase::Repeat => self.repeats += 1,
+            XorCase::ReusedWindow => self.reused_window += 1,
+            XorCase::NewWindow => self.new_window += 1,
+        }
+        self.bits += bits;
+    }
+    
+    pub fn merge(&mut self, other: &ValueStats) {
+        self.repeats += other.repeats;
+        self.reused_window += other.reused_window;
+        self.new_window += other.new_window;
+        self.bits += other.bits;
+    }
+}
+
+/// Where the bits of one or more blocks went. Every bit of a block is
+/// counted exactly once, so the fields add up to its size.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockStats {
+    pub blocks: usize,
+    pub points: usize,
+    /// Blocks per codec id.
+    pub codecs: Vec<(CodecId, usize)>,
+    /// The first point and restart points, which are stored in full in the
+    /// header and index rather than in the stream.
+    pub verbatim_points: usize,
+    pub header_bits: usize,
+    pub index_bits: usize,
+    pub timestamps: TimestampStats,
+    pub values: ValueStats,
+    /// Stream bits that could not be split between timestamps and values,
+    /// either because the codec does not say or because they are padding.
+    pub other_bits: usize,
+}
+
+impl BlockStats {
+    pub fn total_bits(&self) -> usize {
+        self.header_bits + self.index_bits + self.timestamps.bits + self.values.bits + self.other_bits
+    }
+    
+    pub fn bits_per_point(&self) -> f64 {
+        if self.points == 0 {
+            0.0
+        } else {
+            self.total_bits() as f64 / self.points as f64
+        }
+    }
+    
+    pub fn merge(&mut self, other: &BlockStats) {
+        self.blocks += other.blocks;
+        self.points += other.points;
+        for &(codec_id, blocks) in &other.codecs {
+            self.add_codec(codec_id, blocks);
+        }
+        self.verbatim_points += other.verbatim_points;
+        self.header_bits += other.header_bits;
+        self.index_bits += other.index_bits;
+        self.timestamps.merge(&other.timestamps);
+        self.values.merge(&other.values);
+        self.other_bits += other.other_bits;
+    }
+    
+    fn add_codec(&mut self, codec_id: CodecId, blocks: usize) {
+        match self.codecs.iter_mut().find(|(id, _)| *id == codec_id) {
+            Some((_, count)) => *count += blocks,
+            None => {
+                self.codecs.push((codec_id, blocks));
+                self.codecs.sort_unstable();
+            }
+        }
+    }
+}
+
+/// Decodes `block` with the built-in codecs and reports where its bits went.
+pub fn analyze_block(block: &CompressedBlock) -> Result<BlockStats, CompressionError> {
+    analyze_block_with_registry(block, CodecRegistry::global())
+}
+
+pub fn analyze_block_with_registry(block: &CompressedBlock, registry: &CodecRegistry) -> Result<BlockStats, CompressionError> {
+    let mut stats = BlockStats::default();
+    if block.count == 0 && block.compressed_data.is_empty() {
+        return Ok(stats);
+    }
+    
+    let mut decoder = BlockDecoder::with_registry(block, registry)?;
+    let (codec_id, header_bytes, index_bytes) = decoder.layout();
+    stats.blocks = 1;
+    stats.codecs.push((codec_id, 1));
+    stats.header_bits = header_bytes * 8;
+    stats.index_bits = index_bytes * 8;
+    
+    while decoder.remaining() > 0 {
+        decoder.next_traced(&mut stats)?;
+        stats.points += 1;
+    }
+    
+    // Whatever the codec did not attribute, including the padding at the
+    // end of the stream.
+    stats.other_bits = (block.compressed_data.len() * 8)
+        .saturating_sub(stats.header_bits + stats.index_bits + stats.timestamps.bits + stats.values.bits);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block, compress_block_indexed, compress_block_with};
+    use crate::codec::{ChimpCodec, GorillaCodec, CHIMP_CODEC_ID, GORILLA_CODEC_ID, INTEGER_CODEC_ID};
+    use crate::integer::IntegerCodec;
+    use tsdb_core::DataPoint;
+
+    #[test]
+    fn test_analyze_regular_series() {
+        // Regular one-second interval; values repeat in runs of four.
+        let points: Vec<DataPoint> = (0..1000u64)
+            .map(|i| DataPoint::new(1_600_000_000_000 + i * 1000, (i / 4) as f64 * 0.5))
+            .collect();
+        let block = compress_block(&points).unwrap();
+        let stats = analyze_block(&block).unwrap();
+        
+        assert_eq!(stats.points, 1000);
+        assert_eq!(stats.verbatim_points, 1);
+        assert_eq!(stats.codecs, vec![(GORILLA_CODEC_ID, 1)]);
+        assert_eq!(stats.timestamps.count(), 999);
+        // The first delta is a change from the implicit zero delta.
+        assert_eq!(stats.timestamps.zero, 998);
+        assert_eq!(stats.timestamps.bits_12, 1);
+        assert_eq!(stats.values.repeats, 750);
+        assert_eq!(stats.values.repeats + stats.values.reused_window + stats.values.new_window, 999);
+        assert_eq!(stats.total_bits(), block.compressed_data.len() * 8);
+        assert!(stats.other_bits < 8);
+    }
+
+    #[test]
+    fn test_analyze_timestamp_buckets() {
+        let timestamps = [0u64, 10, 20, 100, 300, 1_000, 100_000, 100_000 + (1 << 40)];
+        let points: Vec<DataPoint> = timestamps.iter().map(|&t| DataPoint::new(t, 1.0)).collect();
+        let stats = analyze_block(&compress_block(&points).unwrap()).unwrap();
+        
+        let buckets = &stats.timestamps;
+        assert_eq!(
+            (buckets.zero, buckets.bits_7, buckets.bits_9, buckets.bits_12, buckets.bits_32, buckets.bits_64),
+            (1, 1, 2, 1, 1, 1)
+        );
+        assert_eq!(stats.values.repeats, 7);
+    }
+
+    #[test]
+    fn test_analyze_restarts_and_other_codecs() {
+        let points: Vec<DataPoint> = (0..100u64).map(|i| DataPoint::new(i * 10, i as f64)).collect();
+        
+        let indexed = analyze_block(&compress_block_indexed(&points, &GorillaCodec, 10).unwrap()).unwrap();
+        assert_eq!(indexed.verbatim_points, 10);
+        assert_eq!(indexed.index_bits, 9 * 24 * 8);
+        assert_eq!(indexed.timestamps.count(), 90);
+        
+        let chimp = analyze_block(&compress_block_with(&points, &ChimpCodec).unwrap()).unwrap();
+        assert_eq!(chimp.timestamps.count(), 99);
+        assert!(chimp.values.bits > 0);
+        assert_eq!(chimp.values.repeats + chimp.values.reused_window + chimp.values.new_window, 0);
+        
+        let integer = compress_block_with(&points, &IntegerCodec).unwrap();
+        let integer_stats = analyze_block(&integer).unwrap();
+        assert_eq!(integer_stats.timestamps.count(), 0);
+        assert_eq!(integer_stats.total_bits(), integer.compressed_data.len() * 8);
+        
+        let mut merged = indexed.clone();
+        merged.merge(&chimp);
+        merged.merge(&integer_stats);
+        assert_eq!(merged.blocks, 3);
+        assert_eq!(merged.points, 300);
+        assert_eq!(merged.codecs, vec![(GORILLA_CODEC_ID, 1), (CHIMP_CODEC_ID, 1), (INTEGER_CODEC_ID, 1)]);
+    }
+
+    #[test]
+    fn test_analyze_empty_block() {
+        let block = CompressedBlock {
+            start_timestamp: 0,
+            end_timestamp: 0,
+            count: 0,
+            value_type: tsdb_core::ValueType::Float,
+            compressed_data: Vec::new(),
+        };
+        assert_eq!(analyze_block(&block).unwrap(), BlockStats::default());
+    }
+}
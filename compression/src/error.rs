@@ -1,4 +1,5 @@
 use thiserror::Error;
+use tsdb_core::ValueType;
 
 #[derive(Error, Debug)]
 pub enum CompressionError {
@@ -13,4 +14,25 @@ pub enum CompressionError {
     
     #[error("Invalid compressed format")]
     InvalidFormat,
+    
+    #[error("Unsupported block format version: {0}")]
+    UnsupportedVersion(u8),
+    
+    #[error("Unsupported codec id: {0}")]
+    UnsupportedCodec(u8),
+    
+    #[error("Timestamp {0} is too far from the previous one to encode")]
+    TimestampOverflow(u64),
+    
+    #[error("Value {0} cannot be stored by an integer codec")]
+    NonIntegerValue(f64),
+    
+    #[error("Block checksum mismatch: stored {expected:#010x}, computed {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    
+    #[error("Expected a {expected} value, got a {actual} value")]
+    TypeMismatch { expected: ValueType, actual: ValueType },
+    
+    #[error("String of {0} bytes is too long to store")]
+    ValueTooLong(usize),
 }
\ No newline at end of file
@@ -0,0 +1,359 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use crate::codec::{Codec, CodecId, PointDecoder, PointEncoder, HISTOGRAM_CODEC_ID};
+use crate::diagnostics::BlockStats;
+use crate::timestamp::{read_delta_of_delta, write_delta_of_delta, TimestampCompressor, TimestampDecompressor};
+use crate::typed::check_value_type;
+use crate::value::{ValueCompressor, ValueDecompressor};
+use std::collections::BTreeMap;
+use tsdb_core::{DataPoint, Histogram, TimestampPrecision, ValueType, MAX_HISTOGRAM_SCALE, MIN_HISTOGRAM_SCALE};
+
+/// Delta-of-delta timestamps with each histogram written against the one
+/// before it.
+///
+/// Consecutive histograms of a series tend to share their scale and most of
+/// their buckets, so each field is stored as a change from the previous
+/// histogram: the scale as a single bit unless it changed; the sum, minimum
+/// and maximum as Gorilla XORs; and the zero count, the number of buckets,
+/// the gaps between bucket indices and each bucket's count as differences in
+/// the timestamp delta-of-delta buckets. A bucket's count is taken relative
+/// to the previous histogram's count for the same index, so a distribution
+/// that holds steady costs little more than a bit per bucket.
+///
+/// The first histogram of a block, or of a restart segment, is written
+/// against an empty one at the start of the stream.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HistogramCodec;
+
+impl Codec for HistogramCodec {
+    fn id(&self) -> CodecId {
+        HISTOGRAM_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "histogram"
+    }
+    
+    fn value_type(&self) -> ValueType {
+        ValueType::Histogram
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(HistogramEncoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            first: first_point.value.as_histogram().cloned().unwrap_or_default(),
+            state: EncoderState::new(),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(HistogramDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            state: DecoderState::new(),
+        })
+    }
+}
+
+struct EncoderState {
+    previous: Histogram,
+    sum: ValueCompressor,
+    min: ValueCompressor,
+    max: ValueCompressor,
+}
+
+impl EncoderState {
+    fn new() -> Self {
+        let previous = empty_histogram();
+        Self {
+            sum: ValueCompressor::new(previous.sum),
+            min: ValueCompressor::new(previous.min),
+            max: ValueCompressor::new(previous.max),
+            previous,
+        }
+    }
+    
+    fn write(&mut self, histogram: &Histogram, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        check_scale(histogram)?;
+        if histogram.scale == self.previous.scale {
+            writer.write_bit(0)?;
+        } else {
+            writer.write_bit(1)?;
+            writer.write_bits(histogram.scale as u8 as u64, 8)?;
+        }
+        self.sum.compress(histogram.sum, writer)?;
+        self.min.compress(histogram.min, writer)?;
+        self.max.compress(histogram.max, writer)?;
+        write_delta_of_delta(histogram.zero_count.wrapping_sub(self.previous.zero_count) as i64, writer)?;
+        write_buckets(&histogram.positive, &self.previous.positive, writer)?;
+        write_buckets(&histogram.negative, &self.previous.negative, writer)?;
+        
+        self.previous.clone_from(histogram);
+        Ok(())
+    }
+}
+
+struct DecoderState {
+    previous: Histogram,
+    sum: ValueDecompressor,
+    min: ValueDecompressor,
+    max: ValueDecompressor,
+}
+
+impl DecoderState {
+    fn new() -> Self {
+        let previous = empty_histogram();
+        Self {
+            sum: ValueDecompressor::new(previous.sum),
+            min: ValueDecompressor::new(previous.min),
+            max: ValueDecompressor::new(previous.max),
+            previous,
+        }
+    }
+    
+    fn read(&mut self, reader: &mut BitReader<'_>) -> Result<Histogram, CompressionError> {
+        let scale = if reader.read_bit()? == 0 {
+            self.previous.scale
+        } else {
+            reader.read_bits(8)? as u8 as i8
+        };
+        if !is_valid_scale(scale) {
+            return Err(CompressionError::InvalidFormat);
+        }
+        
+        let histogram = Histogram {
+            scale,
+            sum: self.sum.decompress(reader)?,
+            min: self.min.decompress(reader)?,
+            max: self.max.decompress(reader)?,
+            zero_count: self.previous.zero_count.wrapping_add(read_delta_of_delta(reader)?.0 as u64),
+            positive: read_buckets(&self.previous.positive, reader)?,
+            negative: read_buckets(&self.previous.negative, reader)?,
+        };
+        self.previous.clone_from(&histogram);
+        Ok(histogram)
+    }
+}
+
+/// The histogram each block and restart segment starts from. Its scale is
+/// the default, so histograms at the default scale never spell theirs out.
+fn empty_histogram() -> Histogram {
+    let mut histogram = Histogram::new();
+    histogram.min = 0.0;
+    histogram.max = 0.0;
+    histogram
+}
+
+fn write_buckets(buckets: &BTreeMap<i32, u64>, previous: &BTreeMap<i32, u64>, writer: &mut BitWriter) -> Result<(), CompressionError> {
+    write_delta_of_delta(buckets.len() as i64 - previous.len() as i64, writer)?;
+    
+    // The first index is taken from the previous first index, the rest as
+    // the gap to the index before, less the one every gap has.
+    let mut last_index = previous.keys().next().map_or(0, |&index| index as i64 - 1);
+    for &index in buckets.keys() {
+        write_delta_of_delta(index as i64 - last_index - 1, writer)?;
+        last_index = index as i64;
+    }
+    for (index, &count) in buckets {
+        let base = previous.get(index).copied().unwrap_or(0);
+        write_delta_of_delta(count.wrapping_sub(base) as i64, writer)?;
+    }
+    Ok(())
+}
+
+fn read_buckets(previous: &BTreeMap<i32, u64>, reader: &mut BitReader<'_>) -> Result<BTreeMap<i32, u64>, CompressionError> {
+    let len = (previous.len() as i64)
+        .checked_add(read_delta_of_delta(reader)?.0)
+        .and_then(|len| usize::try_from(len).ok())
+        .ok_or(CompressionError::InvalidFormat)?;
+    // Every bucket takes at least two bits, one for its index and one for
+    // its count, which bounds a corrupt length before anything is allocated.
+    if len > reader.remaining_bits() / 2 {
+        return Err(CompressionError::InsufficientData);
+    }
+    
+    let mut indices = Vec::with_capacity(len);
+    let mut last_index = previous.keys().next().map_or(0, |&index| index as i64 - 1);
+    for _ in 0..len {
+        let gap = read_delta_of_delta(reader)?.0;
+        let index = last_index.checked_add(1)
+            .and_then(|index| index.checked_add(gap))
+            .filter(|&index| index > last_index || indices.is_empty())
+            .and_then(|index| i32::try_from(index).ok())
+            .ok_or(CompressionError::InvalidFormat)?;
+        indices.push(index);
+        last_index = index as i64;
+    }
+    
+    let mut buckets = BTreeMap::new();
+    for index in indices {
+        let base = previous.get(&index).copied().unwrap_or(0);
+        buckets.insert(index, base.wrapping_add(read_delta_of_delta(reader)?.0 as u64));
+    }
+    Ok(buckets)
+}
+
+fn is_valid_scale(scale: i8) -> bool {
+    (MIN_HISTOGRAM_SCALE..=MAX_HISTOGRAM_SCALE).contains(&scale)
+}
+
+/// The scale is a public field, so a histogram built by hand can hold one
+/// the decoder would refuse. Such a histogram is refused here instead,
+/// before any of it is written.
+fn check_scale(histogram: &Histogram) -> Result<(), CompressionError> {
+    if is_valid_scale(histogram.scale) { Ok(()) } else { Err(CompressionError::InvalidFormat) }
+}
+
+fn histogram_value(point: &DataPoint) -> Result<&Histogram, CompressionError> {
+    check_value_type(point, ValueType::Histogram)?;
+    let histogram = point.value.as_histogram().ok_or(CompressionError::InvalidFormat)?;
+    check_scale(histogram)?;
+    Ok(histogram)
+}
+
+struct HistogramEncoder {
+    timestamps: TimestampCompressor,
+    first: Histogram,
+    state: EncoderState,
+}
+
+impl PointEncoder for HistogramEncoder {
+    fn write_first(&mut self, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let first = std::mem::take(&mut self.first);
+        self.state.write(&first, writer)
+    }
+    
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let histogram = histogram_value(point)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        self.state.write(histogram, writer)
+    }
+}
+
+struct HistogramDecoder {
+    timestamps: TimestampDecompressor,
+    state: DecoderState,
+}
+
+impl PointDecoder for HistogramDecoder {
+    fn read_first(&mut self, reader: &mut BitReader<'_>, point: DataPoint) -> Result<DataPoint, CompressionError> {
+        let histogram = self.state.read(reader)?;
+        Ok(DataPoint::new(point.timestamp, histogram))
+    }
+    
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let histogram = self.state.read(reader)?;
+        Ok(DataPoint::new(timestamp, histogram))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let histogram = self.state.read(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.bits += reader.bit_position() - middle;
+        Ok(DataPoint::new(timestamp, histogram))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block_indexed, compress_block_with, decompress_block};
+    use proptest::prelude::*;
+
+    /// Latency histograms from a service whose distribution drifts slowly.
+    fn latency_points(count: u64) -> Vec<DataPoint> {
+        (0..count)
+            .map(|i| {
+                let histogram = Histogram::from_values((0..200).map(|j| 5.0 + ((i * 31 + j * 17) % 97) as f64 * (1.0 + i as f64 * 0.001)));
+                DataPoint::new(1_600_000_000_000 + i * 10_000, histogram)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_histogram_codec_round_trip() {
+        let points = latency_points(300);
+        let block = compress_block_with(&points, &HistogramCodec).unwrap();
+        
+        assert_eq!(block.value_type, ValueType::Histogram);
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_histogram_codec_smaller_than_bincode() {
+        let points = latency_points(300);
+        let block = compress_block_with(&points, &HistogramCodec).unwrap();
+        
+        let buckets: usize = points.iter()
+            .map(|p| p.value.as_histogram().unwrap().positive.len())
+            .sum();
+        // A plain layout needs at least an index and a count per bucket.
+        assert!(block.compressed_data.len() * 4 < buckets * 12);
+    }
+
+    #[test]
+    fn test_histogram_codec_scale_changes_and_restarts() {
+        let mut points = Vec::new();
+        for i in 0..40u64 {
+            let mut histogram = Histogram::with_scale((i % 13) as i8 - 4);
+            histogram.record_n(-(i as f64), i);
+            histogram.record_n(0.0, i % 3);
+            histogram.record(1e300);
+            histogram.record(f64::MIN_POSITIVE);
+            points.push(DataPoint::new(i * 1000, histogram));
+        }
+        points.push(DataPoint::new(50_000, Histogram::new()));
+        
+        for interval in [0, 1, 7] {
+            let block = compress_block_indexed(&points, &HistogramCodec, interval).unwrap();
+            assert_eq!(decompress_block(&block).unwrap(), points);
+        }
+    }
+
+    #[test]
+    fn test_histogram_codec_rejects_other_types() {
+        let points = vec![DataPoint::new(0, Histogram::new()), DataPoint::new(10, 1.0)];
+        assert!(matches!(
+            compress_block_with(&points, &HistogramCodec),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Histogram, actual: ValueType::Float })
+        ));
+    }
+
+    #[test]
+    fn test_histogram_codec_rejects_invalid_scale() {
+        let mut bad = Histogram::from_values([1.0, 2.0]);
+        bad.scale = MAX_HISTOGRAM_SCALE + 1;
+        let good = DataPoint::new(0, Histogram::from_values([1.0]));
+        
+        for points in [vec![DataPoint::new(0, bad.clone())], vec![good.clone(), DataPoint::new(10, bad)]] {
+            assert!(matches!(compress_block_with(&points, &HistogramCodec), Err(CompressionError::InvalidFormat)));
+        }
+        
+        // The builder refuses the point and keeps the ones before it.
+        let mut builder = crate::BlockBuilder::with_codec(std::sync::Arc::new(HistogramCodec), 0);
+        builder.append(&good).unwrap();
+        let mut bad = Histogram::new();
+        bad.scale = MIN_HISTOGRAM_SCALE - 1;
+        assert!(builder.append(&DataPoint::new(10, bad)).is_err());
+        assert_eq!(builder.points().unwrap(), vec![good]);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_histogram_round_trip(
+            samples in prop::collection::vec(prop::collection::vec(-1e6f64..1e6, 0..50), 1..40),
+            restart_interval in 0usize..8,
+        ) {
+            let points: Vec<DataPoint> = samples.into_iter()
+                .enumerate()
+                .map(|(i, values)| DataPoint::new(i as u64 * 100, Histogram::from_values(values)))
+                .collect();
+            let block = compress_block_indexed(&points, &HistogramCodec, restart_interval).unwrap();
+            prop_assert_eq!(decompress_block(&block).unwrap(), points);
+        }
+    }
+}
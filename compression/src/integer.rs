@@ -0,0 +1,348 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use crate::codec::{Codec, CodecId, PointDecoder, PointEncoder, INTEGER_CODEC_ID};
+use crate::timestamp::{TimestampCompressor, TimestampDecompressor};
+use crate::typed::{float_seed, float_value};
+use tsdb_core::{DataPoint, TimestampPrecision};
+
+/// Largest magnitude at which every integer is exactly representable as an
+/// `f64`. Values beyond it may not survive the round trip through `i64`.
+const MAX_EXACT_INTEGER: f64 = (1u64 << 53) as f64;
+
+/// Simple-8b selectors: how many values a word holds and how wide each is.
+/// Selectors 0 and 1 encode runs of zeros without spending any payload bits.
+const SELECTORS: [(usize, usize); 16] = [
+    (240, 0),
+    (120, 0),
+    (60, 1),
+    (30, 2),
+    (20, 3),
+    (15, 4),
+    (12, 5),
+    (10, 6),
+    (8, 7),
+    (7, 8),
+    (6, 10),
+    (5, 12),
+    (4, 15),
+    (3, 20),
+    (2, 30),
+    (1, 60),
+];
+
+const PAYLOAD_BITS: usize = 60;
+
+/// Whether `value` is a whole number that converts to `i64` and back without
+/// changing its bits. Negative zero is excluded, since it would come back as
+/// positive zero.
+pub fn is_exact_integer(value: f64) -> bool {
+    value.abs() <= MAX_EXACT_INTEGER && (value as i64 as f64).to_bits() == value.to_bits()
+}
+
+/// Whether every value in `points` can be stored by [`IntegerCodec`]: a
+/// float holding a whole number.
+pub fn is_integer_valued(points: &[DataPoint]) -> bool {
+    points.iter().all(|point| point.value.as_f64().is_some_and(is_exact_integer))
+}
+
+/// The integer the first delta is taken from. The first value itself is kept
+/// verbatim in the block header, so it need not be an integer; when it is
+/// not, deltas simply start from zero.
+fn base_value(first_value: f64) -> i64 {
+    if is_exact_integer(first_value) {
+        first_value as i64
+    } else {
+        0
+    }
+}
+
+fn zigzag_encode(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn zigzag_decode(value: u64) -> i64 {
+    ((value >> 1) as i64) ^ -((value & 1) as i64)
+}
+
+/// Packs `values` into Simple-8b words. The last word may be padded with
+/// zeros; the reader is told how many values to expect.
+pub fn simple8b_pack(values: &[u64]) -> Result<Vec<u64>, CompressionError> {
+    let mut words = Vec::new();
+    let mut position = 0;
+    
+    while position < values.len() {
+        let remaining = &values[position..];
+        let (selector, taken) = SELECTORS.iter()
+            .enumerate()
+            .find_map(|(selector, &(count, width))| {
+                let taken = count.min(remaining.len());
+                let fits = remaining[..taken].iter().all(|&value| value >> width == 0);
+                fits.then_some((selector, taken))
+            })
+            .ok_or(CompressionError::InvalidBitLength(64 - remaining[0].leading_zeros() as usize))?;
+        
+        let width = SELECTORS[selector].1;
+        let mut word = (selector as u64) << PAYLOAD_BITS;
+        if width > 0 {
+            for (slot, &value) in remaining[..taken].iter().enumerate() {
+                word |= value << (PAYLOAD_BITS - (slot + 1) * width);
+            }
+        }
+        
+        words.push(word);
+        position += taken;
+    }
+    
+    Ok(words)
+}
+
+/// Unpacks Simple-8b words one value at a time.
+pub struct Simple8bReader {
+    words: Vec<u64>,
+    word_index: usize,
+    slot: usize,
+}
+
+impl Simple8bReader {
+    pub fn new(words: Vec<u64>) -> Self {
+        Self {
+            words,
+            word_index: 0,
+            slot: 0,
+        }
+    }
+    
+    pub fn next_value(&mut self) -> Result<u64, CompressionError> {
+        let word = *self.words.get(self.word_index).ok_or(CompressionError::InsufficientData)?;
+        let (count, width) = SELECTORS[(word >> PAYLOAD_BITS) as usize];
+        
+        let value = if width == 0 {
+            0
+        } else {
+            (word >> (PAYLOAD_BITS - (self.slot + 1) * width)) & ((1u64 << width) - 1)
+        };
+        
+        self.slot += 1;
+        if self.slot == count {
+            self.word_index += 1;
+            self.slot = 0;
+        }
+        
+        Ok(value)
+    }
+}
+
+/// Delta-of-delta timestamps with integer values stored as zigzag deltas
+/// packed by Simple-8b. Suited to counters and other whole-number series,
+/// where XOR encoding spends most of its bits on the exponent.
+///
+/// Simple-8b needs to see several values before it can pick a selector, so
+/// the payload is written when the block is finished: a 32-bit word count,
+/// the value words, then the timestamp stream. Every value must satisfy
+/// [`is_exact_integer`]; see [`is_integer_valued`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct IntegerCodec;
+
+impl Codec for IntegerCodec {
+    fn id(&self) -> CodecId {
+        INTEGER_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "integer"
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(IntegerEncoder {
+            first_timestamp: first_point.timestamp,
+            precision,
+            last_value: base_value(float_seed(first_point)),
+            timestamps: Vec::new(),
+            deltas: Vec::new(),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(IntegerDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            values: None,
+            last_value: base_value(float_seed(first_point)),
+        })
+    }
+}
+
+struct IntegerEncoder {
+    first_timestamp: u64,
+    precision: TimestampPrecision,
+    last_value: i64,
+    timestamps: Vec<u64>,
+    deltas: Vec<u64>,
+}
+
+impl PointEncoder for IntegerEncoder {
+    fn encode(&mut self, point: &DataPoint, _writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let value = float_value(point)?;
+        if !is_exact_integer(value) {
+            return Err(CompressionError::NonIntegerValue(value));
+        }
+        
+        // Both values are within 2^53, so the delta cannot overflow.
+        let value = value as i64;
+        self.deltas.push(zigzag_encode(value - self.last_value));
+        self.timestamps.push(point.timestamp);
+        self.last_value = value;
+        Ok(())
+    }
+    
+    fn finish(&mut self, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let words = simple8b_pack(&self.deltas)?;
+        let word_count = u32::try_from(words.len()).map_err(|_| CompressionError::BufferOverflow)?;
+        
+        writer.write_bits(word_count as u64, 32)?;
+        for word in words {
+            writer.write_bits(word, 64)?;
+        }
+        
+        let mut timestamps = TimestampCompressor::with_precision(self.first_timestamp, self.precision);
+        for &timestamp in &self.timestamps {
+            timestamps.compress(timestamp, writer)?;
+        }
+        Ok(())
+    }
+}
+
+struct IntegerDecoder {
+    timestamps: TimestampDecompressor,
+    values: Option<Simple8bReader>,
+    last_value: i64,
+}
+
+impl PointDecoder for IntegerDecoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        // The value words precede the timestamps, so they are read in full
+        // on the first call and unpacked as points are requested.
+        let values = match self.values {
+            Some(ref mut values) => values,
+            None => {
+                let word_count = reader.read_bits(32)? as usize;
+                if word_count > reader.remaining_bits() / 64 {
+                    return Err(CompressionError::InsufficientData);
+                }
+                let words = (0..word_count)
+                    .map(|_| reader.read_bits(64))
+                    .collect::<Result<Vec<u64>, _>>()?;
+                self.values.insert(Simple8bReader::new(words))
+            }
+        };
+        
+        let delta = zigzag_decode(values.next_value()?);
+        let value = self.last_value.checked_add(delta)
+            .ok_or(CompressionError::InvalidFormat)?;
+        let timestamp = self.timestamps.decompress(reader)?;
+        
+        self.last_value = value;
+        Ok(DataPoint::new(timestamp, value as f64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block_with, decompress_block};
+    use crate::codec::GorillaCodec;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_zigzag_round_trip() {
+        for value in [0, 1, -1, 2, -2, i64::MAX, i64::MIN] {
+            assert_eq!(zigzag_decode(zigzag_encode(value)), value);
+        }
+        assert_eq!(zigzag_encode(-1), 1);
+        assert_eq!(zigzag_encode(1), 2);
+    }
+
+    #[test]
+    fn test_is_exact_integer() {
+        assert!(is_exact_integer(0.0));
+        assert!(is_exact_integer(-42.0));
+        assert!(is_exact_integer(MAX_EXACT_INTEGER));
+        assert!(!is_exact_integer(-0.0));
+        assert!(!is_exact_integer(1.5));
+        assert!(!is_exact_integer(f64::NAN));
+        assert!(!is_exact_integer(f64::INFINITY));
+        assert!(!is_exact_integer(MAX_EXACT_INTEGER * 2.0));
+    }
+
+    #[test]
+    fn test_simple8b_zero_runs_and_wide_values() {
+        let mut values = vec![0u64; 250];
+        values.push((1 << 60) - 1);
+        values.extend([3, 1, 2]);
+        
+        let words = simple8b_pack(&values).unwrap();
+        assert_eq!(words[0] >> PAYLOAD_BITS, 0);
+        
+        let mut reader = Simple8bReader::new(words);
+        for &value in &values {
+            assert_eq!(reader.next_value().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_simple8b_rejects_values_over_60_bits() {
+        assert!(matches!(simple8b_pack(&[1 << 60]), Err(CompressionError::InvalidBitLength(61))));
+    }
+
+    #[test]
+    fn test_integer_codec_counter() {
+        let points: Vec<DataPoint> = (0..7200u64)
+            .map(|i| DataPoint::new(1_600_000_000_000 + i * 1000, (1_000_000 + i * 37 + i % 5) as f64))
+            .collect();
+        
+        let integer = compress_block_with(&points, &IntegerCodec).unwrap();
+        let gorilla = compress_block_with(&points, &GorillaCodec).unwrap();
+        
+        assert_eq!(decompress_block(&integer).unwrap(), points);
+        assert!(integer.compressed_data.len() * 3 < gorilla.compressed_data.len() * 2);
+    }
+
+    #[test]
+    fn test_integer_codec_rejects_fractions() {
+        let points = [DataPoint::new(1000, 1.0), DataPoint::new(1010, 1.5)];
+        assert!(matches!(
+            compress_block_with(&points, &IntegerCodec),
+            Err(CompressionError::NonIntegerValue(v)) if v == 1.5
+        ));
+    }
+
+    #[test]
+    fn test_integer_codec_fractional_first_value() {
+        let points = [DataPoint::new(1000, 0.5), DataPoint::new(1010, 3.0), DataPoint::new(1020, -4.0)];
+        let block = compress_block_with(&points, &IntegerCodec).unwrap();
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_integer_codec_single_point() {
+        let points = [DataPoint::new(1000, -7.0)];
+        let block = compress_block_with(&points, &IntegerCodec).unwrap();
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_integer_codec_round_trip(
+            start in 0u64..(1 << 50),
+            values in prop::collection::vec(-(1i64 << 53)..=(1i64 << 53), 1..500),
+        ) {
+            let points: Vec<DataPoint> = values.iter()
+                .enumerate()
+                .map(|(i, &value)| DataPoint::new(start + i as u64 * 1000, value as f64))
+                .collect();
+            prop_assert!(is_integer_valued(&points));
+            
+            let block = compress_block_with(&points, &IntegerCodec).unwrap();
+            prop_assert_eq!(decompress_block(&block).unwrap(), points);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use std::ops::Range;
+
+/// Number of independent accumulators the kernels keep: two 256-bit
+/// registers of `f64`.
+pub const LANES: usize = 8;
+
+/// Sum of `values`. NaN propagates as usual.
+///
+/// Floating-point addition is not associative, so `iter().sum()` must add
+/// one value at a time and cannot be vectorised. This keeps [`LANES`]
+/// independent sums and adds them up at the end, which compiles to SIMD
+/// adds; the result can differ from a sequential sum in the last few bits.
+pub fn sum(values: &[f64]) -> f64 {
+    let chunks = values.chunks_exact(LANES);
+    let tail: f64 = chunks.remainder().iter().sum();
+    
+    let mut lanes = [0.0f64; LANES];
+    for chunk in chunks {
+        for (lane, &value) in lanes.iter_mut().zip(chunk) {
+            *lane += value;
+        }
+    }
+    lanes.iter().sum::<f64>() + tail
+}
+
+/// Smallest value, ignoring NaN as [`f64::min`] does. `None` if `values` is
+/// empty or holds only NaN.
+pub fn min(values: &[f64]) -> Option<f64> {
+    let result = fold_lanes(values, f64::INFINITY, f64::min);
+    (result != f64::INFINITY || values.contains(&f64::INFINITY)).then_some(result)
+}
+
+/// Largest value, ignoring NaN as [`f64::max`] does. `None` if `values` is
+/// empty or holds only NaN.
+pub fn max(values: &[f64]) -> Option<f64> {
+    let result = fold_lanes(values, f64::NEG_INFINITY, f64::max);
+    (result != f64::NEG_INFINITY || values.contains(&f64::NEG_INFINITY)).then_some(result)
+}
+
+/// Number of values that are not NaN.
+pub fn count(values: &[f64]) -> usize {
+    values.iter().filter(|value| !value.is_nan()).count()
+}
+
+/// Arithmetic mean, or `None` for an empty slice.
+pub fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| sum(values) / values.len() as f64)
+}
+
+/// Population standard deviation, or `None` for an empty slice. Takes a
+/// second pass over `values` for the squared deviations from the mean, with
+/// the same independent sums as [`sum`].
+pub fn std_dev(values: &[f64]) -> Option<f64> {
+    let mean = mean(values)?;
+    let chunks = values.chunks_exact(LANES);
+    let tail: f64 = chunks.remainder().iter().map(|value| (value - mean) * (value - mean)).sum();
+    
+    let mut lanes = [0.0f64; LANES];
+    for chunk in chunks {
+        for (lane, &value) in lanes.iter_mut().zip(chunk) {
+            *lane += (value - mean) * (value - mean);
+        }
+    }
+    let variance = (lanes.iter().sum::<f64>() + tail) / values.len() as f64;
+    Some(variance.sqrt())
+}
+
+/// Indices of the timestamps in `[start, end)`. `timestamps` must be sorted,
+/// as decoded blocks are; the matching values are `&values[range]`.
+pub fn time_range(timestamps: &[u64], start: u64, end: u64) -> Range<usize> {
+    let first = timestamps.partition_point(|&timestamp| timestamp < start);
+    let last = first + timestamps[first..].partition_point(|&timestamp| timestamp < end);
+    first..last
+}
+
+fn fold_lanes(values: &[f64], identity: f64, combine: fn(f64, f64) -> f64) -> f64 {
+    let chunks = values.chunks_exact(LANES);
+    let tail = chunks.remainder().iter().fold(identity, |acc, &value| combine(acc, value));
+    
+    let mut lanes = [identity; LANES];
+    for chunk in chunks {
+        for (lane, &value) in lanes.iter_mut().zip(chunk) {
+            *lane = combine(*lane, value);
+        }
+    }
+    lanes.iter().fold(tail, |acc, &lane| combine(acc, lane))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_kernels_on_small_slices() {
+        let values = [3.0, -1.0, 7.5, 2.0, f64::NAN];
+        assert!(sum(&values).is_nan());
+        assert_eq!(sum(&values[..4]), 11.5);
+        assert_eq!(min(&values), Some(-1.0));
+        assert_eq!(max(&values), Some(7.5));
+        assert_eq!(count(&values), 4);
+        assert_eq!(mean(&values[..4]), Some(2.875));
+        assert_eq!(std_dev(&[2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0]), Some(2.0));
+    }
+
+    #[test]
+    fn test_kernels_on_empty_and_nan_slices() {
+        assert_eq!(sum(&[]), 0.0);
+        assert_eq!(min(&[]), None);
+        assert_eq!(max(&[]), None);
+        assert_eq!(mean(&[]), None);
+        assert_eq!(std_dev(&[]), None);
+        assert_eq!(min(&[f64::NAN; 20]), None);
+        assert_eq!(count(&[f64::NAN; 20]), 0);
+        assert_eq!(min(&[f64::INFINITY]), Some(f64::INFINITY));
+        assert_eq!(max(&[f64::NEG_INFINITY]), Some(f64::NEG_INFINITY));
+    }
+
+    #[test]
+    fn test_time_range() {
+        let timestamps = [10, 20, 20, 30, 40];
+        assert_eq!(time_range(&timestamps, 20, 40), 1..4);
+        assert_eq!(time_range(&timestamps, 0, 10), 0..0);
+        assert_eq!(time_range(&timestamps, 41, 100), 5..5);
+        assert_eq!(time_range(&timestamps, 0, u64::MAX), 0..5);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_kernels_match_iterators(values in prop::collection::vec(-1e6f64..1e6, 0..100)) {
+            let expected: f64 = values.iter().sum();
+            prop_assert!((sum(&values) - expected).abs() <= 1e-6);
+            prop_assert_eq!(min(&values), values.iter().copied().reduce(f64::min));
+            prop_assert_eq!(max(&values), values.iter().copied().reduce(f64::max));
+            prop_assert_eq!(count(&values), values.len());
+            
+            if !values.is_empty() {
+                let mean = expected / values.len() as f64;
+                let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64;
+                prop_assert!((std_dev(&values).unwrap() - variance.sqrt()).abs() <= 1e-6);
+            }
+        }
+    }
+}
@@ -1,7 +1,33 @@
+pub mod bits;
 pub mod timestamp;
 pub mod value;
+pub mod chimp;
+pub mod integer;
+pub mod lossy;
+pub mod typed;
+pub mod histogram;
+pub mod block;
+pub mod builder;
+pub mod columns;
+pub mod kernels;
+pub mod diagnostics;
+pub mod codec;
 pub mod error;
+/// Fixtures for tests and benchmarks, here and in crates built on this one.
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
 
+pub use bits::*;
 pub use timestamp::*;
 pub use value::*;
+pub use chimp::*;
+pub use integer::*;
+pub use lossy::*;
+pub use typed::*;
+pub use histogram::*;
+pub use block::*;
+pub use builder::*;
+pub use columns::*;
+pub use codec::*;
+pub use diagnostics::*;
 pub use error::*;
\ No newline at end of file
@@ -0,0 +1,268 @@
+use crate::error::CompressionError;
+use crate::bits::BitWriter;
+use crate::codec::{Codec, CodecId, GorillaCodec, PointDecoder, PointEncoder, LOSSY_CODEC_ID};
+use crate::typed::float_value;
+use tsdb_core::{DataPoint, TimestampPrecision};
+
+const MANTISSA_BITS: u32 = 52;
+const EXPONENT_MASK: u64 = 0x7FF;
+
+/// The largest error a lossy block may introduce into any one value.
+///
+/// A negative or NaN bound is treated as zero, which stores values exactly.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorBound {
+    /// `|decoded - original| <= bound`.
+    Absolute(f64),
+    /// `|decoded - original| <= bound * |original|`.
+    Relative(f64),
+}
+
+impl ErrorBound {
+    /// Rounds `value` toward zero by clearing as many low mantissa bits as
+    /// the bound allows. Runs of zero bits make the XOR with the previous
+    /// value much shorter. NaN and infinities are returned unchanged.
+    pub fn quantize(self, value: f64) -> f64 {
+        if !value.is_finite() {
+            return value;
+        }
+        
+        let bits = value.to_bits();
+        let dropped = match self {
+            ErrorBound::Absolute(bound) => {
+                if bound.is_nan() || bound <= 0.0 {
+                    return value;
+                }
+                if value.abs() <= bound {
+                    return 0.0;
+                }
+                // Clearing `d` bits starting at the lowest mantissa bit,
+                // whose weight is 2^lowest, changes the value by less than
+                // 2^(lowest + d).
+                let biased_exponent = ((bits >> MANTISSA_BITS) & EXPONENT_MASK) as i32;
+                let lowest = biased_exponent.max(1) - 1075;
+                floor_log2(bound) - lowest
+            }
+            ErrorBound::Relative(bound) => {
+                if bound.is_nan() || bound <= 0.0 {
+                    return value;
+                }
+                // Keeping `kept` bits below the leading one bounds the
+                // relative error by 2^-kept.
+                let kept = (0..MANTISSA_BITS as i32)
+                    .find(|&kept| 0.5f64.powi(kept) <= bound)
+                    .unwrap_or(MANTISSA_BITS as i32);
+                leading_bit(bits) - kept
+            }
+        };
+        
+        let dropped = dropped.clamp(0, MANTISSA_BITS as i32) as u32;
+        f64::from_bits(bits & !((1u64 << dropped) - 1))
+    }
+    
+    /// Whether `decoded` is an acceptable stand-in for `original`.
+    pub fn contains(self, original: f64, decoded: f64) -> bool {
+        if !original.is_finite() {
+            return original.to_bits() == decoded.to_bits();
+        }
+        let error = (decoded - original).abs();
+        match self {
+            ErrorBound::Absolute(bound) => error <= bound.max(0.0),
+            ErrorBound::Relative(bound) => error <= bound.max(0.0) * original.abs(),
+        }
+    }
+}
+
+/// Position of the highest significant bit within the mantissa: 52 (the
+/// implicit bit) for normal numbers, lower for subnormals.
+fn leading_bit(bits: u64) -> i32 {
+    if (bits >> MANTISSA_BITS) & EXPONENT_MASK != 0 {
+        MANTISSA_BITS as i32
+    } else {
+        let mantissa = bits & ((1u64 << MANTISSA_BITS) - 1);
+        63 - mantissa.leading_zeros() as i32
+    }
+}
+
+/// `floor(log2(value))` for a positive finite value, computed exactly from
+/// its bits.
+fn floor_log2(value: f64) -> i32 {
+    let bits = value.to_bits();
+    let biased_exponent = ((bits >> MANTISSA_BITS) & EXPONENT_MASK) as i32;
+    if biased_exponent == 0 {
+        leading_bit(bits) - 1074
+    } else {
+        biased_exponent - 1023
+    }
+}
+
+/// Gorilla encoding of values quantised to within an [`ErrorBound`].
+///
+/// Quantisation happens before the XOR, so the block decodes with the plain
+/// Gorilla decoder and the bound only matters when encoding; any
+/// `LossyCodec` can decode a block written by another. The first point of a
+/// block is stored verbatim in the header and is always exact.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LossyCodec {
+    bound: ErrorBound,
+}
+
+impl LossyCodec {
+    pub fn new(bound: ErrorBound) -> Self {
+        Self { bound }
+    }
+    
+    pub fn bound(&self) -> ErrorBound {
+        self.bound
+    }
+}
+
+impl Default for LossyCodec {
+    fn default() -> Self {
+        Self::new(ErrorBound::Absolute(0.0))
+    }
+}
+
+impl Codec for LossyCodec {
+    fn id(&self) -> CodecId {
+        LOSSY_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "lossy"
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(LossyEncoder {
+            bound: self.bound,
+            inner: GorillaCodec.encoder(first_point, precision),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        GorillaCodec.decoder(first_point, precision)
+    }
+}
+
+struct LossyEncoder {
+    bound: ErrorBound,
+    inner: Box<dyn PointEncoder>,
+}
+
+impl PointEncoder for LossyEncoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let quantized = DataPoint::new(point.timestamp, self.bound.quantize(float_value(point)?));
+        self.inner.encode(&quantized, writer)
+    }
+    
+    fn finish(&mut self, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        self.inner.finish(writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block_with, decompress_block};
+    use proptest::prelude::*;
+
+    fn bounds() -> impl Strategy<Value = ErrorBound> {
+        prop_oneof![
+            (-12i32..12).prop_map(|exp| ErrorBound::Absolute(10f64.powi(exp))),
+            (1i32..16).prop_map(|exp| ErrorBound::Relative(10f64.powi(-exp))),
+            (0.0f64..1.0).prop_map(ErrorBound::Relative),
+            any::<f64>().prop_map(|bound| ErrorBound::Absolute(bound.abs())),
+        ]
+    }
+
+    fn sensor_points(count: u64) -> Vec<DataPoint> {
+        (0..count)
+            .map(|i| {
+                let noise = ((i * 7919) % 1000) as f64 / 997.0;
+                DataPoint::new(1_600_000_000_000 + i * 100, 21.5 + (i as f64 * 0.003).sin() * 4.0 + noise * 0.05)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_quantize_absolute() {
+        let bound = ErrorBound::Absolute(0.01);
+        for value in [0.0, 1.0, -12.3456, 123_456.789, 1e-300, -1e300, 0.009] {
+            let quantized = bound.quantize(value);
+            assert!(bound.contains(value, quantized), "{} -> {}", value, quantized);
+        }
+        assert_eq!(bound.quantize(0.009), 0.0);
+        assert_eq!(bound.quantize(1e300), 1e300);
+    }
+
+    #[test]
+    fn test_quantize_relative() {
+        let bound = ErrorBound::Relative(0.001);
+        for value in [1.0, -12.3456, 123_456.789, 1e-310, -5e-324, f64::MAX, f64::MIN_POSITIVE] {
+            let quantized = bound.quantize(value);
+            assert!(bound.contains(value, quantized), "{} -> {}", value, quantized);
+        }
+        assert_eq!(bound.quantize(1.0), 1.0);
+    }
+
+    #[test]
+    fn test_quantize_special_values() {
+        for bound in [ErrorBound::Absolute(1.0), ErrorBound::Relative(0.5)] {
+            assert!(bound.quantize(f64::NAN).is_nan());
+            assert_eq!(bound.quantize(f64::INFINITY), f64::INFINITY);
+            assert_eq!(bound.quantize(f64::NEG_INFINITY), f64::NEG_INFINITY);
+        }
+    }
+
+    #[test]
+    fn test_quantize_non_positive_bound_is_lossless() {
+        for bound in [ErrorBound::Absolute(0.0), ErrorBound::Absolute(-1.0), ErrorBound::Relative(f64::NAN)] {
+            assert_eq!(bound.quantize(12.3456).to_bits(), 12.3456f64.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_lossy_codec_shrinks_sensor_data() {
+        let points = sensor_points(7200);
+        let bound = ErrorBound::Absolute(0.01);
+        
+        let lossy = compress_block_with(&points, &LossyCodec::new(bound)).unwrap();
+        let exact = compress_block_with(&points, &GorillaCodec).unwrap();
+        assert!(lossy.compressed_data.len() * 2 < exact.compressed_data.len());
+        
+        let decoded = decompress_block(&lossy).unwrap();
+        for (original, decoded) in points.iter().zip(&decoded) {
+            assert_eq!(original.timestamp, decoded.timestamp);
+            assert!(bound.contains(original.value.to_f64().unwrap(), decoded.value.to_f64().unwrap()));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn prop_quantize_within_bound(value in any::<f64>(), bound in bounds()) {
+            let quantized = bound.quantize(value);
+            prop_assert!(bound.contains(value, quantized), "{:?}: {} -> {}", bound, value, quantized);
+        }
+        
+        #[test]
+        fn prop_lossy_block_within_bound(
+            values in prop::collection::vec(any::<f64>(), 1..300),
+            bound in bounds(),
+        ) {
+            let points: Vec<DataPoint> = values.iter()
+                .enumerate()
+                .map(|(i, &value)| DataPoint::new(1000 + i as u64 * 10, value))
+                .collect();
+            
+            let block = compress_block_with(&points, &LossyCodec::new(bound)).unwrap();
+            let decoded = decompress_block(&block).unwrap();
+            
+            prop_assert_eq!(decoded.len(), points.len());
+            for (original, decoded) in points.iter().zip(&decoded) {
+                prop_assert_eq!(original.timestamp, decoded.timestamp);
+                let (original, decoded) = (original.value.to_f64().unwrap(), decoded.value.to_f64().unwrap());
+                prop_assert!(bound.contains(original, decoded), "{:?}: {} -> {}", bound, original, decoded);
+            }
+        }
+    }
+}
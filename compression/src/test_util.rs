@@ -0,0 +1,106 @@
+use crate::bits::{BitReader, BitWriter};
+use crate::codec::{Codec, CodecId, PointDecoder, PointEncoder};
+use crate::error::CompressionError;
+use crate::typed::float_value;
+use tsdb_core::{DataPoint, TimestampPrecision};
+
+/// The id [`RawCodec`] writes into block headers.
+pub const RAW_CODEC_ID: CodecId = 250;
+
+/// Stores every float point as two raw 64-bit words. It is not in the
+/// default registry, so its blocks also stand in for a codec the reader
+/// does not know.
+pub struct RawCodec;
+
+struct RawPoints;
+
+impl Codec for RawCodec {
+    fn id(&self) -> CodecId {
+        RAW_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+    
+    fn encoder(&self, _first_point: &DataPoint, _precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(RawPoints)
+    }
+    
+    fn decoder(&self, _first_point: &DataPoint, _precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(RawPoints)
+    }
+}
+
+impl PointEncoder for RawPoints {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        writer.write_bits(point.timestamp, 64)?;
+        writer.write_bits(float_value(point)?.to_bits(), 64)
+    }
+}
+
+impl PointDecoder for RawPoints {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = reader.read_bits(64)?;
+        Ok(DataPoint::new(timestamp, f64::from_bits(reader.read_bits(64)?)))
+    }
+}
+
+/// The original bit-at-a-time writer. [`BitWriter`](crate::BitWriter)
+/// must lay out bits exactly as it does, and is benchmarked against it.
+#[derive(Debug, Default)]
+pub struct ReferenceBitWriter {
+    buffer: Vec<u8>,
+    bit_count: usize,
+}
+
+impl ReferenceBitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn write_bits(&mut self, value: u64, num_bits: usize) {
+        for i in (0..num_bits).rev() {
+            if self.bit_count.is_multiple_of(8) {
+                self.buffer.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                self.buffer[self.bit_count / 8] |= 1 << (7 - self.bit_count % 8);
+            }
+            self.bit_count += 1;
+        }
+    }
+    
+    pub fn bit_count(&self) -> usize {
+        self.bit_count
+    }
+    
+    pub fn finish(self) -> Vec<u8> {
+        self.buffer
+    }
+}
+
+/// The original bit-at-a-time reader, the baseline
+/// [`BitReader`](crate::BitReader) is benchmarked against. It panics past
+/// the end of its buffer.
+#[derive(Debug)]
+pub struct ReferenceBitReader {
+    buffer: Vec<u8>,
+    bit_position: usize,
+}
+
+impl ReferenceBitReader {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self { buffer, bit_position: 0 }
+    }
+    
+    pub fn read_bits(&mut self, num_bits: usize) -> u64 {
+        let mut value = 0u64;
+        for _ in 0..num_bits {
+            let bit = (self.buffer[self.bit_position / 8] >> (7 - self.bit_position % 8)) & 1;
+            value = (value << 1) | bit as u64;
+            self.bit_position += 1;
+        }
+        value
+    }
+}
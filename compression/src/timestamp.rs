@@ -1,37 +1,68 @@
 use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use tsdb_core::TimestampPrecision;
 
+/// Payload widths of the four non-zero delta-of-delta buckets at
+/// millisecond precision. Each finer precision widens every bucket by ten
+/// bits, about a factor of a thousand, so the same jitter in wall-clock
+/// terms lands in the same bucket whatever unit it is counted in.
+const MILLISECOND_BUCKET_WIDTHS: [usize; 4] = [7, 9, 12, 32];
+
+/// The size class a delta-of-delta was written in, named after the width
+/// of its payload at millisecond precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DodBucket {
+    /// A single `0` bit: the same interval as last time.
+    Zero,
+    Bits7,
+    Bits9,
+    Bits12,
+    Bits32,
+    /// The escape pattern of the widest bucket followed by a full 64-bit
+    /// value.
+    Bits64,
+}
+
+/// Bucket widths for timestamps counted in `precision`.
+fn bucket_widths(precision: TimestampPrecision) -> [usize; 4] {
+    let extra = match precision {
+        TimestampPrecision::Milliseconds => 0,
+        TimestampPrecision::Microseconds => 10,
+        TimestampPrecision::Nanoseconds => 20,
+    };
+    MILLISECOND_BUCKET_WIDTHS.map(|width| width + extra)
+}
+
+#[derive(Debug, Clone)]
 pub struct TimestampCompressor {
     last_timestamp: u64,
     last_delta: i64,
+    widths: [usize; 4],
 }
 
 impl TimestampCompressor {
+    /// A compressor for millisecond timestamps.
     pub fn new(first_timestamp: u64) -> Self {
+        Self::with_precision(first_timestamp, TimestampPrecision::Milliseconds)
+    }
+    
+    /// A compressor whose buckets are sized for timestamps counted in
+    /// `precision`. The decompressor must be given the same precision.
+    pub fn with_precision(first_timestamp: u64, precision: TimestampPrecision) -> Self {
         Self {
             last_timestamp: first_timestamp,
             last_delta: 0,
+            widths: bucket_widths(precision),
         }
     }
     
     pub fn compress(&mut self, timestamp: u64, writer: &mut BitWriter) -> Result<(), CompressionError> {
-        let delta = timestamp as i64 - self.last_timestamp as i64;
-        let delta_of_delta = delta - self.last_delta;
+        let delta = i64::try_from(timestamp as i128 - self.last_timestamp as i128)
+            .map_err(|_| CompressionError::TimestampOverflow(timestamp))?;
+        let delta_of_delta = delta.checked_sub(self.last_delta)
+            .ok_or(CompressionError::TimestampOverflow(timestamp))?;
         
-        if delta_of_delta == 0 {
-            writer.write_bit(0)?;
-        } else if delta_of_delta >= -63 && delta_of_delta <= 64 {
-            writer.write_bits(0b10, 2)?;
-            writer.write_bits((delta_of_delta as u8 as u64) & 0x7F, 7)?;
-        } else if delta_of_delta >= -255 && delta_of_delta <= 256 {
-            writer.write_bits(0b110, 3)?;
-            writer.write_bits((delta_of_delta as u16 as u64) & 0x1FF, 9)?;
-        } else if delta_of_delta >= -2047 && delta_of_delta <= 2048 {
-            writer.write_bits(0b1110, 4)?;
-            writer.write_bits((delta_of_delta as u16 as u64) & 0xFFF, 12)?;
-        } else {
-            writer.write_bits(0b1111, 4)?;
-            writer.write_bits(delta_of_delta as u32 as u64, 32)?;
-        }
+        write_bucketed(delta_of_delta, &self.widths, writer)?;
         
         self.last_timestamp = timestamp;
         self.last_delta = delta;
@@ -42,220 +73,165 @@ impl TimestampCompressor {
 pub struct TimestampDecompressor {
     last_timestamp: u64,
     last_delta: i64,
+    widths: [usize; 4],
 }
 
 impl TimestampDecompressor {
+    /// A decompressor for millisecond timestamps.
     pub fn new(first_timestamp: u64) -> Self {
+        Self::with_precision(first_timestamp, TimestampPrecision::Milliseconds)
+    }
+    
+    pub fn with_precision(first_timestamp: u64, precision: TimestampPrecision) -> Self {
         Self {
             last_timestamp: first_timestamp,
             last_delta: 0,
+            widths: bucket_widths(precision),
         }
     }
     
-    pub fn decompress(&mut self, reader: &mut BitReader) -> Result<u64, CompressionError> {
-        let delta_of_delta = if reader.read_bit()? == 0 {
-            0
-        } else {
-            let next_bit = reader.read_bit()?;
-            if next_bit == 0 {
-                let bits = reader.read_bits(7)? as u8;
-                (bits as i8) as i64
-            } else {
-                let next_bit = reader.read_bit()?;
-                if next_bit == 0 {
-                    let bits = reader.read_bits(9)? as u16;
-                    if bits & 0x100 != 0 {
-                        ((bits | 0xFE00) as i16) as i64
-                    } else {
-                        (bits as i16) as i64
-                    }
-                } else {
-                    let next_bit = reader.read_bit()?;
-                    if next_bit == 0 {
-                        let bits = reader.read_bits(12)? as u16;
-                        if bits & 0x800 != 0 {
-                            ((bits | 0xF000) as i16) as i64
-                        } else {
-                            (bits as i16) as i64
-                        }
-                    } else {
-                        reader.read_bits(32)? as i32 as i64
-                    }
-                }
-            }
-        };
-        
-        let delta = self.last_delta + delta_of_delta;
-        let timestamp = (self.last_timestamp as i64 + delta) as u64;
+    pub fn decompress(&mut self, reader: &mut BitReader<'_>) -> Result<u64, CompressionError> {
+        self.decompress_bucket(reader).map(|(timestamp, _)| timestamp)
+    }
+    
+    /// Like [`decompress`](Self::decompress), also reporting which bucket
+    /// the delta-of-delta was stored in.
+    pub fn decompress_bucket(&mut self, reader: &mut BitReader<'_>) -> Result<(u64, DodBucket), CompressionError> {
+        let (delta_of_delta, bucket) = read_bucketed(reader, &self.widths)?;
+        let delta = self.last_delta.checked_add(delta_of_delta)
+            .ok_or(CompressionError::InvalidFormat)?;
+        let timestamp = u64::try_from(self.last_timestamp as i128 + delta as i128)
+            .map_err(|_| CompressionError::InvalidFormat)?;
         
         self.last_timestamp = timestamp;
         self.last_delta = delta;
         
-        Ok(timestamp)
+        Ok((timestamp, bucket))
     }
 }
 
-pub struct BitWriter {
-    buffer: Vec<u8>,
-    bit_count: usize,
+/// Writes a delta-of-delta in the smallest millisecond bucket that holds
+/// it. Codecs also use this for deltas of values.
+pub(crate) fn write_delta_of_delta(delta_of_delta: i64, writer: &mut BitWriter) -> Result<(), CompressionError> {
+    write_bucketed(delta_of_delta, &MILLISECOND_BUCKET_WIDTHS, writer)
 }
 
-impl BitWriter {
-    pub fn new() -> Self {
-        Self {
-            buffer: Vec::new(),
-            bit_count: 0,
-        }
-    }
-    
-    pub fn write_bit(&mut self, bit: u8) -> Result<(), CompressionError> {
-        if self.bit_count % 8 == 0 {
-            self.buffer.push(0);
-        }
-        
-        let byte_index = self.bit_count / 8;
-        let bit_index = 7 - (self.bit_count % 8);
-        
-        if bit != 0 {
-            self.buffer[byte_index] |= 1 << bit_index;
-        }
-        
-        self.bit_count += 1;
-        Ok(())
+pub(crate) fn read_delta_of_delta(reader: &mut BitReader<'_>) -> Result<(i64, DodBucket), CompressionError> {
+    read_bucketed(reader, &MILLISECOND_BUCKET_WIDTHS)
+}
+
+/// Writes `delta_of_delta` in the smallest of the buckets `widths` that
+/// holds it. The lowest value of the widest bucket is never written as a
+/// value: it is the escape after which the decoder reads the full 64 bits
+/// that follow.
+fn write_bucketed(delta_of_delta: i64, widths: &[usize; 4], writer: &mut BitWriter) -> Result<(), CompressionError> {
+    if delta_of_delta == 0 {
+        return writer.write_bit(0);
     }
     
-    pub fn write_bits(&mut self, value: u64, num_bits: usize) -> Result<(), CompressionError> {
-        for i in (0..num_bits).rev() {
-            let bit = ((value >> i) & 1) as u8;
-            self.write_bit(bit)?;
+    for (index, &width) in widths.iter().enumerate() {
+        let limit = 1i64 << (width - 1);
+        let widest = index == widths.len() - 1;
+        let lowest = if widest { -limit + 1 } else { -limit };
+        if (lowest..limit).contains(&delta_of_delta) {
+            write_prefix(index, writer)?;
+            return writer.write_bits(delta_of_delta as u64, width);
         }
-        Ok(())
-    }
-    
-    pub fn finish(self) -> Vec<u8> {
-        self.buffer
     }
     
-    pub fn bit_count(&self) -> usize {
-        self.bit_count
-    }
+    let widest = widths[widths.len() - 1];
+    write_prefix(widths.len() - 1, writer)?;
+    writer.write_bits(escape_pattern(widest), widest)?;
+    writer.write_bits(delta_of_delta as u64, 64)
 }
 
-pub struct BitReader {
-    buffer: Vec<u8>,
-    bit_position: usize,
+/// Writes the prefix of bucket `index`: a `1` for every bucket up to it,
+/// then a `0` unless it is the widest.
+fn write_prefix(index: usize, writer: &mut BitWriter) -> Result<(), CompressionError> {
+    if index == 3 {
+        writer.write_bits(0b1111, 4)
+    } else {
+        writer.write_bits(((1u64 << (index + 1)) - 1) << 1, index + 2)
+    }
 }
 
-impl BitReader {
-    pub fn new(buffer: Vec<u8>) -> Self {
-        Self {
-            buffer,
-            bit_position: 0,
-        }
-    }
-    
-    pub fn read_bit(&mut self) -> Result<u8, CompressionError> {
-        if self.bit_position >= self.buffer.len() * 8 {
-            return Err(CompressionError::InsufficientData);
-        }
-        
-        let byte_index = self.bit_position / 8;
-        let bit_index = 7 - (self.bit_position % 8);
-        
-        let bit = (self.buffer[byte_index] >> bit_index) & 1;
-        self.bit_position += 1;
-        
-        Ok(bit)
+fn read_bucketed(reader: &mut BitReader<'_>, widths: &[usize; 4]) -> Result<(i64, DodBucket), CompressionError> {
+    if reader.read_bit()? == 0 {
+        return Ok((0, DodBucket::Zero));
     }
-    
-    pub fn read_bits(&mut self, num_bits: usize) -> Result<u64, CompressionError> {
-        let mut value = 0u64;
-        
-        for _ in 0..num_bits {
-            value = (value << 1) | (self.read_bit()? as u64);
+    for (&width, bucket) in widths.iter().zip([DodBucket::Bits7, DodBucket::Bits9, DodBucket::Bits12]) {
+        if reader.read_bit()? == 0 {
+            return Ok((sign_extend(reader.read_bits(width)?, width as u32), bucket));
         }
-        
-        Ok(value)
     }
     
-    pub fn peek_bits(&self, num_bits: usize) -> Result<u64, CompressionError> {
-        let mut value = 0u64;
-        let mut pos = self.bit_position;
-        
-        for _ in 0..num_bits {
-            if pos >= self.buffer.len() * 8 {
-                return Err(CompressionError::InsufficientData);
-            }
-            
-            let byte_index = pos / 8;
-            let bit_index = 7 - (pos % 8);
-            let bit = (self.buffer[byte_index] >> bit_index) & 1;
-            
-            value = (value << 1) | (bit as u64);
-            pos += 1;
-        }
-        
-        Ok(value)
+    let widest = widths[3];
+    let bits = reader.read_bits(widest)?;
+    if bits == escape_pattern(widest) {
+        Ok((reader.read_bits(64)? as i64, DodBucket::Bits64))
+    } else {
+        Ok((sign_extend(bits, widest as u32), DodBucket::Bits32))
     }
 }
 
+/// The bits of the lowest value `width` bits can hold, e.g. `i32::MIN` for
+/// the 32-bit bucket.
+fn escape_pattern(width: usize) -> u64 {
+    1 << (width - 1)
+}
+
+fn sign_extend(bits: u64, width: u32) -> i64 {
+    let shift = 64 - width;
+    ((bits << shift) as i64) >> shift
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
-    #[test]
-    fn test_bit_writer_single_bit() {
-        let mut writer = BitWriter::new();
-        writer.write_bit(1).unwrap();
-        writer.write_bit(0).unwrap();
-        writer.write_bit(1).unwrap();
-        
-        let data = writer.finish();
-        assert_eq!(data.len(), 1);
-        assert_eq!(data[0], 0b10100000);
+    fn round_trip(timestamps: &[u64]) -> Vec<u64> {
+        round_trip_with(timestamps, TimestampPrecision::Milliseconds)
     }
 
-    #[test]
-    fn test_bit_writer_multiple_bytes() {
+    fn round_trip_with(timestamps: &[u64], precision: TimestampPrecision) -> Vec<u64> {
+        let mut compressor = TimestampCompressor::with_precision(timestamps[0], precision);
         let mut writer = BitWriter::new();
-        for i in 0..16 {
-            writer.write_bit(i % 2).unwrap();
+        for &timestamp in &timestamps[1..] {
+            compressor.compress(timestamp, &mut writer).unwrap();
         }
         
         let data = writer.finish();
-        assert_eq!(data.len(), 2);
-        assert_eq!(data[0], 0b01010101);
-        assert_eq!(data[1], 0b01010101);
-    }
-
-    #[test]
-    fn test_bit_writer_bits() {
-        let mut writer = BitWriter::new();
-        writer.write_bits(0b101, 3).unwrap();
-        writer.write_bits(0b11, 2).unwrap();
-        
-        let data = writer.finish();
-        assert_eq!(data[0], 0b10111000);
-    }
-
-    #[test]
-    fn test_bit_reader_single_bit() {
-        let data = vec![0b10100000];
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = TimestampDecompressor::with_precision(timestamps[0], precision);
         
-        assert_eq!(reader.read_bit().unwrap(), 1);
-        assert_eq!(reader.read_bit().unwrap(), 0);
-        assert_eq!(reader.read_bit().unwrap(), 1);
-        assert_eq!(reader.read_bit().unwrap(), 0);
+        let mut decoded = vec![timestamps[0]];
+        for _ in 1..timestamps.len() {
+            decoded.push(decompressor.decompress(&mut reader).unwrap());
+        }
+        decoded
     }
 
-    #[test]
-    fn test_bit_reader_bits() {
-        let data = vec![0b10111000];
-        let mut reader = BitReader::new(data);
-        
-        assert_eq!(reader.read_bits(3).unwrap(), 0b101);
-        assert_eq!(reader.read_bits(2).unwrap(), 0b11);
+    /// Increasing sequences whose gaps mix steady intervals, jitter and
+    /// occasional gaps far beyond the 32-bit bucket.
+    fn increasing_timestamps() -> impl Strategy<Value = Vec<u64>> {
+        let gap = prop_oneof![
+            4 => Just(1000u64),
+            4 => 990u64..1010,
+            2 => 0u64..5000,
+            1 => 0u64..(1 << 40),
+            1 => 0u64..(1 << 62),
+        ];
+        (0u64..(1 << 62), prop::collection::vec(gap, 1..300)).prop_map(|(start, gaps)| {
+            let mut timestamps = vec![start];
+            for gap in gaps {
+                match timestamps.last().unwrap().checked_add(gap) {
+                    Some(next) => timestamps.push(next),
+                    None => break,
+                }
+            }
+            timestamps
+        })
     }
 
     #[test]
@@ -268,7 +244,7 @@ mod tests {
         compressor.compress(1030, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = TimestampDecompressor::new(1000);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 1010);
@@ -286,7 +262,7 @@ mod tests {
         compressor.compress(1032, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = TimestampDecompressor::new(1000);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 1010);
@@ -304,7 +280,7 @@ mod tests {
         compressor.compress(2010, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = TimestampDecompressor::new(1000);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 1010);
@@ -313,10 +289,103 @@ mod tests {
     }
 
     #[test]
-    fn test_empty_data_error() {
-        let data = vec![];
-        let mut reader = BitReader::new(data);
+    fn test_timestamp_compression_negative_small_delta() {
+        let timestamps = [1000, 1010, 1019, 1029, 1038, 1100, 1101];
+        assert_eq!(round_trip(&timestamps), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_compression_bucket_edges() {
+        let mut timestamps = vec![1_000_000u64, 1_010_000];
+        for dod in [-64i64, 63, 64, -256, 255, 256, -2048, 2047, 2048, -4096] {
+            let delta = timestamps[timestamps.len() - 1] - timestamps[timestamps.len() - 2];
+            timestamps.push((timestamps[timestamps.len() - 1] as i64 + delta as i64 + dod) as u64);
+        }
+        assert_eq!(round_trip(&timestamps), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_compression_gap_beyond_32_bits() {
+        let day_ms = 24 * 60 * 60 * 1000;
+        let timestamps = [1_600_000_000_000, 1_600_000_001_000, 1_600_000_001_000 + 30 * day_ms, 1_600_000_001_000 + 30 * day_ms + 1000];
+        assert_eq!(round_trip(&timestamps), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_compression_nanosecond_gaps() {
+        let second_ns = 1_000_000_000u64;
+        let start = 1_600_000_000 * second_ns;
+        let timestamps = [start, start + 10 * second_ns, start + 20 * second_ns, start + 3600 * second_ns, start + 3610 * second_ns];
+        assert_eq!(round_trip(&timestamps), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_buckets_scale_with_precision() {
+        // The same millisecond of jitter, counted in each unit.
+        for (precision, jitter) in [
+            (TimestampPrecision::Milliseconds, 1i64),
+            (TimestampPrecision::Microseconds, 1_000),
+            (TimestampPrecision::Nanoseconds, 1_000_000),
+        ] {
+            let second = precision.units_per_second() as i64;
+            let mut compressor = TimestampCompressor::with_precision(0, precision);
+            let mut writer = BitWriter::new();
+            compressor.compress(second as u64, &mut writer).unwrap();
+            compressor.compress((2 * second + jitter) as u64, &mut writer).unwrap();
+            
+            let data = writer.finish();
+            let mut reader = BitReader::new(&data);
+            let mut decompressor = TimestampDecompressor::with_precision(0, precision);
+            decompressor.decompress(&mut reader).unwrap();
+            assert_eq!(decompressor.decompress_bucket(&mut reader).unwrap(), ((2 * second + jitter) as u64, DodBucket::Bits7), "{}", precision);
+        }
+        
+        let second_ns = 1_000_000_000u64;
+        let start = 1_600_000_000 * second_ns;
+        let timestamps = [start, start + second_ns, start + 2 * second_ns + 1, start + 40 * 86_400 * second_ns, start + 40 * 86_400 * second_ns + 17];
+        assert_eq!(round_trip_with(&timestamps, TimestampPrecision::Nanoseconds), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_compression_i32_min_delta_of_delta() {
+        let timestamps = [0u64, 1u64 << 32, (1u64 << 32) + (1u64 << 31)];
+        assert_eq!(round_trip(&timestamps), timestamps);
+    }
+
+    #[test]
+    fn test_timestamp_compression_overflow() {
+        let mut compressor = TimestampCompressor::new(0);
+        let mut writer = BitWriter::new();
         
-        assert!(reader.read_bit().is_err());
+        assert!(matches!(
+            compressor.compress(u64::MAX, &mut writer),
+            Err(CompressionError::TimestampOverflow(u64::MAX))
+        ));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_timestamp_compression_delta_of_delta_overflow() {
+        let mut compressor = TimestampCompressor::new(i64::MAX as u64);
+        let mut writer = BitWriter::new();
+        
+        compressor.compress(0, &mut writer).unwrap();
+        assert!(matches!(
+            compressor.compress(i64::MAX as u64, &mut writer),
+            Err(CompressionError::TimestampOverflow(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_increasing_timestamps_round_trip(
+            timestamps in increasing_timestamps(),
+            precision in prop::sample::select(vec![
+                TimestampPrecision::Milliseconds,
+                TimestampPrecision::Microseconds,
+                TimestampPrecision::Nanoseconds,
+            ]),
+        ) {
+            prop_assert_eq!(round_trip_with(&timestamps, precision), timestamps);
+        }
+    }
+}
@@ -0,0 +1,509 @@
+use crate::error::CompressionError;
+use crate::bits::{BitWriter, BitReader};
+use crate::codec::{Codec, CodecId, GorillaCodec, PointDecoder, PointEncoder, BOOLEAN_CODEC_ID, INT64_CODEC_ID, STRING_CODEC_ID};
+use crate::diagnostics::BlockStats;
+use crate::histogram::HistogramCodec;
+use crate::timestamp::{read_delta_of_delta, write_delta_of_delta, TimestampCompressor, TimestampDecompressor};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tsdb_core::{DataPoint, TimestampPrecision, Value, ValueType};
+
+/// Longest string, in bytes, that [`StringCodec`] can store.
+pub const MAX_STRING_LEN: usize = u16::MAX as usize;
+
+/// The 64 bits stored for a point kept in full in a block header or restart
+/// index. Strings and histograms do not fit; [`StringCodec`] and
+/// [`HistogramCodec`] write them to the stream instead and store zero here.
+pub fn verbatim_bits(value: &Value) -> u64 {
+    match *value {
+        Value::Float(value) => value.to_bits(),
+        Value::Integer(value) => value as u64,
+        Value::Boolean(value) => value as u64,
+        Value::String(_) | Value::Histogram(_) => 0,
+    }
+}
+
+/// Reverses [`verbatim_bits`]. Strings and histograms come back empty until the codec's
+/// decoder fills them in; see [`PointDecoder::read_first`].
+pub fn verbatim_value(value_type: ValueType, bits: u64) -> Value {
+    match value_type {
+        ValueType::Float => Value::Float(f64::from_bits(bits)),
+        ValueType::Integer => Value::Integer(bits as i64),
+        ValueType::Boolean => Value::Boolean(bits != 0),
+        ValueType::String => Value::String(String::new()),
+        ValueType::Histogram => Value::Histogram(Box::default()),
+    }
+}
+
+/// The codec new blocks of `value_type` are written with by default.
+pub fn codec_for_type(value_type: ValueType) -> Arc<dyn Codec> {
+    match value_type {
+        ValueType::Float => Arc::new(GorillaCodec),
+        ValueType::Integer => Arc::new(Int64Codec),
+        ValueType::Boolean => Arc::new(BooleanCodec),
+        ValueType::String => Arc::new(StringCodec),
+        ValueType::Histogram => Arc::new(HistogramCodec),
+    }
+}
+
+/// Fails unless `point` holds a value of type `expected`.
+pub fn check_value_type(point: &DataPoint, expected: ValueType) -> Result<(), CompressionError> {
+    let actual = point.value_type();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(CompressionError::TypeMismatch { expected, actual })
+    }
+}
+
+/// The value of a float point, for the float codecs.
+pub(crate) fn float_value(point: &DataPoint) -> Result<f64, CompressionError> {
+    check_value_type(point, ValueType::Float)?;
+    Ok(point.value.as_f64().unwrap_or_default())
+}
+
+/// The float a float codec is seeded with. Callers check the seed point
+/// against [`Codec::value_type`] before creating an encoder.
+pub(crate) fn float_seed(point: &DataPoint) -> f64 {
+    point.value.as_f64().unwrap_or_default()
+}
+
+/// Delta-of-delta timestamps with `i64` values stored as delta-of-deltas in
+/// the same buckets. Deltas wrap, so the whole `i64` range round-trips; a
+/// steadily increasing counter costs a single bit per value.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Int64Codec;
+
+impl Codec for Int64Codec {
+    fn id(&self) -> CodecId {
+        INT64_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "int64"
+    }
+    
+    fn value_type(&self) -> ValueType {
+        ValueType::Integer
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(Int64Encoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            last_value: first_point.value.as_i64().unwrap_or_default(),
+            last_delta: 0,
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(Int64Decoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            last_value: first_point.value.as_i64().unwrap_or_default(),
+            last_delta: 0,
+        })
+    }
+}
+
+struct Int64Encoder {
+    timestamps: TimestampCompressor,
+    last_value: i64,
+    last_delta: i64,
+}
+
+impl PointEncoder for Int64Encoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        check_value_type(point, ValueType::Integer)?;
+        let value = point.value.as_i64().unwrap_or_default();
+        self.timestamps.compress(point.timestamp, writer)?;
+        
+        let delta = value.wrapping_sub(self.last_value);
+        write_delta_of_delta(delta.wrapping_sub(self.last_delta), writer)?;
+        self.last_value = value;
+        self.last_delta = delta;
+        Ok(())
+    }
+}
+
+struct Int64Decoder {
+    timestamps: TimestampDecompressor,
+    last_value: i64,
+    last_delta: i64,
+}
+
+impl PointDecoder for Int64Decoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let (delta_of_delta, _) = read_delta_of_delta(reader)?;
+        Ok(DataPoint::new(timestamp, self.apply(delta_of_delta)))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let (delta_of_delta, _) = read_delta_of_delta(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.bits += reader.bit_position() - middle;
+        Ok(DataPoint::new(timestamp, self.apply(delta_of_delta)))
+    }
+}
+
+impl Int64Decoder {
+    fn apply(&mut self, delta_of_delta: i64) -> i64 {
+        self.last_delta = self.last_delta.wrapping_add(delta_of_delta);
+        self.last_value = self.last_value.wrapping_add(self.last_delta);
+        self.last_value
+    }
+}
+
+/// Delta-of-delta timestamps with each boolean packed into a single bit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BooleanCodec;
+
+impl Codec for BooleanCodec {
+    fn id(&self) -> CodecId {
+        BOOLEAN_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "boolean"
+    }
+    
+    fn value_type(&self) -> ValueType {
+        ValueType::Boolean
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(BooleanEncoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(BooleanDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+        })
+    }
+}
+
+struct BooleanEncoder {
+    timestamps: TimestampCompressor,
+}
+
+impl PointEncoder for BooleanEncoder {
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        check_value_type(point, ValueType::Boolean)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        writer.write_bit(point.value.as_bool().unwrap_or_default() as u8)
+    }
+}
+
+struct BooleanDecoder {
+    timestamps: TimestampDecompressor,
+}
+
+impl PointDecoder for BooleanDecoder {
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let value = reader.read_bit()? == 1;
+        Ok(DataPoint::new(timestamp, value))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let value = reader.read_bit()? == 1;
+        
+        stats.timestamps.record(bucket, reader.bit_position() - 1 - start);
+        stats.values.bits += 1;
+        Ok(DataPoint::new(timestamp, value))
+    }
+}
+
+/// Delta-of-delta timestamps with strings encoded against a dictionary built
+/// as the block is written.
+///
+/// Each value is one of: `0`, the same string as the previous point; `10`
+/// and an index into the dictionary, as wide as the dictionary needs; or
+/// `11` and a new string, as a 16-bit byte length and its UTF-8 bytes, which
+/// is then added to the dictionary. The first string of a block, or of a
+/// restart segment, opens the stream and seeds the dictionary.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StringCodec;
+
+impl Codec for StringCodec {
+    fn id(&self) -> CodecId {
+        STRING_CODEC_ID
+    }
+    
+    fn name(&self) -> &'static str {
+        "string"
+    }
+    
+    fn value_type(&self) -> ValueType {
+        ValueType::String
+    }
+    
+    fn encoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointEncoder> {
+        Box::new(StringEncoder {
+            timestamps: TimestampCompressor::with_precision(first_point.timestamp, precision),
+            first: first_point.value.as_str().unwrap_or_default().to_string(),
+            dictionary: HashMap::new(),
+            last_index: 0,
+        })
+    }
+    
+    fn decoder(&self, first_point: &DataPoint, precision: TimestampPrecision) -> Box<dyn PointDecoder> {
+        Box::new(StringDecoder {
+            timestamps: TimestampDecompressor::with_precision(first_point.timestamp, precision),
+            dictionary: Vec::new(),
+            last_index: 0,
+        })
+    }
+}
+
+/// Bits needed to index a dictionary of `len` entries.
+fn index_width(len: usize) -> usize {
+    match len {
+        0 | 1 => 0,
+        len => (usize::BITS - (len - 1).leading_zeros()) as usize,
+    }
+}
+
+fn write_literal(value: &str, writer: &mut BitWriter) -> Result<(), CompressionError> {
+    writer.write_bits(value.len() as u64, 16)?;
+    for &byte in value.as_bytes() {
+        writer.write_bits(byte as u64, 8)?;
+    }
+    Ok(())
+}
+
+fn read_literal(reader: &mut BitReader<'_>) -> Result<String, CompressionError> {
+    let len = reader.read_bits(16)? as usize;
+    if len * 8 > reader.remaining_bits() {
+        return Err(CompressionError::InsufficientData);
+    }
+    let bytes = (0..len)
+        .map(|_| reader.read_bits(8).map(|byte| byte as u8))
+        .collect::<Result<Vec<u8>, _>>()?;
+    String::from_utf8(bytes).map_err(|_| CompressionError::InvalidFormat)
+}
+
+fn string_value(point: &DataPoint) -> Result<&str, CompressionError> {
+    check_value_type(point, ValueType::String)?;
+    let value = point.value.as_str().unwrap_or_default();
+    if value.len() > MAX_STRING_LEN {
+        return Err(CompressionError::ValueTooLong(value.len()));
+    }
+    Ok(value)
+}
+
+struct StringEncoder {
+    timestamps: TimestampCompressor,
+    first: String,
+    dictionary: HashMap<String, usize>,
+    last_index: usize,
+}
+
+impl PointEncoder for StringEncoder {
+    fn write_first(&mut self, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        if self.first.len() > MAX_STRING_LEN {
+            return Err(CompressionError::ValueTooLong(self.first.len()));
+        }
+        write_literal(&self.first, writer)?;
+        self.dictionary.insert(std::mem::take(&mut self.first), 0);
+        Ok(())
+    }
+    
+    fn encode(&mut self, point: &DataPoint, writer: &mut BitWriter) -> Result<(), CompressionError> {
+        let value = string_value(point)?;
+        self.timestamps.compress(point.timestamp, writer)?;
+        
+        match self.dictionary.get(value) {
+            Some(&index) if index == self.last_index => writer.write_bit(0)?,
+            Some(&index) => {
+                writer.write_bits(0b10, 2)?;
+                writer.write_bits(index as u64, index_width(self.dictionary.len()))?;
+                self.last_index = index;
+            }
+            None => {
+                writer.write_bits(0b11, 2)?;
+                write_literal(value, writer)?;
+                self.last_index = self.dictionary.len();
+                self.dictionary.insert(value.to_string(), self.last_index);
+            }
+        }
+        Ok(())
+    }
+}
+
+struct StringDecoder {
+    timestamps: TimestampDecompressor,
+    dictionary: Vec<String>,
+    last_index: usize,
+}
+
+impl StringDecoder {
+    fn read_value(&mut self, reader: &mut BitReader<'_>) -> Result<String, CompressionError> {
+        if reader.read_bit()? == 1 {
+            if reader.read_bit()? == 0 {
+                let index = reader.read_bits(index_width(self.dictionary.len()))? as usize;
+                self.last_index = index;
+            } else {
+                self.dictionary.push(read_literal(reader)?);
+                self.last_index = self.dictionary.len() - 1;
+            }
+        }
+        self.dictionary.get(self.last_index)
+            .cloned()
+            .ok_or(CompressionError::InvalidFormat)
+    }
+}
+
+impl PointDecoder for StringDecoder {
+    fn read_first(&mut self, reader: &mut BitReader<'_>, point: DataPoint) -> Result<DataPoint, CompressionError> {
+        let value = read_literal(reader)?;
+        self.dictionary.push(value.clone());
+        Ok(DataPoint::new(point.timestamp, value))
+    }
+    
+    fn decode(&mut self, reader: &mut BitReader<'_>) -> Result<DataPoint, CompressionError> {
+        let timestamp = self.timestamps.decompress(reader)?;
+        let value = self.read_value(reader)?;
+        Ok(DataPoint::new(timestamp, value))
+    }
+    
+    fn decode_traced(&mut self, reader: &mut BitReader<'_>, stats: &mut BlockStats) -> Result<DataPoint, CompressionError> {
+        let start = reader.bit_position();
+        let (timestamp, bucket) = self.timestamps.decompress_bucket(reader)?;
+        let middle = reader.bit_position();
+        let value = self.read_value(reader)?;
+        
+        stats.timestamps.record(bucket, middle - start);
+        stats.values.bits += reader.bit_position() - middle;
+        Ok(DataPoint::new(timestamp, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{compress_block_indexed, compress_block_with, decompress_block};
+    use proptest::prelude::*;
+
+    fn series<V: Into<Value> + Clone>(values: &[V]) -> Vec<DataPoint> {
+        values.iter()
+            .enumerate()
+            .map(|(i, value)| DataPoint::new(1000 + i as u64 * 10, value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_verbatim_round_trip() {
+        for value in [Value::Float(-1.5), Value::Integer(i64::MIN), Value::Boolean(true)] {
+            assert_eq!(verbatim_value(value.value_type(), verbatim_bits(&value)), value);
+        }
+    }
+
+    #[test]
+    fn test_int64_counter() {
+        let points = series(&(0..1000i64).map(|i| (1 << 40) + i * 3).collect::<Vec<_>>());
+        let block = compress_block_with(&points, &Int64Codec).unwrap();
+        
+        assert_eq!(block.value_type, ValueType::Integer);
+        assert_eq!(decompress_block(&block).unwrap(), points);
+        // Both columns are regular, so every point after the second costs
+        // two bits.
+        assert!(block.compressed_data.len() < 300);
+    }
+
+    #[test]
+    fn test_int64_extremes() {
+        let points = series(&[0, i64::MAX, i64::MIN, -1, i64::MIN, i64::MAX]);
+        let block = compress_block_with(&points, &Int64Codec).unwrap();
+        assert_eq!(decompress_block(&block).unwrap(), points);
+    }
+
+    #[test]
+    fn test_boolean_bit_packed() {
+        let points = series(&(0..800).map(|i| i % 3 == 0).collect::<Vec<_>>());
+        let block = compress_block_with(&points, &BooleanCodec).unwrap();
+        
+        assert_eq!(decompress_block(&block).unwrap(), points);
+        assert!(block.compressed_data.len() < 300);
+    }
+
+    #[test]
+    fn test_string_dictionary() {
+        let states = ["up", "up", "degraded", "up", "down", "down", "degraded", "", "up"];
+        let points = series(&states);
+        let block = compress_block_with(&points, &StringCodec).unwrap();
+        assert_eq!(decompress_block(&block).unwrap(), points);
+        
+        let repeated = series(&vec!["v1.4.2-rc1"; 1000]);
+        let block = compress_block_with(&repeated, &StringCodec).unwrap();
+        assert!(block.compressed_data.len() < 300);
+    }
+
+    #[test]
+    fn test_string_restarts() {
+        let values: Vec<String> = (0..100).map(|i| format!("build-{}", i % 7)).collect();
+        let points = series(&values);
+        let block = compress_block_indexed(&points, &StringCodec, 9).unwrap();
+        assert_eq!(decompress_block(&block).unwrap(), points);
+        
+        let mut decoder = crate::BlockDecoder::new(&block).unwrap();
+        decoder.seek(points[50].timestamp);
+        assert_eq!(decoder.next().unwrap().unwrap(), points[45]);
+    }
+
+    #[test]
+    fn test_typed_codecs_reject_other_types() {
+        assert!(matches!(
+            compress_block_with(&series(&[1.5]), &Int64Codec),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Integer, actual: ValueType::Float })
+        ));
+        let mixed = vec![DataPoint::new(1000, true), DataPoint::new(1010, "yes")];
+        assert!(matches!(
+            compress_block_with(&mixed, &BooleanCodec),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Boolean, actual: ValueType::String })
+        ));
+        assert!(matches!(
+            compress_block_with(&series(&[7i64]), &GorillaCodec),
+            Err(CompressionError::TypeMismatch { expected: ValueType::Float, actual: ValueType::Integer })
+        ));
+    }
+
+    #[test]
+    fn test_string_too_long() {
+        let long = "x".repeat(MAX_STRING_LEN + 1);
+        assert!(matches!(
+            compress_block_with(&series(&[long.as_str()]), &StringCodec),
+            Err(CompressionError::ValueTooLong(len)) if len == MAX_STRING_LEN + 1
+        ));
+        assert!(matches!(
+            compress_block_with(&series(&["ok", long.as_str()]), &StringCodec),
+            Err(CompressionError::ValueTooLong(_))
+        ));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_int64_round_trip(values in prop::collection::vec(any::<i64>(), 1..200), restart_interval in 0usize..16) {
+            let points = series(&values);
+            let block = compress_block_indexed(&points, &Int64Codec, restart_interval).unwrap();
+            prop_assert_eq!(decompress_block(&block).unwrap(), points);
+        }
+        
+        #[test]
+        fn prop_string_round_trip(
+            values in prop::collection::vec(prop::sample::select(vec!["a", "b", "ccc", "", "é", "status: ok"]), 1..200),
+            restart_interval in 0usize..16,
+        ) {
+            let points = series(&values);
+            let block = compress_block_indexed(&points, &StringCodec, restart_interval).unwrap();
+            prop_assert_eq!(decompress_block(&block).unwrap(), points);
+        }
+    }
+}
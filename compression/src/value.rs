@@ -1,6 +1,22 @@
 use crate::error::CompressionError;
-use crate::timestamp::{BitWriter, BitReader};
+use crate::bits::{BitWriter, BitReader};
 
+/// How a Gorilla value was written relative to the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XorCase {
+    /// A single `0` bit: the value did not change.
+    Repeat,
+    /// The XOR fit in the previous leading/trailing-zero window.
+    ReusedWindow,
+    /// A new window was written before the XOR.
+    NewWindow,
+}
+
+/// The leading-zero count of a window that has not been set up yet. No XOR
+/// fits it, so the first value that differs from the seed writes a window.
+const NO_WINDOW: usize = usize::MAX;
+
+#[derive(Debug, Clone)]
 pub struct ValueCompressor {
     last_value: f64,
     last_leading_zeros: usize,
@@ -9,9 +25,11 @@ pub struct ValueCompressor {
 
 impl ValueCompressor {
     pub fn new(first_value: f64) -> Self {
+        // Start with no usable window so the first XOR sets one up, rather
+        // than reusing a full 64-bit window for every value that follows.
         Self {
             last_value: first_value,
-            last_leading_zeros: 0,
+            last_leading_zeros: NO_WINDOW,
             last_trailing_zeros: 0,
         }
     }
@@ -28,7 +46,9 @@ impl ValueCompressor {
         writer.write_bit(1)?;
         
         let xor = current_bits ^ last_bits;
-        let leading_zeros = xor.leading_zeros() as usize;
+        // The leading-zero count has a 5-bit field; longer runs spend a few
+        // zero bits in the payload instead.
+        let leading_zeros = (xor.leading_zeros() as usize).min(31);
         let trailing_zeros = xor.trailing_zeros() as usize;
         
         if leading_zeros >= self.last_leading_zeros 
@@ -43,8 +63,9 @@ impl ValueCompressor {
             
             writer.write_bits(leading_zeros as u64, 5)?;
             
+            // A full 64-bit window does not fit in 6 bits and is written as 0.
             let meaningful_bits = 64 - leading_zeros - trailing_zeros;
-            writer.write_bits(meaningful_bits as u64, 6)?;
+            writer.write_bits((meaningful_bits & 0x3F) as u64, 6)?;
             
             let shifted_xor = xor >> trailing_zeros;
             writer.write_bits(shifted_xor, meaningful_bits)?;
@@ -68,44 +89,59 @@ impl ValueDecompressor {
     pub fn new(first_value: f64) -> Self {
         Self {
             last_value: first_value,
-            last_leading_zeros: 0,
+            last_leading_zeros: NO_WINDOW,
             last_trailing_zeros: 0,
         }
     }
     
-    pub fn decompress(&mut self, reader: &mut BitReader) -> Result<f64, CompressionError> {
+    pub fn decompress(&mut self, reader: &mut BitReader<'_>) -> Result<f64, CompressionError> {
+        self.decompress_case(reader).map(|(value, _)| value)
+    }
+    
+    /// Like [`decompress`](Self::decompress), also reporting how the value
+    /// was encoded.
+    pub fn decompress_case(&mut self, reader: &mut BitReader<'_>) -> Result<(f64, XorCase), CompressionError> {
         if reader.read_bit()? == 0 {
-            return Ok(self.last_value);
+            return Ok((self.last_value, XorCase::Repeat));
         }
         
-        let xor = if reader.read_bit()? == 0 {
+        let (xor, case) = if reader.read_bit()? == 0 {
+            // The encoder never reuses a window before writing one.
+            if self.last_leading_zeros == NO_WINDOW {
+                return Err(CompressionError::InvalidFormat);
+            }
             let meaningful_bits = 64 - self.last_leading_zeros - self.last_trailing_zeros;
             let shifted_xor = reader.read_bits(meaningful_bits)?;
-            shifted_xor << self.last_trailing_zeros
+            (shifted_xor << self.last_trailing_zeros, XorCase::ReusedWindow)
         } else {
             let leading_zeros = reader.read_bits(5)? as usize;
-            let meaningful_bits = reader.read_bits(6)? as usize;
-            let trailing_zeros = 64 - leading_zeros - meaningful_bits;
+            let meaningful_bits = match reader.read_bits(6)? as usize {
+                0 => 64,
+                bits => bits,
+            };
+            let trailing_zeros = 64usize.checked_sub(leading_zeros + meaningful_bits)
+                .ok_or(CompressionError::InvalidFormat)?;
             
             let shifted_xor = reader.read_bits(meaningful_bits)?;
             
             self.last_leading_zeros = leading_zeros;
             self.last_trailing_zeros = trailing_zeros;
             
-            shifted_xor << trailing_zeros
+            (shifted_xor << trailing_zeros, XorCase::NewWindow)
         };
         
         let current_bits = self.last_value.to_bits() ^ xor;
         let value = f64::from_bits(current_bits);
         
         self.last_value = value;
-        Ok(value)
+        Ok((value, case))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
 
     #[test]
     fn test_value_compression_same_value() {
@@ -116,7 +152,7 @@ mod tests {
         compressor.compress(42.5, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = ValueDecompressor::new(42.5);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 42.5);
@@ -133,7 +169,7 @@ mod tests {
         compressor.compress(45.0, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = ValueDecompressor::new(42.5);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 43.0);
@@ -141,6 +177,17 @@ mod tests {
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 45.0);
     }
 
+    #[test]
+    fn test_value_decompression_needs_window_before_reuse() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b10, 2).unwrap();
+        writer.write_bits(0, 64).unwrap();
+        let data = writer.finish();
+        
+        let mut decompressor = ValueDecompressor::new(42.5);
+        assert!(matches!(decompressor.decompress(&mut BitReader::new(&data)), Err(CompressionError::InvalidFormat)));
+    }
+
     #[test]
     fn test_value_compression_similar_patterns() {
         let mut compressor = ValueCompressor::new(1.0);
@@ -151,7 +198,7 @@ mod tests {
         compressor.compress(1.3, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = ValueDecompressor::new(1.0);
         
         assert!((decompressor.decompress(&mut reader).unwrap() - 1.1).abs() < 1e-10);
@@ -169,7 +216,7 @@ mod tests {
         compressor.compress(0.0, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = ValueDecompressor::new(0.0);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), 0.0);
@@ -186,10 +233,103 @@ mod tests {
         compressor.compress(f64::NEG_INFINITY, &mut writer).unwrap();
         
         let data = writer.finish();
-        let mut reader = BitReader::new(data);
+        let mut reader = BitReader::new(&data);
         let mut decompressor = ValueDecompressor::new(f64::NAN);
         
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), f64::INFINITY);
         assert_eq!(decompressor.decompress(&mut reader).unwrap(), f64::NEG_INFINITY);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_value_compression_reuses_window() {
+        let mut compressor = ValueCompressor::new(1.0);
+        let mut writer = BitWriter::new();
+        
+        compressor.compress(1.5, &mut writer).unwrap();
+        let first = writer.bit_count();
+        compressor.compress(1.0, &mut writer).unwrap();
+        
+        assert!(first < 64);
+        assert_eq!(writer.bit_count() - first, 2 + 1);
+    }
+
+    #[test]
+    fn test_value_compression_long_leading_zeros() {
+        let values = [1.0, f64::from_bits(1.0f64.to_bits() + 1), 1.0, f64::from_bits(1.0f64.to_bits() ^ (1 << 40))];
+        let mut compressor = ValueCompressor::new(values[0]);
+        let mut writer = BitWriter::new();
+        
+        for &value in &values[1..] {
+            compressor.compress(value, &mut writer).unwrap();
+        }
+        
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = ValueDecompressor::new(values[0]);
+        
+        for &value in &values[1..] {
+            assert_eq!(decompressor.decompress(&mut reader).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_value_compression_full_width_xor() {
+        let values = [f64::from_bits(1), f64::from_bits(1 << 63), f64::from_bits(u64::MAX)];
+        let mut compressor = ValueCompressor::new(values[0]);
+        let mut writer = BitWriter::new();
+        
+        for &value in &values[1..] {
+            compressor.compress(value, &mut writer).unwrap();
+        }
+        
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = ValueDecompressor::new(values[0]);
+        
+        for &value in &values[1..] {
+            assert_eq!(decompressor.decompress(&mut reader).unwrap().to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_value_decompression_rejects_oversized_window() {
+        // Control bits '11', 31 leading zeros, then 0 meaningful bits (read
+        // as 64): a window wider than the value itself.
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b11, 2).unwrap();
+        writer.write_bits(31, 5).unwrap();
+        writer.write_bits(0, 6).unwrap();
+        writer.write_bits(u64::MAX, 64).unwrap();
+        
+        let data = writer.finish();
+        let mut reader = BitReader::new(&data);
+        let mut decompressor = ValueDecompressor::new(1.0);
+        
+        assert!(matches!(decompressor.decompress(&mut reader), Err(CompressionError::InvalidFormat)));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_value_decompression_random_bytes(first in any::<f64>(), data in prop::collection::vec(any::<u8>(), 0..256)) {
+            let mut reader = BitReader::new(&data);
+            let mut decompressor = ValueDecompressor::new(first);
+            while decompressor.decompress(&mut reader).is_ok() {}
+        }
+        
+        #[test]
+        fn prop_value_compression_round_trip(bits in prop::collection::vec(any::<u64>(), 1..200)) {
+            let mut compressor = ValueCompressor::new(f64::from_bits(bits[0]));
+            let mut writer = BitWriter::new();
+            for &b in &bits[1..] {
+                compressor.compress(f64::from_bits(b), &mut writer).unwrap();
+            }
+            
+            let data = writer.finish();
+            let mut reader = BitReader::new(&data);
+            let mut decompressor = ValueDecompressor::new(f64::from_bits(bits[0]));
+            for &b in &bits[1..] {
+                prop_assert_eq!(decompressor.decompress(&mut reader).unwrap().to_bits(), b);
+            }
+        }
+    }
+}
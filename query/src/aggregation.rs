@@ -1,5 +1,7 @@
-use tsdb_core::DataPoint;
+use tsdb_core::{DataPoint, Histogram, TimestampPrecision, Value, ValueType};
+use compression::{kernels, PointColumns};
 use crate::error::QueryError;
+use std::ops::Range;
 
 #[derive(Debug, Clone)]
 pub enum Aggregation {
@@ -11,83 +13,235 @@ pub enum Aggregation {
     First,
     Last,
     StdDev,
+    /// Merges the histograms of each window into one.
+    Merge,
+    /// The given quantile, in `[0, 1]`, of the merged histograms of each
+    /// window.
+    Quantile(f64),
+    /// Per-second increase of a counter over each window, with seconds
+    /// counted in the precision of the timestamps. A drop in value is taken
+    /// as a reset to zero.
+    Rate,
 }
 
+impl Aggregation {
+    /// Whether the aggregation only makes sense over counters.
+    pub fn needs_counter(&self) -> bool {
+        matches!(self, Aggregation::Rate)
+    }
+    
+    /// Whether the aggregation is defined for values of `value_type`.
+    pub fn accepts(&self, value_type: ValueType) -> bool {
+        match self {
+            Aggregation::Count | Aggregation::First | Aggregation::Last => true,
+            Aggregation::Merge | Aggregation::Quantile(_) => value_type == ValueType::Histogram,
+            _ => value_type.is_numeric(),
+        }
+    }
+}
+
+/// One window of an aggregated series. `First` and `Last` keep the type of
+/// the series and `Merge` yields a histogram; every other aggregation yields
+/// a float.
 #[derive(Debug, Clone)]
 pub struct AggregatedPoint {
     pub timestamp: u64,
-    pub value: f64,
+    pub value: Value,
     pub count: usize,
 }
 
 impl AggregatedPoint {
-    pub fn new(timestamp: u64, value: f64, count: usize) -> Self {
-        Self { timestamp, value, count }
+    pub fn new(timestamp: u64, value: impl Into<Value>, count: usize) -> Self {
+        Self { timestamp, value: value.into(), count }
     }
 }
 
+/// Aggregates `points` into fixed windows of `window_size` starting at the
+/// first point. Points are taken in timestamp order; see
+/// [`aggregate_columns`].
+///
+/// All points must hold values of one type. `Count`, `First` and `Last` work
+/// on any type, `Merge` and `Quantile` need histograms, and the other
+/// aggregations need floats or integers; anything else fails with
+/// [`QueryError::AggregationError`].
+///
+/// Timestamps and `window_size` are taken to be milliseconds; see
+/// [`aggregate_points_with_precision`] for other units.
 pub fn aggregate_points(
     points: &[DataPoint],
     aggregation: Aggregation,
     window_size: u64,
 ) -> Result<Vec<AggregatedPoint>, QueryError> {
-    if points.is_empty() {
-        return Ok(Vec::new());
+    aggregate_points_with_precision(points, aggregation, window_size, TimestampPrecision::Milliseconds)
+}
+
+/// Like [`aggregate_points`], with timestamps and `window_size` counted in
+/// `precision`.
+pub fn aggregate_points_with_precision(
+    points: &[DataPoint],
+    aggregation: Aggregation,
+    window_size: u64,
+    precision: TimestampPrecision,
+) -> Result<Vec<AggregatedPoint>, QueryError> {
+    let value_type = match points.first() {
+        Some(point) => point.value_type(),
+        None => return Ok(Vec::new()),
+    };
+    if let Some(point) = points.iter().find(|p| p.value_type() != value_type) {
+        return Err(QueryError::AggregationError(format!(
+            "cannot aggregate {} and {} values together", value_type, point.value_type()
+        )));
+    }
+    if !aggregation.accepts(value_type) {
+        return Err(QueryError::AggregationError(format!(
+            "{:?} is not defined for {} values", aggregation, value_type
+        )));
+    }
+    if let Aggregation::Quantile(q) = aggregation {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(QueryError::InvalidQuery(format!("quantile {} is not in [0, 1]", q)));
+        }
     }
     
-    let mut result = Vec::new();
-    let start_time = points[0].timestamp;
-    let end_time = points.last().unwrap().timestamp;
-    
-    let mut current_window_start = start_time;
+    let mut sorted = Vec::new();
+    let points = if points.is_sorted_by_key(|p| p.timestamp) {
+        points
+    } else {
+        sorted.extend_from_slice(points);
+        sorted.sort_by_key(|p| p.timestamp);
+        &sorted
+    };
     
-    while current_window_start <= end_time {
-        let window_end = current_window_start + window_size;
-        
-        let window_points: Vec<&DataPoint> = points
-            .iter()
-            .filter(|p| p.timestamp >= current_window_start && p.timestamp < window_end)
-            .collect();
-        
-        if !window_points.is_empty() {
-            let aggregated_value = match aggregation {
-                Aggregation::Sum => window_points.iter().map(|p| p.value).sum(),
-                Aggregation::Avg => {
-                    let sum: f64 = window_points.iter().map(|p| p.value).sum();
-                    sum / window_points.len() as f64
-                },
-                Aggregation::Min => window_points.iter()
-                    .map(|p| p.value)
-                    .fold(f64::INFINITY, f64::min),
-                Aggregation::Max => window_points.iter()
-                    .map(|p| p.value)
-                    .fold(f64::NEG_INFINITY, f64::max),
-                Aggregation::Count => window_points.len() as f64,
-                Aggregation::First => window_points[0].value,
-                Aggregation::Last => window_points.last().unwrap().value,
-                Aggregation::StdDev => {
-                    let mean: f64 = window_points.iter().map(|p| p.value).sum::<f64>() 
-                        / window_points.len() as f64;
-                    let variance: f64 = window_points.iter()
-                        .map(|p| (p.value - mean).powi(2))
-                        .sum::<f64>() / window_points.len() as f64;
-                    variance.sqrt()
-                },
+    if matches!(
+        aggregation,
+        Aggregation::Count | Aggregation::First | Aggregation::Last | Aggregation::Merge | Aggregation::Quantile(_)
+    ) {
+        let timestamps: Vec<u64> = points.iter().map(|p| p.timestamp).collect();
+        let mut result = Vec::new();
+        for_each_window(&timestamps, window_size, |window_start, range| {
+            let window = &points[range];
+            let value = match aggregation {
+                Aggregation::First => window[0].value.clone(),
+                Aggregation::Last => window[window.len() - 1].value.clone(),
+                Aggregation::Merge => Value::from(merge_histograms(window)),
+                Aggregation::Quantile(q) => Value::Float(merge_histograms(window).quantile(q).unwrap_or(f64::NAN)),
+                _ => Value::Float(window.len() as f64),
             };
-            
-            result.push(AggregatedPoint::new(
-                current_window_start,
-                aggregated_value,
-                window_points.len(),
-            ));
-        }
-        
-        current_window_start = window_end;
+            result.push(AggregatedPoint::new(window_start, value, window.len()));
+        })?;
+        return Ok(result);
     }
     
+    let columns = PointColumns::from_points(points);
+    aggregate_columns_with_precision(&columns.timestamps, &columns.values, aggregation, window_size, precision)
+}
+
+/// Aggregates parallel timestamp and value columns into fixed windows of
+/// `window_size` starting at the first timestamp. `timestamps` must be
+/// sorted. Each window is a contiguous slice of `values`, so sums, minima
+/// and maxima run on the vectorised kernels in [`compression::kernels`].
+/// Windows without points are skipped.
+///
+/// Timestamps and `window_size` are taken to be milliseconds; see
+/// [`aggregate_columns_with_precision`] for other units.
+pub fn aggregate_columns(
+    timestamps: &[u64],
+    values: &[f64],
+    aggregation: Aggregation,
+    window_size: u64,
+) -> Result<Vec<AggregatedPoint>, QueryError> {
+    aggregate_columns_with_precision(timestamps, values, aggregation, window_size, TimestampPrecision::Milliseconds)
+}
+
+/// Like [`aggregate_columns`], with timestamps and `window_size` counted in
+/// `precision`.
+pub fn aggregate_columns_with_precision(
+    timestamps: &[u64],
+    values: &[f64],
+    aggregation: Aggregation,
+    window_size: u64,
+    precision: TimestampPrecision,
+) -> Result<Vec<AggregatedPoint>, QueryError> {
+    if timestamps.len() != values.len() {
+        return Err(QueryError::AggregationError(format!(
+            "{} timestamps but {} values", timestamps.len(), values.len()
+        )));
+    }
+    if !aggregation.accepts(ValueType::Float) {
+        return Err(QueryError::AggregationError(format!("{:?} is not defined for float values", aggregation)));
+    }
+    
+    let mut result = Vec::new();
+    for_each_window(timestamps, window_size, |window_start, range| {
+        let window_timestamps = &timestamps[range.clone()];
+        let window = &values[range];
+        let aggregated_value = match aggregation {
+            Aggregation::Sum => kernels::sum(window),
+            Aggregation::Avg => kernels::sum(window) / window.len() as f64,
+            Aggregation::Min => kernels::min(window).unwrap_or(f64::INFINITY),
+            Aggregation::Max => kernels::max(window).unwrap_or(f64::NEG_INFINITY),
+            Aggregation::Count => window.len() as f64,
+            Aggregation::First => window[0],
+            Aggregation::Last => window[window.len() - 1],
+            Aggregation::StdDev => kernels::std_dev(window).unwrap_or(f64::NAN),
+            Aggregation::Rate => {
+                let increase: f64 = window.windows(2)
+                    .map(|pair| if pair[1] >= pair[0] { pair[1] - pair[0] } else { pair[1] })
+                    .sum();
+                let elapsed = window_timestamps[window.len() - 1] - window_timestamps[0];
+                if elapsed == 0 { f64::NAN } else { increase * precision.units_per_second() as f64 / elapsed as f64 }
+            },
+            Aggregation::Merge | Aggregation::Quantile(_) => unreachable!("rejected above"),
+        };
+        
+        result.push(AggregatedPoint::new(window_start, aggregated_value, window.len()));
+    })?;
+    
     Ok(result)
 }
 
+/// Merges the histograms among `points` into one. Points holding other
+/// types are skipped. The result has the scale of the coarsest histogram, so
+/// it can be merged again with results over other windows or series.
+pub fn merge_histograms(points: &[DataPoint]) -> Histogram {
+    let mut histograms = points.iter().filter_map(|p| p.value.as_histogram());
+    let mut merged = histograms.next().cloned().unwrap_or_default();
+    for histogram in histograms {
+        merged.merge(histogram);
+    }
+    merged
+}
+
+/// Calls `f` with the start and index range of every non-empty window of
+/// `window_size` over the sorted `timestamps`, starting at the first one.
+fn for_each_window(
+    timestamps: &[u64],
+    window_size: u64,
+    mut f: impl FnMut(u64, Range<usize>),
+) -> Result<(), QueryError> {
+    if timestamps.is_empty() {
+        return Ok(());
+    }
+    if window_size == 0 {
+        return Err(QueryError::InvalidQuery("window_size must be positive".to_string()));
+    }
+    
+    let start_time = timestamps[0];
+    let mut position = 0;
+    
+    while position < timestamps.len() {
+        // Jump straight to the window holding the next point.
+        let window_index = (timestamps[position] - start_time) / window_size;
+        let window_start = start_time + window_index * window_size;
+        let window_end = window_start.saturating_add(window_size);
+        
+        let range = kernels::time_range(&timestamps[position..], window_start, window_end);
+        f(window_start, position + range.start..position + range.end);
+        position += range.end;
+    }
+    Ok(())
+}
+
 pub fn downsample_points(
     points: &[DataPoint],
     max_points: usize,
@@ -199,4 +353,148 @@ mod tests {
         assert_eq!(result[1].timestamp, 1200); // index 2  
         assert_eq!(result[2].timestamp, 1400); // index 4
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_aggregate_unsorted_points() {
+        let mut points = create_test_points();
+        points.reverse();
+        let result = aggregate_points(&points, Aggregation::First, 200).unwrap();
+        
+        assert_eq!(result[0].timestamp, 1000);
+        assert_eq!(result[0].value, 10.0);
+    }
+
+    #[test]
+    fn test_aggregate_columns_skips_empty_windows() {
+        let timestamps = [0, 10, 1_000_000, 1_000_005];
+        let values = [1.0, 2.0, 3.0, 4.0];
+        let result = aggregate_columns(&timestamps, &values, Aggregation::Sum, 100).unwrap();
+        
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[0].timestamp, result[0].value.clone(), result[0].count), (0, Value::Float(3.0), 2));
+        assert_eq!((result[1].timestamp, result[1].value.clone(), result[1].count), (1_000_000, Value::Float(7.0), 2));
+    }
+
+    #[test]
+    fn test_aggregate_columns_matches_points() {
+        let points: Vec<DataPoint> = (0..10_000u64)
+            .map(|i| DataPoint::new(i * 7, (i as f64 * 0.01).cos()))
+            .collect();
+        let columns = PointColumns::from_points(&points);
+        
+        for aggregation in [Aggregation::Min, Aggregation::Max, Aggregation::Count, Aggregation::Last] {
+            let from_points = aggregate_points(&points, aggregation.clone(), 1000).unwrap();
+            let from_columns = aggregate_columns(&columns.timestamps, &columns.values, aggregation, 1000).unwrap();
+            assert_eq!(from_points.len(), from_columns.len());
+            for (a, b) in from_points.iter().zip(&from_columns) {
+                assert_eq!((a.timestamp, &a.value, a.count), (b.timestamp, &b.value, b.count));
+            }
+        }
+    }
+
+    #[test]
+    fn test_aggregate_columns_rejects_bad_input() {
+        assert!(matches!(
+            aggregate_columns(&[1, 2], &[1.0], Aggregation::Sum, 10),
+            Err(QueryError::AggregationError(_))
+        ));
+        assert!(matches!(
+            aggregate_columns(&[1, 2], &[1.0, 2.0], Aggregation::Sum, 0),
+            Err(QueryError::InvalidQuery(_))
+        ));
+    }
+
+    #[test]
+    fn test_aggregate_integer_values() {
+        let points: Vec<DataPoint> = (0..6).map(|i| DataPoint::new(1000 + i * 100, i as i64 * 10)).collect();
+        
+        let sum = aggregate_points(&points, Aggregation::Sum, 300).unwrap();
+        assert_eq!(sum[0].value, 30.0);
+        assert_eq!(sum[1].value, 120.0);
+        
+        let last = aggregate_points(&points, Aggregation::Last, 300).unwrap();
+        assert_eq!(last[1].value, Value::Integer(50));
+    }
+
+    #[test]
+    fn test_aggregate_non_numeric_values() {
+        let states: Vec<DataPoint> = ["ok", "ok", "degraded", "down"].iter()
+            .enumerate()
+            .map(|(i, state)| DataPoint::new(1000 + i as u64 * 100, *state))
+            .collect();
+        
+        let first = aggregate_points(&states, Aggregation::First, 200).unwrap();
+        assert_eq!(first.iter().map(|p| p.value.clone()).collect::<Vec<_>>(), vec![Value::from("ok"), Value::from("degraded")]);
+        let count = aggregate_points(&states, Aggregation::Count, 1000).unwrap();
+        assert_eq!(count[0].value, 4.0);
+        
+        for aggregation in [Aggregation::Sum, Aggregation::Avg, Aggregation::Min, Aggregation::Max, Aggregation::StdDev] {
+            assert!(matches!(
+                aggregate_points(&states, aggregation, 200),
+                Err(QueryError::AggregationError(_))
+            ));
+        }
+        
+        let flags = vec![DataPoint::new(1000, true), DataPoint::new(1100, false)];
+        assert!(matches!(aggregate_points(&flags, Aggregation::Max, 200), Err(QueryError::AggregationError(_))));
+    }
+
+    #[test]
+    fn test_aggregate_rate() {
+        // A counter growing by 10 a second that resets at 4s.
+        let values = [0.0, 10.0, 20.0, 30.0, 5.0, 15.0];
+        let points: Vec<DataPoint> = values.iter()
+            .enumerate()
+            .map(|(i, &value)| DataPoint::new(i as u64 * 1000, value))
+            .collect();
+        
+        let rate = aggregate_points(&points, Aggregation::Rate, 6000).unwrap();
+        assert_eq!(rate[0].value, (30.0 + 5.0 + 10.0) / 5.0);
+        
+        let single = aggregate_points(&points[..1], Aggregation::Rate, 1000).unwrap();
+        assert!(single[0].value.as_f64().unwrap().is_nan());
+        
+        // The same counter with timestamps in nanoseconds.
+        let nanos: Vec<DataPoint> = points.iter()
+            .map(|p| DataPoint::new(p.timestamp * 1_000_000, p.value.clone()))
+            .collect();
+        let rate = aggregate_points_with_precision(&nanos, Aggregation::Rate, 6_000_000_000, TimestampPrecision::Nanoseconds).unwrap();
+        assert_eq!(rate.len(), 1);
+        assert_eq!(rate[0].value, (30.0 + 5.0 + 10.0) / 5.0);
+    }
+
+    #[test]
+    fn test_aggregate_histograms() {
+        let points: Vec<DataPoint> = (0..4u64)
+            .map(|i| DataPoint::new(1000 + i * 100, Histogram::from_values((1..=100).map(|v| (v + i * 100) as f64))))
+            .collect();
+        
+        let merged = aggregate_points(&points, Aggregation::Merge, 200).unwrap();
+        assert_eq!(merged.len(), 2);
+        let first = merged[0].value.as_histogram().unwrap();
+        assert_eq!(first.count(), 200);
+        assert_eq!((first.min, first.max), (1.0, 200.0));
+        assert_eq!(merge_histograms(&points).count(), 400);
+        
+        let median = aggregate_points(&points, Aggregation::Quantile(0.5), 1000).unwrap();
+        let estimate = median[0].value.as_f64().unwrap();
+        assert!((estimate - 200.0).abs() / 200.0 < 0.05, "median = {}", estimate);
+        let last = aggregate_points(&points, Aggregation::Last, 1000).unwrap();
+        assert_eq!(last[0].value, points[3].value);
+        
+        assert!(matches!(aggregate_points(&points, Aggregation::Quantile(1.5), 200), Err(QueryError::InvalidQuery(_))));
+        assert!(matches!(aggregate_points(&points, Aggregation::Avg, 200), Err(QueryError::AggregationError(_))));
+        let floats = vec![DataPoint::new(1000, 1.0)];
+        assert!(matches!(aggregate_points(&floats, Aggregation::Merge, 200), Err(QueryError::AggregationError(_))));
+        assert!(matches!(aggregate_columns(&[1000], &[1.0], Aggregation::Quantile(0.5), 200), Err(QueryError::AggregationError(_))));
+    }
+
+    #[test]
+    fn test_aggregate_mixed_types() {
+        let points = vec![DataPoint::new(1000, 1.0), DataPoint::new(1100, 2i64)];
+        assert!(matches!(
+            aggregate_points(&points, Aggregation::Count, 200),
+            Err(QueryError::AggregationError(_))
+        ));
+    }
+}
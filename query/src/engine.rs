@@ -1,12 +1,14 @@
-use tsdb_core::{TimeSeriesKey, DataPoint};
+use tsdb_core::{DataPoint, LabelMatcher, SeriesId, SeriesKey, SeriesMetadata, TimestampPrecision, ValueType};
 use storage::TSMap;
 use crate::error::QueryError;
-use crate::aggregation::{Aggregation, AggregatedPoint, aggregate_points, downsample_points};
+use crate::aggregation::{Aggregation, AggregatedPoint, aggregate_columns_with_precision, aggregate_points_with_precision, downsample_points};
 use std::sync::Arc;
 
+/// A read of one series. Times and the window size are in the series'
+/// timestamp precision; see [`QueryEngine::precision`].
 #[derive(Debug, Clone)]
 pub struct Query {
-    pub key: TimeSeriesKey,
+    pub series: SeriesId,
     pub start_time: u64,
     pub end_time: u64,
     pub aggregation: Option<Aggregation>,
@@ -15,9 +17,11 @@ pub struct Query {
 }
 
 impl Query {
-    pub fn new(key: TimeSeriesKey, start_time: u64, end_time: u64) -> Self {
+    /// A query over `series`, given as a [`SeriesKey`], its id, or the name
+    /// of a label-less series.
+    pub fn new(series: impl Into<SeriesId>, start_time: u64, end_time: u64) -> Self {
         Self {
-            key,
+            series: series.into(),
             start_time,
             end_time,
             aggregation: None,
@@ -38,20 +42,75 @@ impl Query {
     }
     
     pub fn validate(&self) -> Result<(), QueryError> {
-        if self.start_time > self.end_time {
-            return Err(QueryError::InvalidTimeRange(self.start_time, self.end_time));
+        validate(self.start_time, self.end_time, self.aggregation.as_ref(), self.window_size)
+    }
+}
+
+/// A read of several series at once: a [`Query`] without the series, which
+/// are given alongside it, as to [`QueryEngine::execute_merged`], or picked
+/// by a selector, as by [`QueryEngine::execute_selector`].
+#[derive(Debug, Clone)]
+pub struct MultiSeriesQuery {
+    pub start_time: u64,
+    pub end_time: u64,
+    pub aggregation: Option<Aggregation>,
+    pub window_size: Option<u64>,
+    pub max_points: Option<usize>,
+}
+
+impl MultiSeriesQuery {
+    pub fn new(start_time: u64, end_time: u64) -> Self {
+        Self {
+            start_time,
+            end_time,
+            aggregation: None,
+            window_size: None,
+            max_points: None,
         }
-        
-        if self.aggregation.is_some() && self.window_size.is_none() {
-            return Err(QueryError::InvalidQuery(
-                "Aggregation requires window_size".to_string()
-            ));
+    }
+    
+    pub fn with_aggregation(mut self, aggregation: Aggregation, window_size: u64) -> Self {
+        self.aggregation = Some(aggregation);
+        self.window_size = Some(window_size);
+        self
+    }
+    
+    pub fn with_max_points(mut self, max_points: usize) -> Self {
+        self.max_points = Some(max_points);
+        self
+    }
+    
+    pub fn validate(&self) -> Result<(), QueryError> {
+        validate(self.start_time, self.end_time, self.aggregation.as_ref(), self.window_size)
+    }
+    
+    /// The same read of `series` alone.
+    pub fn for_series(&self, series: impl Into<SeriesId>) -> Query {
+        Query {
+            series: series.into(),
+            start_time: self.start_time,
+            end_time: self.end_time,
+            aggregation: self.aggregation.clone(),
+            window_size: self.window_size,
+            max_points: self.max_points,
         }
-        
-        Ok(())
     }
 }
 
+fn validate(start_time: u64, end_time: u64, aggregation: Option<&Aggregation>, window_size: Option<u64>) -> Result<(), QueryError> {
+    if start_time > end_time {
+        return Err(QueryError::InvalidTimeRange(start_time, end_time));
+    }
+    
+    if aggregation.is_some() && window_size.is_none() {
+        return Err(QueryError::InvalidQuery(
+            "Aggregation requires window_size".to_string()
+        ));
+    }
+    
+    Ok(())
+}
+
 #[derive(Debug, Clone)]
 pub enum QueryResult {
     Points(Vec<DataPoint>),
@@ -83,26 +142,133 @@ impl QueryEngine {
     pub fn execute(&self, query: Query) -> Result<QueryResult, QueryError> {
         query.validate()?;
         
-        let mut points = self.storage.scan_range(&query.key, query.start_time, query.end_time)?;
+        self.check_metric_type(query.series, query.aggregation.as_ref())?;
+        let precision = self.precision(query.series);
+        
+        // Aggregations over floats run on the kernels straight from the
+        // decoded columns; downsampling still needs whole points.
+        let is_float = self.storage.value_type(query.series) == Some(ValueType::Float);
+        if let (Some(aggregation), Some(window_size)) = (&query.aggregation, query.window_size) {
+            if is_float && query.max_points.is_none() {
+                let columns = self.storage.scan_columns(query.series, query.start_time, query.end_time)?;
+                if columns.is_empty() {
+                    return Ok(QueryResult::Points(Vec::new()));
+                }
+                let aggregated = aggregate_columns_with_precision(&columns.timestamps, &columns.values, aggregation.clone(), window_size, precision)?;
+                return Ok(QueryResult::Aggregated(aggregated));
+            }
+        }
         
+        let points = self.storage.scan_range(query.series, query.start_time, query.end_time)?;
+        self.finish(points, query.max_points, query.aggregation.zip(query.window_size), precision)
+    }
+    
+    /// Runs `query` over the points of all of `series` together, as if they
+    /// were one series. With [`Aggregation::Merge`] or
+    /// [`Aggregation::Quantile`] this combines histogram series, such as the
+    /// latencies of every host, into one distribution per window.
+    ///
+    /// The series must share a timestamp precision, since their points are
+    /// put on one time axis.
+    pub fn execute_merged<S: Into<SeriesId>>(&self, series: impl IntoIterator<Item = S>, query: MultiSeriesQuery) -> Result<QueryResult, QueryError> {
+        query.validate()?;
+        
+        let mut points = Vec::new();
+        let mut precision = None;
+        for id in series {
+            let id = id.into();
+            self.check_metric_type(id, query.aggregation.as_ref())?;
+            let series_precision = self.precision(id);
+            match precision {
+                Some(expected) if expected != series_precision => {
+                    return Err(QueryError::InvalidQuery(format!(
+                        "cannot merge series in {} with series in {}", expected, series_precision
+                    )));
+                }
+                _ => precision = Some(series_precision),
+            }
+            points.extend(self.storage.scan_range(id, query.start_time, query.end_time)?);
+        }
+        points.sort_by_key(|p| p.timestamp);
+        self.finish(points, query.max_points, query.aggregation.zip(query.window_size), precision.unwrap_or_default())
+    }
+    
+    /// The unit `series` counts its timestamps in, which queries over it
+    /// use for their times and windows.
+    pub fn precision(&self, series: impl Into<SeriesId>) -> TimestampPrecision {
+        self.storage.config().precision_for(series)
+    }
+    
+    /// Refuses counter-only aggregations over series declared as something
+    /// other than a counter. Undeclared series are given the benefit of the
+    /// doubt.
+    fn check_metric_type(&self, series: SeriesId, aggregation: Option<&Aggregation>) -> Result<(), QueryError> {
+        let Some(aggregation) = aggregation.filter(|a| a.needs_counter()) else {
+            return Ok(());
+        };
+        match self.storage.metadata(series) {
+            Some(metadata) if !metadata.may_be_counter() => Err(QueryError::AggregationError(format!(
+                "{:?} needs a counter, series {} is a {}", aggregation, series, metadata.metric_type
+            ))),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Downsamples and aggregates the points a query read.
+    fn finish(
+        &self,
+        mut points: Vec<DataPoint>,
+        max_points: Option<usize>,
+        aggregation: Option<(Aggregation, u64)>,
+        precision: TimestampPrecision,
+    ) -> Result<QueryResult, QueryError> {
         if points.is_empty() {
             return Ok(QueryResult::Points(points));
         }
         
-        if let Some(max_points) = query.max_points {
+        if let Some(max_points) = max_points {
             points = downsample_points(&points, max_points);
         }
         
-        if let Some(aggregation) = query.aggregation {
-            let window_size = query.window_size.unwrap(); // validated above
-            let aggregated = aggregate_points(&points, aggregation, window_size)?;
+        if let Some((aggregation, window_size)) = aggregation {
+            let aggregated = aggregate_points_with_precision(&points, aggregation, window_size, precision)?;
             Ok(QueryResult::Aggregated(aggregated))
         } else {
             Ok(QueryResult::Points(points))
         }
     }
     
-    pub fn list_keys(&self) -> Vec<TimeSeriesKey> {
+    /// The series matching `selector`, e.g. `{job="api", status=~"5.."}`,
+    /// looked up in the storage's label index.
+    pub fn select_series(&self, selector: &str) -> Result<Vec<SeriesId>, QueryError> {
+        let matchers = LabelMatcher::parse_selector(selector)
+            .map_err(|e| QueryError::InvalidQuery(e.to_string()))?;
+        Ok(self.storage.select(&matchers))
+    }
+    
+    /// Runs `query` over each series matching `selector` in turn.
+    pub fn execute_selector(&self, selector: &str, query: MultiSeriesQuery) -> Result<Vec<(SeriesId, QueryResult)>, QueryError> {
+        let queries = self.select_series(selector)?
+            .into_iter()
+            .map(|series| query.for_series(series))
+            .collect();
+        self.execute_multi(queries)
+    }
+    
+    /// The declared type, unit and help text of a series.
+    pub fn series_metadata(&self, series: impl Into<SeriesId>) -> Option<SeriesMetadata> {
+        self.storage.metadata(series)
+    }
+    
+    /// The key and metadata of every series matching `selector`.
+    pub fn metadata(&self, selector: &str) -> Result<Vec<(SeriesKey, SeriesMetadata)>, QueryError> {
+        Ok(self.select_series(selector)?
+            .into_iter()
+            .filter_map(|id| Some((self.storage.series_key(id)?, self.storage.metadata(id)?)))
+            .collect())
+    }
+    
+    pub fn list_keys(&self) -> Vec<SeriesKey> {
         self.storage.keys()
     }
     
@@ -110,13 +276,13 @@ impl QueryEngine {
         self.storage.get_stats()
     }
     
-    pub fn execute_multi(&self, queries: Vec<Query>) -> Result<Vec<(TimeSeriesKey, QueryResult)>, QueryError> {
+    pub fn execute_multi(&self, queries: Vec<Query>) -> Result<Vec<(SeriesId, QueryResult)>, QueryError> {
         let mut results = Vec::new();
         
         for query in queries {
-            let key = query.key.clone();
+            let series = query.series;
             let result = self.execute(query)?;
-            results.push((key, result));
+            results.push((series, result));
         }
         
         Ok(results)
@@ -127,6 +293,7 @@ impl QueryEngine {
 mod tests {
     use super::*;
     use storage::TSMap;
+    use tsdb_core::{Histogram, MetricType};
 
     fn setup_test_data() -> Arc<TSMap> {
         let storage = Arc::new(TSMap::new());
@@ -143,7 +310,7 @@ mod tests {
     #[test]
     fn test_query_creation() {
         let query = Query::new("test".to_string(), 1000, 2000);
-        assert_eq!(query.key, "test");
+        assert_eq!(query.series, SeriesKey::new("test").id());
         assert_eq!(query.start_time, 1000);
         assert_eq!(query.end_time, 2000);
         assert!(query.aggregation.is_none());
@@ -204,6 +371,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_query_engine_float_aggregation_matches_points() {
+        let storage = setup_test_data();
+        let points = storage.scan_range("test.metric", 1000, 1900).unwrap();
+        let engine = QueryEngine::new(storage);
+        
+        let aggregations = [
+            Aggregation::Sum, Aggregation::Avg, Aggregation::Min, Aggregation::Max, Aggregation::Count,
+            Aggregation::First, Aggregation::Last, Aggregation::StdDev, Aggregation::Rate,
+        ];
+        for aggregation in aggregations {
+            let query = Query::new("test.metric", 1000, 1900).with_aggregation(aggregation.clone(), 400);
+            let QueryResult::Aggregated(result) = engine.execute(query).unwrap() else {
+                panic!("Expected Aggregated result");
+            };
+            let expected = crate::aggregation::aggregate_points(&points, aggregation, 400).unwrap();
+            
+            let summary = |points: &[AggregatedPoint]| -> Vec<_> {
+                points.iter().map(|p| (p.timestamp, p.count, p.value.as_f64().map(f64::to_bits))).collect()
+            };
+            assert_eq!(summary(&result), summary(&expected));
+        }
+    }
+
     #[test]
     fn test_query_engine_execute_with_max_points() {
         let storage = setup_test_data();
@@ -221,6 +412,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_query_engine_execute_historical_blocks() {
+        let storage = Arc::new(TSMap::new());
+        let key = "test.metric".to_string();
+        let minute = 60 * 1000;
+        
+        // Four hours of one-minute data: the first two hours end up sealed.
+        for i in 0..240 {
+            storage.insert(key.clone(), DataPoint::new(i * minute, 1.0)).unwrap();
+        }
+        
+        let engine = QueryEngine::new(storage);
+        let query = Query::new(key, 0, 60 * minute - 1)
+            .with_aggregation(Aggregation::Count, 60 * minute);
+        
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregated(points) => {
+                assert_eq!(points.len(), 1);
+                assert_eq!(points[0].count, 60);
+            },
+            _ => panic!("Expected Aggregated result"),
+        }
+    }
+
+    #[test]
+    fn test_query_engine_nanosecond_series() {
+        let config = storage::StorageConfig::default()
+            .with_series_precision("trades", TimestampPrecision::Nanoseconds);
+        let storage = Arc::new(TSMap::with_config(config));
+        let second = 1_000_000_000u64;
+        
+        // A counter rising by 5 a second for three hours, in nanoseconds.
+        // One block spans two hours of nanoseconds, not two hours of
+        // milliseconds.
+        for i in 0..3 * 3600 / 10 {
+            storage.insert("trades", DataPoint::new(i * 10 * second, (i * 50) as f64)).unwrap();
+            storage.insert("ticks", DataPoint::new(i * 10_000, i as f64)).unwrap();
+        }
+        assert_eq!(storage.get_stats().series[&SeriesKey::new("trades")].block_count, 2);
+        
+        let engine = QueryEngine::new(storage);
+        assert_eq!(engine.precision("trades"), TimestampPrecision::Nanoseconds);
+        let query = Query::new("trades", 0, 3600 * second - 1).with_aggregation(Aggregation::Rate, 600 * second);
+        match engine.execute(query).unwrap() {
+            QueryResult::Aggregated(points) => {
+                assert_eq!(points.len(), 6);
+                assert!(points.iter().all(|p| p.value == 5.0));
+            },
+            _ => panic!("Expected Aggregated result"),
+        }
+        
+        let query = MultiSeriesQuery::new(0, 3600 * second - 1).with_aggregation(Aggregation::Rate, 600 * second);
+        assert!(matches!(engine.execute_merged(["trades", "ticks"], query), Err(QueryError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn test_query_engine_execute_merged_histograms() {
+        let storage = Arc::new(TSMap::new());
+        let keys: Vec<SeriesKey> = ["1", "2"].iter()
+            .map(|host| SeriesKey::new("latency").with_label("host", *host))
+            .collect();
+        
+        // Each host reports the latencies it saw every second: host1 is
+        // fast, host2 is ten times slower.
+        for (k, key) in keys.iter().enumerate() {
+            for i in 0..10u64 {
+                let scale = 10f64.powi(k as i32);
+                let histogram = Histogram::from_values((1..=10).map(|v| v as f64 * scale));
+                storage.insert(key.clone(), DataPoint::new(1000 + i * 1000, histogram)).unwrap();
+            }
+        }
+        
+        let engine = QueryEngine::new(storage);
+        let query = MultiSeriesQuery::new(0, 20_000).with_aggregation(Aggregation::Merge, 5000);
+        match engine.execute_merged(&keys, query).unwrap() {
+            QueryResult::Aggregated(points) => {
+                assert_eq!(points.len(), 2);
+                let merged = points[0].value.as_histogram().unwrap();
+                assert_eq!(merged.count(), 100);
+                assert_eq!((merged.min, merged.max), (1.0, 100.0));
+            },
+            _ => panic!("Expected Aggregated result"),
+        }
+        
+        let query = MultiSeriesQuery::new(0, 20_000).with_aggregation(Aggregation::Quantile(0.99), 20_000);
+        match engine.execute_merged(&keys, query).unwrap() {
+            QueryResult::Aggregated(points) => {
+                assert_eq!(points[0].count, 20);
+                let p99 = points[0].value.as_f64().unwrap();
+                assert!(p99 > 90.0 && p99 <= 100.0, "p99 = {}", p99);
+            },
+            _ => panic!("Expected Aggregated result"),
+        }
+    }
+
     #[test]
     fn test_query_engine_execute_nonexistent_key() {
         let storage = setup_test_data();
@@ -265,4 +551,60 @@ mod tests {
         let results = engine.execute_multi(queries).unwrap();
         assert_eq!(results.len(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_query_engine_select_series() {
+        let storage = Arc::new(TSMap::new());
+        let keys: Vec<SeriesKey> = [
+            r#"http_requests{job="api",status="200"}"#,
+            r#"http_requests{job="api",status="503"}"#,
+            r#"http_requests{job="api",status="500"}"#,
+            r#"http_requests{job="web",status="502"}"#,
+        ].iter().map(|key| SeriesKey::parse(key).unwrap()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            storage.insert(key.clone(), DataPoint::new(1000, i as f64)).unwrap();
+        }
+        
+        let engine = QueryEngine::new(storage);
+        let mut selected = engine.select_series(r#"{job="api", status=~"5.."}"#).unwrap();
+        selected.sort();
+        let mut expected = vec![keys[1].id(), keys[2].id()];
+        expected.sort();
+        assert_eq!(selected, expected);
+        
+        let query = MultiSeriesQuery::new(0, 2000).with_aggregation(Aggregation::Sum, 2000);
+        let results = engine.execute_selector(r#"http_requests{job="web"}"#, query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, keys[3].id());
+        
+        assert!(matches!(engine.select_series("{job="), Err(QueryError::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn test_query_engine_metadata() {
+        let storage = Arc::new(TSMap::new());
+        let counter = SeriesKey::parse(r#"rx_bytes{host="a"}"#).unwrap();
+        let gauge = SeriesKey::parse(r#"temperature{host="a"}"#).unwrap();
+        for i in 0..10 {
+            storage.insert(counter.clone(), DataPoint::new(i * 1000, (i * 100) as f64)).unwrap();
+            storage.insert(gauge.clone(), DataPoint::new(i * 1000, 20.0 + i as f64)).unwrap();
+            storage.insert("untyped", DataPoint::new(i * 1000, i as f64)).unwrap();
+        }
+        storage.set_metadata(counter.clone(), SeriesMetadata::new(MetricType::Counter).with_unit("bytes")).unwrap();
+        storage.set_metadata(gauge.clone(), SeriesMetadata::new(MetricType::Gauge).with_unit("celsius")).unwrap();
+        
+        let engine = QueryEngine::new(storage);
+        assert_eq!(engine.series_metadata(&gauge).unwrap().unit.as_deref(), Some("celsius"));
+        let mut listed = engine.metadata(r#"{host="a"}"#).unwrap();
+        listed.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(listed.iter().map(|(_, m)| m.metric_type).collect::<Vec<_>>(), vec![MetricType::Counter, MetricType::Gauge]);
+        
+        let rate = |query: Query| engine.execute(query.with_aggregation(Aggregation::Rate, 10_000));
+        match rate(Query::new(&counter, 0, 10_000)).unwrap() {
+            QueryResult::Aggregated(points) => assert_eq!(points[0].value, 100.0),
+            _ => panic!("Expected Aggregated result"),
+        }
+        assert!(rate(Query::new("untyped", 0, 10_000)).is_ok());
+        assert!(matches!(rate(Query::new(&gauge, 0, 10_000)), Err(QueryError::AggregationError(_))));
+    }
+}
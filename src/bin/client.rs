@@ -30,7 +30,7 @@ fn load_sample_data(storage: &TSMap) -> Result<(), Box<dyn std::error::Error>> {
     
     let base_time = SystemTime::now()
         .duration_since(UNIX_EPOCH)?
-        .as_millis() as u64 - 3600_000; // 1 hour ago
+        .as_millis() as u64 - 3_600_000; // 1 hour ago
     
     let metrics = vec![
         ("server.cpu.percent", generate_cpu_data(base_time)),
@@ -72,7 +72,6 @@ fn generate_cpu_data(base_time: u64) -> Vec<DataPoint> {
 
 fn generate_memory_data(base_time: u64) -> Vec<DataPoint> {
     let mut points = Vec::new();
-    let mut current_mem = 4_000_000_000.0; // 4GB baseline
     
     for i in 0..3600 {
         let timestamp = base_time + i * 1000;
@@ -81,7 +80,7 @@ fn generate_memory_data(base_time: u64) -> Vec<DataPoint> {
         let growth = i as f64 * 1000.0;
         let spike = if i % 300 == 0 { 500_000_000.0 } else { 0.0 };
         
-        current_mem = 4_000_000_000.0 + growth + spike;
+        let current_mem = 4_000_000_000.0 + growth + spike; // 4GB baseline
         points.push(DataPoint::new(timestamp, current_mem));
     }
     
@@ -157,7 +156,7 @@ fn demo_aggregation_queries(query_engine: &QueryEngine) -> Result<(), Box<dyn st
     for (name, agg) in aggregations {
         let query = Query::new(
             cpu_key.clone(),
-            now - 1800_000, // Last 30 minutes
+            now - 1_800_000, // Last 30 minutes
             now
         ).with_aggregation(agg, 300_000); // 5-minute windows
         
@@ -200,4 +199,19 @@ fn demo_compression_stats(storage: &TSMap) {
         let savings = 100.0 * (1.0 - (stats.total_compressed_size as f64 / uncompressed_estimate as f64));
         println!("Space savings: {:.1}%", savings);
     }
+    
+    let mut keys: Vec<_> = stats.series.keys().collect();
+    keys.sort();
+    for key in keys {
+        let breakdown = &stats.series[key].compression;
+        let timestamps = &breakdown.timestamps;
+        let values = &breakdown.values;
+        println!("\n{}: {:.2} bits/point", key, breakdown.bits_per_point());
+        println!("  timestamp bits: {}, value bits: {}", timestamps.bits, values.bits);
+        println!("  delta-of-delta buckets: 0={} 7={} 9={} 12={} 32={} 64={}",
+                 timestamps.zero, timestamps.bits_7, timestamps.bits_9,
+                 timestamps.bits_12, timestamps.bits_32, timestamps.bits_64);
+        println!("  values: {} repeats, {} reused window, {} new window",
+                 values.repeats, values.reused_window, values.new_window);
+    }
 }
\ No newline at end of file
@@ -34,7 +34,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 async fn simulate_data_ingestion(storage: Arc<TSMap>) {
     info!("Starting data ingestion simulation");
     
-    let keys = vec![
+    let keys = [
         "cpu.usage".to_string(),
         "memory.usage".to_string(),
         "disk.io".to_string(),
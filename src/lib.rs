@@ -1,4 +1,15 @@
+// The member crates share module names such as `error`, which the globs
+// leave ambiguous. The items in those modules still come through, as each
+// crate re-exports them at its root; the modules themselves are reached
+// through the crates, re-exported by name below, as in `storage::error`.
+#![allow(ambiguous_glob_reexports)]
+
 pub use tsdb_core::*;
 pub use compression::*;
 pub use storage::*;
-pub use query::*;
\ No newline at end of file
+pub use query::*;
+
+pub use tsdb_core;
+pub use compression;
+pub use storage;
+pub use query;
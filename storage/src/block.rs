@@ -1,28 +1,75 @@
-use tsdb_core::{DataPoint, CompressedBlock};
-use compression::{TimestampCompressor, ValueCompressor, BitWriter};
+use tsdb_core::{BlockFormat, DataPoint, CompressedBlock, DuplicatePolicy, OpenBlock, SeriesKey, TimestampPrecision, TsdbError, ValueType};
+use compression::{codec_for_type, compress_block_with_precision, BlockBuilder, BlockDecoder, Codec, GorillaCodec};
+use crate::config::StorageConfig;
 use crate::error::StorageError;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::Arc;
+use std::time::Duration;
 
-const BLOCK_DURATION_MS: u64 = 2 * 60 * 60 * 1000; // 2 hours in milliseconds
+/// How much time a block covers, whatever unit its timestamps are in.
+pub const BLOCK_DURATION: Duration = Duration::from_secs(2 * 60 * 60);
 
-#[derive(Debug, Clone)]
+/// The open block of a series. Points are compressed as they are added, so
+/// the block holds encoder state and a bit stream rather than the points
+/// themselves; [`points`](Self::points) decodes them back.
+#[derive(Debug)]
 pub struct TimeSeriesBlock {
     pub start_time: u64,
     pub end_time: u64,
-    pub points: Vec<DataPoint>,
+    precision: TimestampPrecision,
+    duplicate_policy: DuplicatePolicy,
+    /// The timestamps held, sorted, so duplicates are found without
+    /// decoding the stream. Not kept under
+    /// [`KeepAll`](DuplicatePolicy::KeepAll).
+    timestamps: Vec<u64>,
+    /// Points added under [`LastWriteWins`](DuplicatePolicy::LastWriteWins)
+    /// at a timestamp already held. Both stay in the stream; the later one
+    /// wins when the block is read or compressed.
+    replaced: usize,
+    builder: BlockBuilder,
     pub is_sealed: bool,
 }
 
 impl TimeSeriesBlock {
     pub fn new(start_time: u64) -> Self {
+        Self::with_restart_interval(start_time, 0)
+    }
+    
+    /// A block that writes a restart point every `restart_interval` points
+    /// as it goes, so [`compress_indexed`](Self::compress_indexed) with the
+    /// same interval does not have to re-encode.
+    pub fn with_restart_interval(start_time: u64, restart_interval: usize) -> Self {
+        Self::with_codec(start_time, Arc::new(GorillaCodec), restart_interval)
+    }
+    
+    /// A block whose points are encoded with `codec` as they are added, and
+    /// so must all hold values of its [`value_type`](Codec::value_type).
+    pub fn with_codec(start_time: u64, codec: Arc<dyn Codec>, restart_interval: usize) -> Self {
+        Self::with_precision(start_time, codec, restart_interval, TimestampPrecision::Milliseconds)
+    }
+    
+    /// A block whose timestamps are counted in `precision`. It spans
+    /// [`BLOCK_DURATION`] in that unit, and its timestamps are compressed
+    /// with buckets sized for it.
+    pub fn with_precision(start_time: u64, codec: Arc<dyn Codec>, restart_interval: usize, precision: TimestampPrecision) -> Self {
         Self {
             start_time,
-            end_time: start_time + BLOCK_DURATION_MS,
-            points: Vec::new(),
+            end_time: start_time.saturating_add(precision.from_duration(BLOCK_DURATION)),
+            precision,
+            duplicate_policy: DuplicatePolicy::default(),
+            timestamps: Vec::new(),
+            replaced: 0,
+            builder: BlockBuilder::with_precision(codec, restart_interval, precision),
             is_sealed: false,
         }
     }
     
+    /// Resolves points added at a timestamp the block already holds with
+    /// `policy` rather than keeping them all.
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+    
     pub fn can_accept(&self, timestamp: u64) -> bool {
         !self.is_sealed && timestamp >= self.start_time && timestamp < self.end_time
     }
@@ -36,72 +83,213 @@ impl TimeSeriesBlock {
             return Err(StorageError::InvalidTimeRange(point.timestamp, self.end_time));
         }
         
-        self.points.push(point);
+        let existing = match self.duplicate_policy {
+            DuplicatePolicy::KeepAll => None,
+            _ => Some(self.timestamps.binary_search(&point.timestamp)),
+        };
+        match (existing, self.duplicate_policy) {
+            (Some(Ok(_)), DuplicatePolicy::Reject) => return Err(TsdbError::DuplicateTimestamp(point.timestamp).into()),
+            (Some(Ok(_)), DuplicatePolicy::FirstWriteWins) => return Ok(()),
+            _ => {}
+        }
+        
+        self.builder.append(&point)?;
+        match existing {
+            Some(Ok(_)) => self.replaced += 1,
+            Some(Err(index)) => self.timestamps.insert(index, point.timestamp),
+            None => {}
+        }
         Ok(())
     }
     
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+    
+    pub fn len(&self) -> usize {
+        self.builder.len() - self.replaced
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.builder.is_empty()
+    }
+    
+    /// The type of value the block holds.
+    pub fn value_type(&self) -> ValueType {
+        self.builder.codec().value_type()
+    }
+    
+    /// The unit the block's timestamps are counted in.
+    pub fn precision(&self) -> TimestampPrecision {
+        self.precision
+    }
+    
+    /// The codec points are encoded with as they are added.
+    pub fn codec(&self) -> &Arc<dyn Codec> {
+        self.builder.codec()
+    }
+    
+    /// Whether every value is an exact integer; see
+    /// [`StorageConfig::codec_for_values`](crate::StorageConfig::codec_for_values).
+    pub fn is_integer_valued(&self) -> bool {
+        self.builder.is_integer_valued()
+    }
+    
+    /// Bytes the compressed points currently take.
+    pub fn compressed_size(&self) -> usize {
+        if self.is_empty() { 0 } else { self.builder.encoded_size() }
+    }
+    
+    /// Decodes the points in the order they were added. Once a point has
+    /// replaced another, they come back sorted by timestamp instead, with
+    /// only the later of the two.
+    pub fn points(&self) -> Result<Vec<DataPoint>, StorageError> {
+        let mut points = self.builder.points()?;
+        if self.replaced > 0 {
+            points.sort_by_key(|p| p.timestamp);
+            self.duplicate_policy.dedup(&mut points);
+        }
+        Ok(points)
+    }
+    
     pub fn seal(&mut self) {
         self.is_sealed = true;
     }
     
+    /// Compresses with the codec the block was opened with.
     pub fn compress(&self) -> Result<CompressedBlock, StorageError> {
-        if self.points.is_empty() {
+        self.compress_with(self.codec().as_ref())
+    }
+    
+    pub fn compress_with(&self, codec: &dyn Codec) -> Result<CompressedBlock, StorageError> {
+        self.compress_indexed(codec, 0)
+    }
+    
+    /// Compresses with a restart point every `restart_interval` points; see
+    /// [`compress_block_indexed`](compression::compress_block_indexed).
+    ///
+    /// When the points arrived in order, none replaced another, and the
+    /// block was opened with the same codec and interval, the stream built
+    /// so far already is the block and is used as is. Otherwise the points
+    /// are decoded, sorted and encoded again.
+    pub fn compress_indexed(&self, codec: &dyn Codec, restart_interval: usize) -> Result<CompressedBlock, StorageError> {
+        if self.is_empty() {
             return Ok(CompressedBlock {
                 start_timestamp: self.start_time,
                 end_timestamp: self.start_time,
                 count: 0,
+                value_type: self.value_type(),
                 compressed_data: Vec::new(),
             });
         }
         
-        let mut writer = BitWriter::new();
-        let first_point = &self.points[0];
-        
-        let mut timestamp_compressor = TimestampCompressor::new(first_point.timestamp);
-        let mut value_compressor = ValueCompressor::new(first_point.value);
-        
-        for (i, point) in self.points.iter().enumerate() {
-            if i > 0 {
-                timestamp_compressor.compress(point.timestamp, &mut writer)?;
-                value_compressor.compress(point.value, &mut writer)?;
-            }
+        if codec.id() == self.codec().id()
+            && restart_interval == self.builder.restart_interval()
+            && self.builder.is_sorted()
+            && self.replaced == 0
+        {
+            return Ok(self.builder.snapshot()?);
         }
         
-        let compressed_data = writer.finish();
-        
-        Ok(CompressedBlock {
-            start_timestamp: first_point.timestamp,
-            end_timestamp: self.points.last().unwrap().timestamp,
-            count: self.points.len(),
-            compressed_data,
-        })
+        // Points may have arrived out of order within the block; sealed blocks
+        // are always stored sorted so readers can stop early.
+        let mut points = self.points()?;
+        points.sort_by_key(|p| p.timestamp);
+        Ok(compress_block_with_precision(&points, codec, restart_interval, self.precision)?)
     }
     
+    /// Whether the block's time span has passed, reading the clock in the
+    /// block's precision.
     pub fn should_seal(&self) -> bool {
-        if self.is_sealed {
-            return false;
-        }
-        
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_millis() as u64;
-            
-        now >= self.end_time
+        !self.is_sealed && self.precision.now() >= self.end_time
+    }
+}
+
+impl OpenBlock for TimeSeriesBlock {
+    type Error = StorageError;
+    
+    fn start_time(&self) -> u64 {
+        self.start_time
+    }
+    
+    fn can_accept(&self, timestamp: u64) -> bool {
+        self.can_accept(timestamp)
+    }
+    
+    fn add_point(&mut self, point: DataPoint) -> Result<(), StorageError> {
+        self.add_point(point)
+    }
+    
+    fn len(&self) -> usize {
+        self.len()
+    }
+    
+    fn points(&self) -> Result<Vec<DataPoint>, StorageError> {
+        self.points()
+    }
+    
+    fn should_seal(&self) -> bool {
+        self.should_seal()
+    }
+}
+
+/// Storage's encoding of a series: blocks compressed as points arrive, in
+/// the series' precision, sealed with the codec the config picks for them.
+impl BlockFormat for StorageConfig {
+    type Block = TimeSeriesBlock;
+    
+    fn open_block(&self, key: &SeriesKey, first_point: &DataPoint) -> TimeSeriesBlock {
+        TimeSeriesBlock::with_precision(
+            first_point.timestamp,
+            codec_for_type(first_point.value_type()),
+            self.restart_interval,
+            self.precision_for(key),
+        )
+        .with_duplicate_policy(self.duplicate_policy_for(key))
+    }
+    
+    fn seal_block(&self, key: &SeriesKey, mut block: TimeSeriesBlock) -> Result<Option<CompressedBlock>, StorageError> {
+        block.seal();
+        // Only float series have a choice of codec. Anything other than
+        // the codec the block was built with, such as `IntegerCodec` for an
+        // integer-valued block, means encoding its points again.
+        let codec = match block.value_type() {
+            ValueType::Float => self.codec_for_values(key, block.is_integer_valued()),
+            _ => block.codec().clone(),
+        };
+        let compressed = block.compress_indexed(codec.as_ref(), self.restart_interval)?;
+        Ok(Some(compressed).filter(|compressed| compressed.count > 0))
+    }
+    
+    fn decode_block(&self, block: &CompressedBlock) -> Result<Vec<DataPoint>, StorageError> {
+        Ok(BlockDecoder::with_registry(block, &self.codecs)?.collect::<Result<_, _>>()?)
+    }
+    
+    fn is_corruption(&self, error: &StorageError) -> bool {
+        matches!(error, StorageError::CompressionError(e) if crate::memory::is_corruption(e))
+    }
+    
+    fn out_of_order_window(&self, key: &SeriesKey) -> u64 {
+        self.precision_for(key).from_duration(self.out_of_order_window)
+    }
+    
+    fn duplicate_policy(&self, key: &SeriesKey) -> DuplicatePolicy {
+        self.duplicate_policy_for(key)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use compression::compress_block_indexed;
 
     #[test]
     fn test_block_creation() {
         let block = TimeSeriesBlock::new(1000);
         assert_eq!(block.start_time, 1000);
-        assert_eq!(block.end_time, 1000 + BLOCK_DURATION_MS);
+        assert_eq!(block.end_time, 1000 + BLOCK_DURATION.as_millis() as u64);
         assert!(!block.is_sealed);
-        assert!(block.points.is_empty());
+        assert!(block.is_empty());
     }
 
     #[test]
@@ -110,9 +298,31 @@ mod tests {
         
         assert!(block.can_accept(1000));
         assert!(block.can_accept(1500));
-        assert!(block.can_accept(1000 + BLOCK_DURATION_MS - 1));
+        assert!(block.can_accept(1000 + BLOCK_DURATION.as_millis() as u64 - 1));
         assert!(!block.can_accept(999));
-        assert!(!block.can_accept(1000 + BLOCK_DURATION_MS));
+        assert!(!block.can_accept(1000 + BLOCK_DURATION.as_millis() as u64));
+    }
+
+    #[test]
+    fn test_block_precision() {
+        let now = TimestampPrecision::Nanoseconds.now();
+        let block = TimeSeriesBlock::with_precision(now, Arc::new(GorillaCodec), 0, TimestampPrecision::Nanoseconds);
+        assert_eq!(block.end_time - block.start_time, 7_200_000_000_000);
+        assert!(!block.should_seal());
+        
+        // Two hours ago in nanoseconds; read as milliseconds this would lie
+        // far in the future.
+        let old = TimeSeriesBlock::with_precision(now - 7_200_000_000_000, Arc::new(GorillaCodec), 0, TimestampPrecision::Nanoseconds);
+        assert!(old.should_seal());
+        
+        let mut block = TimeSeriesBlock::with_precision(1_000_000, Arc::new(GorillaCodec), 0, TimestampPrecision::Microseconds);
+        block.add_point(DataPoint::new(1_000_000, 1.0)).unwrap();
+        block.add_point(DataPoint::new(1_000_500, 2.0)).unwrap();
+        block.add_point(DataPoint::new(1_000_250, 3.0)).unwrap();
+        let compressed = block.compress().unwrap();
+        let header = compression::BlockHeader::read_from(&compressed.compressed_data).unwrap();
+        assert_eq!(header.precision, TimestampPrecision::Microseconds);
+        assert_eq!(compression::decompress_block(&compressed).unwrap().len(), 3);
     }
 
     #[test]
@@ -121,8 +331,8 @@ mod tests {
         let point = DataPoint::new(1500, 42.5);
         
         assert!(block.add_point(point.clone()).is_ok());
-        assert_eq!(block.points.len(), 1);
-        assert_eq!(block.points[0], point);
+        assert_eq!(block.len(), 1);
+        assert_eq!(block.points().unwrap(), vec![point]);
     }
 
     #[test]
@@ -131,7 +341,7 @@ mod tests {
         let point = DataPoint::new(999, 42.5);
         
         assert!(block.add_point(point).is_err());
-        assert!(block.points.is_empty());
+        assert!(block.is_empty());
     }
 
     #[test]
@@ -141,7 +351,7 @@ mod tests {
         let point = DataPoint::new(1500, 42.5);
         
         assert!(block.add_point(point).is_err());
-        assert!(block.points.is_empty());
+        assert!(block.is_empty());
     }
 
     #[test]
@@ -188,4 +398,109 @@ mod tests {
         assert_eq!(compressed.end_timestamp, 1700);
         assert!(!compressed.compressed_data.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_block_compress_sorts_points() {
+        let mut block = TimeSeriesBlock::new(1000);
+        block.add_point(DataPoint::new(1700, 3.0)).unwrap();
+        block.add_point(DataPoint::new(1500, 1.0)).unwrap();
+        block.add_point(DataPoint::new(1600, 2.0)).unwrap();
+        
+        let compressed = block.compress().unwrap();
+        assert_eq!(compressed.start_timestamp, 1500);
+        assert_eq!(compressed.end_timestamp, 1700);
+        
+        let points = compression::decompress_block(&compressed).unwrap();
+        let timestamps: Vec<u64> = points.iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, vec![1500, 1600, 1700]);
+    }
+
+    #[test]
+    fn test_block_reuses_stream_when_in_order() {
+        let points: Vec<DataPoint> = (0..100).map(|i| DataPoint::new(1000 + i * 10, i as f64 * 0.5)).collect();
+        let mut block = TimeSeriesBlock::with_restart_interval(1000, 16);
+        for point in &points {
+            block.add_point(point.clone()).unwrap();
+        }
+        
+        let expected = compress_block_indexed(&points, &GorillaCodec, 16).unwrap();
+        let compressed = block.compress_indexed(&GorillaCodec, 16).unwrap();
+        assert_eq!(compressed.compressed_data, expected.compressed_data);
+        assert_eq!(block.compressed_size(), expected.compressed_data.len());
+        
+        // A different interval or codec re-encodes the decoded points.
+        let reencoded = block.compress_indexed(&compression::ChimpCodec, 16).unwrap();
+        assert_eq!(compression::decompress_block(&reencoded).unwrap(), points);
+    }
+
+    #[test]
+    fn test_block_smaller_than_points() {
+        let mut block = TimeSeriesBlock::new(1000);
+        for i in 0..1000 {
+            block.add_point(DataPoint::new(1000 + i * 10, 20.0 + (i % 7) as f64)).unwrap();
+        }
+        
+        assert!(block.compressed_size() * 4 < 1000 * std::mem::size_of::<DataPoint>());
+    }
+
+    #[test]
+    fn test_block_typed_values() {
+        let mut block = TimeSeriesBlock::with_codec(1000, compression::codec_for_type(ValueType::String), 4);
+        let points: Vec<DataPoint> = (0..10u64)
+            .map(|i| DataPoint::new(1000 + i * 10, if i % 3 == 0 { "leader" } else { "follower" }))
+            .collect();
+        for point in &points {
+            block.add_point(point.clone()).unwrap();
+        }
+        
+        assert_eq!(block.value_type(), ValueType::String);
+        assert!(!block.is_integer_valued());
+        assert_eq!(block.points().unwrap(), points);
+        
+        let compressed = block.compress_indexed(block.codec().as_ref(), 4).unwrap();
+        assert_eq!(compressed.value_type, ValueType::String);
+        assert_eq!(compression::decompress_block(&compressed).unwrap(), points);
+        
+        assert!(matches!(
+            block.add_point(DataPoint::new(2000, 1.5)),
+            Err(StorageError::CompressionError(compression::CompressionError::TypeMismatch { .. }))
+        ));
+        assert_eq!(block.len(), 10);
+    }
+
+    #[test]
+    fn test_block_duplicate_policy() {
+        let block = |policy: DuplicatePolicy| {
+            let mut block = TimeSeriesBlock::new(1000).with_duplicate_policy(policy);
+            for (timestamp, value) in [(1000, 1.0), (1010, 2.0), (1020, 3.0)] {
+                block.add_point(DataPoint::new(timestamp, value)).unwrap();
+            }
+            block
+        };
+        
+        let mut reject = block(DuplicatePolicy::Reject);
+        assert!(matches!(
+            reject.add_point(DataPoint::new(1010, 9.0)),
+            Err(StorageError::Core(TsdbError::DuplicateTimestamp(1010)))
+        ));
+        reject.add_point(DataPoint::new(1015, 9.0)).unwrap();
+        assert_eq!(reject.len(), 4);
+        
+        let mut first = block(DuplicatePolicy::FirstWriteWins);
+        first.add_point(DataPoint::new(1010, 9.0)).unwrap();
+        assert_eq!(first.points().unwrap()[1], DataPoint::new(1010, 2.0));
+        assert_eq!(first.len(), 3);
+        
+        let mut last = block(DuplicatePolicy::LastWriteWins);
+        last.add_point(DataPoint::new(1010, 9.0)).unwrap();
+        last.add_point(DataPoint::new(1020, 8.0)).unwrap();
+        let points = last.points().unwrap();
+        assert_eq!(points, vec![DataPoint::new(1000, 1.0), DataPoint::new(1010, 9.0), DataPoint::new(1020, 8.0)]);
+        assert_eq!(last.len(), 3);
+        assert_eq!(compression::decompress_block(&last.compress().unwrap()).unwrap(), points);
+        
+        let mut all = block(DuplicatePolicy::KeepAll);
+        all.add_point(DataPoint::new(1010, 9.0)).unwrap();
+        assert_eq!(all.len(), 4);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::error::StorageError;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use tsdb_core::{CompressedBlock, DataPoint, SeriesKey, SeriesMetadata, ValueType};
+
+const CHECKPOINT_MAGIC: &[u8; 4] = b"TSCP";
+const CHECKPOINT_VERSION: u8 = 1;
+
+/// One series as stored in a checkpoint.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SeriesCheckpoint {
+    pub key: SeriesKey,
+    pub metadata: SeriesMetadata,
+    pub value_type: Option<ValueType>,
+    pub sealed_blocks: Vec<CompressedBlock>,
+    pub quarantined_blocks: Vec<CompressedBlock>,
+    /// Points of the open block and the out-of-order buffer, which are
+    /// rebuilt from them on load.
+    pub open_points: Vec<DataPoint>,
+}
+
+/// Writes `series` to a file next to `path`, then renames it over `path`
+/// and syncs the directory, so the rename itself survives a crash. The
+/// header records `wal_position`, the WAL position the series are up to.
+pub(crate) fn write_checkpoint(path: &Path, wal_position: u64, series: &[SeriesCheckpoint]) -> Result<(), StorageError> {
+    let temp_path = path.with_extension("tmp");
+    {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        writer.write_all(&[CHECKPOINT_VERSION])?;
+        writer.write_all(&wal_position.to_le_bytes())?;
+        bincode::serialize_into(&mut writer, series)
+            .map_err(|e| StorageError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("Serialization error: {}", e)
+            )))?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    }
+    fs::rename(&temp_path, path)?;
+    sync_parent_dir(path)
+}
+
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), StorageError> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(parent)?.sync_all()?;
+    Ok(())
+}
+
+/// Directories cannot be opened for syncing here; the rename is as durable
+/// as the platform makes it.
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), StorageError> {
+    Ok(())
+}
+
+/// Reads a checkpoint written by [`write_checkpoint`], returning the WAL
+/// position it records and its series.
+pub(crate) fn read_checkpoint(path: &Path) -> Result<(u64, Vec<SeriesCheckpoint>), StorageError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut header = [0u8; 13];
+    reader.read_exact(&mut header)
+        .map_err(|_| StorageError::CorruptCheckpoint("truncated header".to_string()))?;
+    if &header[..4] != CHECKPOINT_MAGIC {
+        return Err(StorageError::CorruptCheckpoint("not a checkpoint file".to_string()));
+    }
+    if header[4] != CHECKPOINT_VERSION {
+        return Err(StorageError::CorruptCheckpoint(format!("unsupported version {}", header[4])));
+    }
+    let wal_position = u64::from_le_bytes(header[5..].try_into().unwrap());
+    
+    let series = bincode::deserialize_from(reader)
+        .map_err(|e| StorageError::CorruptCheckpoint(e.to_string()))?;
+    Ok((wal_position, series))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_checkpoint_rejects_other_files() {
+        let temp_file = NamedTempFile::new().unwrap();
+        
+        fs::write(temp_file.path(), b"TSC").unwrap();
+        assert!(matches!(read_checkpoint(temp_file.path()), Err(StorageError::CorruptCheckpoint(_))));
+        fs::write(temp_file.path(), b"WAL!\x01").unwrap();
+        assert!(matches!(read_checkpoint(temp_file.path()), Err(StorageError::CorruptCheckpoint(_))));
+        fs::write(temp_file.path(), b"TSCP\x09\0\0\0\0\0\0\0\0").unwrap();
+        assert!(matches!(read_checkpoint(temp_file.path()), Err(StorageError::CorruptCheckpoint(_))));
+        
+        write_checkpoint(temp_file.path(), 42, &[]).unwrap();
+        let mut data = fs::read(temp_file.path()).unwrap();
+        let (wal_position, series) = read_checkpoint(temp_file.path()).unwrap();
+        assert_eq!(wal_position, 42);
+        assert!(series.is_empty());
+        data.truncate(data.len() - 1);
+        fs::write(temp_file.path(), &data).unwrap();
+        assert!(matches!(read_checkpoint(temp_file.path()), Err(StorageError::CorruptCheckpoint(_))));
+    }
+}
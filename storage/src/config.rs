@@ -0,0 +1,235 @@
+use compression::{is_integer_valued, Codec, CodecRegistry, ErrorBound, GorillaCodec, IntegerCodec, LossyCodec, GORILLA_CODEC_ID};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use crate::block::BLOCK_DURATION;
+use tsdb_core::{DataPoint, DuplicatePolicy, SeriesId, SeriesKey, TimestampPrecision};
+
+/// About eight restart points in a two-hour block of one-second samples.
+pub const DEFAULT_RESTART_INTERVAL: usize = 1024;
+
+/// How a series' values are encoded when its blocks are sealed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ValueEncoding {
+    /// Use [`IntegerCodec`] for blocks whose values are all whole numbers,
+    /// and the default codec for everything else. A default codec other
+    /// than [`GorillaCodec`] is taken as a deliberate choice and used for
+    /// every block.
+    ///
+    /// Open blocks are always built with [`GorillaCodec`], whose stream can
+    /// be read while it grows; [`IntegerCodec`] cannot. A block that turns
+    /// out to be integer-valued is therefore decoded and encoded again when
+    /// it is sealed, which costs one extra pass over its points per block.
+    #[default]
+    Auto,
+    /// Always use the default codec.
+    Float,
+    /// The series holds counters or other whole numbers. Blocks are encoded
+    /// with [`IntegerCodec`]; a block that turns out to contain a fraction
+    /// falls back to the default codec rather than failing to seal.
+    Integer,
+    /// Trade precision for space: values are stored with [`LossyCodec`] and
+    /// decode to within the given bound of what was written.
+    Lossy(ErrorBound),
+}
+
+#[derive(Clone)]
+pub struct StorageConfig {
+    /// Codec used to encode blocks as they are sealed, unless the series'
+    /// [`ValueEncoding`] asks for another. Under [`ValueEncoding::Auto`]
+    /// integer-valued blocks only get [`IntegerCodec`] while this is left
+    /// as [`GorillaCodec`].
+    pub default_codec: Arc<dyn Codec>,
+    /// Codecs available to decode sealed blocks. Blocks record the codec
+    /// they were written with, so this must cover every codec ever used as
+    /// `default_codec`, not just the current one.
+    pub codecs: CodecRegistry,
+    /// Encoding for series without an entry in `series_encodings`.
+    pub value_encoding: ValueEncoding,
+    /// Per-series overrides of `value_encoding`.
+    pub series_encodings: HashMap<SeriesId, ValueEncoding>,
+    /// Points between restart points in sealed blocks, letting range scans
+    /// skip the start of a block they do not need. 0 disables the index.
+    pub restart_interval: usize,
+    /// Unit of the timestamps of series without an entry in
+    /// `series_precisions`.
+    pub precision: TimestampPrecision,
+    /// Per-series overrides of `precision`. A series must keep its
+    /// precision for as long as it has data: points are stored as given, so
+    /// changing it would reinterpret every timestamp already written.
+    pub series_precisions: HashMap<SeriesId, TimestampPrecision>,
+    /// How far behind a series' newest point a write may be. Older writes
+    /// are rejected; those within the window are merged into the block
+    /// they belong to, even one already sealed.
+    pub out_of_order_window: Duration,
+    /// How writes to a timestamp a series already holds are handled, for
+    /// series without an entry in `series_duplicate_policies`.
+    pub duplicate_policy: DuplicatePolicy,
+    /// Per-series overrides of `duplicate_policy`.
+    pub series_duplicate_policies: HashMap<SeriesId, DuplicatePolicy>,
+}
+
+impl StorageConfig {
+    /// Switches the codec for newly sealed blocks, registering it for
+    /// decoding as well. Existing blocks keep their codec.
+    pub fn with_default_codec(mut self, codec: Arc<dyn Codec>) -> Self {
+        self.codecs.register(codec.clone());
+        self.default_codec = codec;
+        self
+    }
+    
+    pub fn with_restart_interval(mut self, restart_interval: usize) -> Self {
+        self.restart_interval = restart_interval;
+        self
+    }
+    
+    pub fn with_value_encoding(mut self, encoding: ValueEncoding) -> Self {
+        self.value_encoding = encoding;
+        self
+    }
+    
+    /// Hints how the values of `key` should be encoded.
+    pub fn with_series_encoding(mut self, key: impl Into<SeriesKey>, encoding: ValueEncoding) -> Self {
+        self.series_encodings.insert(key.into().id(), encoding);
+        self
+    }
+    
+    pub fn with_precision(mut self, precision: TimestampPrecision) -> Self {
+        self.precision = precision;
+        self
+    }
+    
+    /// Counts the timestamps of `key` in `precision`.
+    pub fn with_series_precision(mut self, key: impl Into<SeriesKey>, precision: TimestampPrecision) -> Self {
+        self.series_precisions.insert(key.into().id(), precision);
+        self
+    }
+    
+    pub fn with_out_of_order_window(mut self, window: Duration) -> Self {
+        self.out_of_order_window = window;
+        self
+    }
+    
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicate_policy = policy;
+        self
+    }
+    
+    pub fn with_series_duplicate_policy(mut self, key: impl Into<SeriesKey>, policy: DuplicatePolicy) -> Self {
+        self.series_duplicate_policies.insert(key.into().id(), policy);
+        self
+    }
+    
+    pub fn precision_for(&self, series: impl Into<SeriesId>) -> TimestampPrecision {
+        self.series_precisions.get(&series.into()).copied().unwrap_or(self.precision)
+    }
+    
+    pub fn duplicate_policy_for(&self, series: impl Into<SeriesId>) -> DuplicatePolicy {
+        self.series_duplicate_policies.get(&series.into()).copied().unwrap_or(self.duplicate_policy)
+    }
+    
+    pub fn value_encoding_for(&self, series: impl Into<SeriesId>) -> ValueEncoding {
+        self.series_encodings.get(&series.into()).copied().unwrap_or(self.value_encoding)
+    }
+    
+    /// Picks the codec for sealing `points` of `series`. The choice is
+    /// recorded in the block, so readers need nothing from the config but
+    /// the registry.
+    pub fn codec_for(&self, series: impl Into<SeriesId>, points: &[DataPoint]) -> Arc<dyn Codec> {
+        self.codec_for_values(series, is_integer_valued(points))
+    }
+    
+    /// Same as [`codec_for`](Self::codec_for) when it is already known
+    /// whether every value is an exact integer, e.g. from
+    /// [`TimeSeriesBlock::is_integer_valued`](crate::TimeSeriesBlock::is_integer_valued).
+    pub fn codec_for_values(&self, series: impl Into<SeriesId>, integer_valued: bool) -> Arc<dyn Codec> {
+        let default_is_gorilla = self.default_codec.id() == GORILLA_CODEC_ID;
+        match self.value_encoding_for(series) {
+            ValueEncoding::Auto if integer_valued && default_is_gorilla => Arc::new(IntegerCodec),
+            ValueEncoding::Integer if integer_valued => Arc::new(IntegerCodec),
+            ValueEncoding::Lossy(bound) => Arc::new(LossyCodec::new(bound)),
+            _ => self.default_codec.clone(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            default_codec: Arc::new(GorillaCodec),
+            codecs: CodecRegistry::default(),
+            value_encoding: ValueEncoding::default(),
+            series_encodings: HashMap::new(),
+            restart_interval: DEFAULT_RESTART_INTERVAL,
+            precision: TimestampPrecision::default(),
+            series_precisions: HashMap::new(),
+            out_of_order_window: BLOCK_DURATION,
+            duplicate_policy: DuplicatePolicy::default(),
+            series_duplicate_policies: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use compression::{GORILLA_CODEC_ID, INTEGER_CODEC_ID, LOSSY_CODEC_ID};
+
+    fn points(values: &[f64]) -> Vec<DataPoint> {
+        values.iter()
+            .enumerate()
+            .map(|(i, &value)| DataPoint::new(1000 + i as u64 * 10, value))
+            .collect()
+    }
+
+    #[test]
+    fn test_codec_for_detects_integers() {
+        let config = StorageConfig::default();
+        assert_eq!(config.codec_for("requests", &points(&[1.0, 5.0, 9.0])).id(), INTEGER_CODEC_ID);
+        assert_eq!(config.codec_for("cpu", &points(&[1.0, 5.5, 9.0])).id(), GORILLA_CODEC_ID);
+    }
+
+    #[test]
+    fn test_codec_for_series_hints() {
+        let config = StorageConfig::default()
+            .with_value_encoding(ValueEncoding::Float)
+            .with_series_encoding("requests", ValueEncoding::Integer);
+        let counter = points(&[1.0, 5.0, 9.0]);
+        
+        assert_eq!(config.codec_for("requests", &counter).id(), INTEGER_CODEC_ID);
+        assert_eq!(config.codec_for("requests", &points(&[1.0, 0.5])).id(), GORILLA_CODEC_ID);
+        assert_eq!(config.codec_for("other", &counter).id(), GORILLA_CODEC_ID);
+    }
+
+    #[test]
+    fn test_codec_for_explicit_default_codec() {
+        let config = StorageConfig::default()
+            .with_default_codec(Arc::new(compression::ChimpCodec))
+            .with_series_encoding("requests", ValueEncoding::Integer);
+        let counter = points(&[1.0, 5.0, 9.0]);
+        
+        assert_eq!(config.codec_for("other", &counter).id(), compression::CHIMP_CODEC_ID);
+        assert_eq!(config.codec_for("requests", &counter).id(), INTEGER_CODEC_ID);
+    }
+
+    #[test]
+    fn test_precision_for_series() {
+        let config = StorageConfig::default()
+            .with_precision(TimestampPrecision::Microseconds)
+            .with_series_precision("trades", TimestampPrecision::Nanoseconds);
+        
+        assert_eq!(config.precision_for("trades"), TimestampPrecision::Nanoseconds);
+        assert_eq!(config.precision_for("cpu"), TimestampPrecision::Microseconds);
+        assert_eq!(StorageConfig::default().precision_for("cpu"), TimestampPrecision::Milliseconds);
+    }
+
+    #[test]
+    fn test_codec_for_lossy_series() {
+        let config = StorageConfig::default()
+            .with_series_encoding("temperature", ValueEncoding::Lossy(ErrorBound::Absolute(0.05)));
+        
+        assert_eq!(config.codec_for("temperature", &points(&[20.0, 21.0])).id(), LOSSY_CODEC_ID);
+        assert_eq!(config.codec_for("temperature", &points(&[20.01, 21.37])).id(), LOSSY_CODEC_ID);
+        assert_eq!(config.codec_for("humidity", &points(&[20.01, 21.37])).id(), GORILLA_CODEC_ID);
+    }
+}
@@ -1,9 +1,13 @@
 use thiserror::Error;
+use tsdb_core::{SeriesId, SeriesKey, TsdbError};
 
 #[derive(Error, Debug)]
 pub enum StorageError {
-    #[error("Key not found: {0}")]
-    KeyNotFound(String),
+    #[error("Series not found: {0}")]
+    SeriesNotFound(SeriesId),
+    
+    #[error("Series {new} has the same id as {existing}")]
+    SeriesIdCollision { existing: SeriesKey, new: SeriesKey },
     
     #[error("Block is sealed and cannot be modified")]
     BlockSealed,
@@ -11,6 +15,12 @@ pub enum StorageError {
     #[error("Invalid time range: start {0} > end {1}")]
     InvalidTimeRange(u64, u64),
     
+    #[error(transparent)]
+    Core(#[from] TsdbError),
+    
+    #[error("Corrupt checkpoint: {0}")]
+    CorruptCheckpoint(String),
+    
     #[error("Compression error: {0}")]
     CompressionError(#[from] compression::CompressionError),
     
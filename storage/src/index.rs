@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, HashMap};
+use tsdb_core::{LabelMatcher, SeriesId, SeriesKey, METRIC_NAME_LABEL};
+
+/// Maps every label pair to the series that carry it, so selectors can be
+/// answered without looking at each series' key.
+///
+/// Postings lists are kept sorted by id, which lets intersection, union and
+/// difference run as linear merges. The metric name is indexed as the
+/// `__name__` label.
+#[derive(Debug, Default)]
+pub struct LabelIndex {
+    postings: HashMap<String, BTreeMap<String, Vec<SeriesId>>>,
+    all: Vec<SeriesId>,
+}
+
+impl LabelIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Indexes `key`. Adding a series twice has no effect.
+    pub fn add(&mut self, key: &SeriesKey) {
+        let id = key.id();
+        if !insert_sorted(&mut self.all, id) {
+            return;
+        }
+        
+        let labels = std::iter::once((METRIC_NAME_LABEL, key.metric.as_str()))
+            .chain(key.labels.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        for (name, value) in labels {
+            let values = self.postings.entry(name.to_string()).or_default();
+            insert_sorted(values.entry(value.to_string()).or_default(), id);
+        }
+    }
+    
+    /// The series with label `name` set to `value`.
+    pub fn postings(&self, name: &str, value: &str) -> &[SeriesId] {
+        self.postings.get(name)
+            .and_then(|values| values.get(value))
+            .map_or(&[], Vec::as_slice)
+    }
+    
+    /// Every indexed series, sorted by id.
+    pub fn all(&self) -> &[SeriesId] {
+        &self.all
+    }
+    
+    pub fn label_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.postings.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+    
+    /// The values label `name` takes across all series, in order.
+    pub fn label_values(&self, name: &str) -> Vec<&str> {
+        self.postings.get(name)
+            .map(|values| values.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+    
+    /// The series that satisfy every matcher, sorted by id.
+    ///
+    /// Matchers that reject the empty value, such as `job="api"`, can only
+    /// select series that have the label, so their postings are gathered
+    /// and intersected, smallest first. The others, such as `dc!="x"`, are
+    /// applied by removing the series their complement selects. Only when
+    /// every matcher accepts the empty value does the selection start from
+    /// all series.
+    pub fn select(&self, matchers: &[LabelMatcher]) -> Vec<SeriesId> {
+        let (required, excluding): (Vec<_>, Vec<_>) = matchers.iter().partition(|m| !m.matches_value(""));
+        
+        let mut sets: Vec<Vec<SeriesId>> = required.iter()
+            .map(|matcher| self.matching_postings(matcher, true))
+            .collect();
+        sets.sort_by_key(Vec::len);
+        let mut selected = match sets.split_first() {
+            Some((first, rest)) => rest.iter().fold(first.clone(), |acc, set| intersect(&acc, set)),
+            None => self.all.clone(),
+        };
+        
+        for matcher in excluding {
+            if selected.is_empty() {
+                break;
+            }
+            selected = difference(&selected, &self.matching_postings(matcher, false));
+        }
+        selected
+    }
+    
+    /// The union of the postings of every value of the matcher's label that
+    /// the matcher accepts, or rejects when `accepted` is false.
+    fn matching_postings(&self, matcher: &LabelMatcher, accepted: bool) -> Vec<SeriesId> {
+        let Some(values) = self.postings.get(matcher.name()) else {
+            return Vec::new();
+        };
+        values.iter()
+            .filter(|(value, _)| matcher.matches_value(value) == accepted)
+            .fold(Vec::new(), |acc, (_, ids)| union(&acc, ids))
+    }
+}
+
+/// Ids in both sorted lists.
+pub fn intersect(a: &[SeriesId], b: &[SeriesId]) -> Vec<SeriesId> {
+    let mut result = Vec::with_capacity(a.len().min(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => i += 1,
+            std::cmp::Ordering::Greater => j += 1,
+            std::cmp::Ordering::Equal => {
+                result.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    result
+}
+
+/// Ids in either sorted list.
+pub fn union(a: &[SeriesId], b: &[SeriesId]) -> Vec<SeriesId> {
+    let mut result = Vec::with_capacity(a.len() + b.len());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        match a[i].cmp(&b[j]) {
+            std::cmp::Ordering::Less => {
+                result.push(a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                result.push(b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                result.push(a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    result.extend_from_slice(&a[i..]);
+    result.extend_from_slice(&b[j..]);
+    result
+}
+
+/// Ids in sorted list `a` but not in `b`.
+pub fn difference(a: &[SeriesId], b: &[SeriesId]) -> Vec<SeriesId> {
+    let mut result = Vec::with_capacity(a.len());
+    let mut j = 0;
+    for &id in a {
+        while j < b.len() && b[j] < id {
+            j += 1;
+        }
+        if j == b.len() || b[j] != id {
+            result.push(id);
+        }
+    }
+    result
+}
+
+/// Inserts `id` in order, returning false if it was already there.
+fn insert_sorted(ids: &mut Vec<SeriesId>, id: SeriesId) -> bool {
+    match ids.binary_search(&id) {
+        Ok(_) => false,
+        Err(position) => {
+            ids.insert(position, id);
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn key(selector: &str) -> SeriesKey {
+        SeriesKey::parse(selector).unwrap()
+    }
+
+    fn sample_index() -> (LabelIndex, Vec<SeriesKey>) {
+        let keys = vec![
+            key(r#"http_requests{job="api",status="200",dc="x"}"#),
+            key(r#"http_requests{job="api",status="503",dc="x"}"#),
+            key(r#"http_requests{job="api",status="500"}"#),
+            key(r#"http_requests{job="web",status="502",dc="y"}"#),
+            key(r#"cpu_usage{job="api",dc="x"}"#),
+            key("up"),
+        ];
+        let mut index = LabelIndex::new();
+        for key in &keys {
+            index.add(key);
+            index.add(key);
+        }
+        (index, keys)
+    }
+
+    fn select(index: &LabelIndex, selector: &str) -> Vec<SeriesId> {
+        index.select(&LabelMatcher::parse_selector(selector).unwrap())
+    }
+
+    fn ids(keys: &[&SeriesKey]) -> Vec<SeriesId> {
+        let mut ids: Vec<SeriesId> = keys.iter().map(|key| key.id()).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_label_index_postings() {
+        let (index, keys) = sample_index();
+        assert_eq!(index.all().len(), 6);
+        assert_eq!(index.postings("job", "web"), &[keys[3].id()]);
+        assert_eq!(index.postings("job", "nope"), &[] as &[SeriesId]);
+        assert_eq!(index.label_values("dc"), vec!["x", "y"]);
+        assert_eq!(index.label_names(), vec![METRIC_NAME_LABEL, "dc", "job", "status"]);
+    }
+
+    #[test]
+    fn test_label_index_select() {
+        let (index, keys) = sample_index();
+        
+        assert_eq!(select(&index, r#"{job="api", status=~"5.."}"#), ids(&[&keys[1], &keys[2]]));
+        assert_eq!(select(&index, r#"http_requests{dc!="x"}"#), ids(&[&keys[2], &keys[3]]));
+        assert_eq!(select(&index, r#"http_requests{status!~"5.."}"#), ids(&[&keys[0]]));
+        assert_eq!(select(&index, r#"{dc=""}"#), ids(&[&keys[2], &keys[5]]));
+        assert_eq!(select(&index, r#"{job=~"api|web", dc="x"}"#), ids(&[&keys[0], &keys[1], &keys[4]]));
+        assert_eq!(select(&index, "up"), ids(&[&keys[5]]));
+        assert_eq!(select(&index, r#"{job=~".*"}"#).len(), 6);
+        assert!(select(&index, r#"{job="db"}"#).is_empty());
+    }
+
+    #[test]
+    fn test_postings_set_operations() {
+        let a: Vec<SeriesId> = [1, 3, 5, 7].into_iter().map(SeriesId).collect();
+        let b: Vec<SeriesId> = [3, 4, 7, 9].into_iter().map(SeriesId).collect();
+        assert_eq!(intersect(&a, &b), vec![SeriesId(3), SeriesId(7)]);
+        assert_eq!(union(&a, &b), [1, 3, 4, 5, 7, 9].into_iter().map(SeriesId).collect::<Vec<_>>());
+        assert_eq!(difference(&a, &b), vec![SeriesId(1), SeriesId(5)]);
+        assert!(intersect(&a, &[]).is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn prop_select_agrees_with_matching_each_key(
+            series in prop::collection::vec((0..3usize, 0..4usize, 0..4usize), 1..40),
+            job in 0..4usize,
+            status in 0..4usize,
+        ) {
+            let jobs = ["", "api", "web", "db"];
+            let statuses = ["", "200", "404", "503"];
+            let keys: Vec<SeriesKey> = series.iter()
+                .map(|&(metric, job, status)| SeriesKey::new(["up", "cpu", "mem"][metric])
+                    .with_label("job", jobs[job])
+                    .with_label("status", statuses[status]))
+                .collect();
+            let mut index = LabelIndex::new();
+            for key in &keys {
+                index.add(key);
+            }
+            
+            let selector = format!(r#"{{job!="{}", status=~"{}|5.."}}"#, jobs[job], statuses[status]);
+            let matchers = LabelMatcher::parse_selector(&selector).unwrap();
+            let mut expected: Vec<SeriesId> = keys.iter()
+                .filter(|key| matchers.iter().all(|m| m.matches(key)))
+                .map(SeriesKey::id)
+                .collect();
+            expected.sort();
+            expected.dedup();
+            prop_assert_eq!(index.select(&matchers), expected);
+        }
+    }
+}
@@ -1,9 +1,14 @@
 pub mod memory;
 pub mod block;
 pub mod error;
+pub mod config;
 pub mod wal;
+pub mod index;
+mod checkpoint;
 
 pub use memory::*;
 pub use block::*;
 pub use error::*;
-pub use wal::*;
\ No newline at end of file
+pub use config::*;
+pub use wal::*;
+pub use index::*;
\ No newline at end of file
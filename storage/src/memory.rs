@@ -1,57 +1,224 @@
-use tsdb_core::{TimeSeriesKey, DataPoint, TimeSeries, CompressedBlock};
+use tsdb_core::{DataPoint, TimeSeries, CompressedBlock, LabelMatcher, SeriesId, SeriesKey, SeriesMetadata, TsdbError, ValueType};
 use crate::block::TimeSeriesBlock;
+use crate::checkpoint::{self, SeriesCheckpoint};
+use crate::config::StorageConfig;
 use crate::error::StorageError;
+use crate::index::LabelIndex;
+use crate::wal::WriteAheadLog;
+use compression::{analyze_block_with_registry, decompress_block_into, BlockDecoder, BlockStats, CompressionError, PointColumns};
 use dashmap::DashMap;
-use parking_lot::RwLock;
+use parking_lot::{ArcRwLockReadGuard, RawRwLock, RwLock};
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
-use std::collections::BTreeMap;
 
-pub struct TSMap {
-    series: DashMap<TimeSeriesKey, Arc<RwLock<TimeSeriesStorage>>>,
-}
+/// A series as storage keeps it: compressed blocks, sealed with the codecs
+/// the [`StorageConfig`] picks. Building one yourself and adding points with
+/// the same config gives the same blocks a [`TSMap`] would.
+pub type Series = TimeSeries<TimeSeriesBlock>;
+
+/// Read access to a series held by a [`TSMap`], from
+/// [`get_series`](TSMap::get_series).
+pub type SeriesGuard = ArcRwLockReadGuard<RawRwLock, Series>;
 
-struct TimeSeriesStorage {
-    key: TimeSeriesKey,
-    sealed_blocks: Vec<CompressedBlock>,
-    current_block: Option<TimeSeriesBlock>,
+/// The in-memory series, addressed by [`SeriesId`]. Methods that look a
+/// series up take anything that converts to one: a [`SeriesKey`], an id, or
+/// a plain name for a label-less series.
+pub struct TSMap {
+    series: DashMap<SeriesId, Arc<RwLock<Series>>>,
+    index: RwLock<LabelIndex>,
+    config: StorageConfig,
+    /// The WAL position the checkpoint the map was loaded from is up to;
+    /// earlier entries are already in the map.
+    wal_position: u64,
 }
 
 impl TSMap {
     pub fn new() -> Self {
+        Self::with_config(StorageConfig::default())
+    }
+    
+    pub fn with_config(config: StorageConfig) -> Self {
         Self {
             series: DashMap::new(),
+            index: RwLock::new(LabelIndex::new()),
+            config,
+            wal_position: 0,
         }
     }
     
-    pub fn insert(&self, key: TimeSeriesKey, point: DataPoint) -> Result<(), StorageError> {
+    pub fn config(&self) -> &StorageConfig {
+        &self.config
+    }
+    
+    /// Adds a point to the series `key`, creating it on first use.
+    pub fn insert(&self, key: impl Into<SeriesKey>, point: DataPoint) -> Result<(), StorageError> {
+        let storage = self.series_storage(key.into())?;
+        let mut storage = storage.write();
+        storage.add_point(point, &self.config)
+    }
+    
+    /// Declares the type, unit and help text of the series `key`, creating
+    /// it if it has no points yet.
+    pub fn set_metadata(&self, key: impl Into<SeriesKey>, metadata: SeriesMetadata) -> Result<(), StorageError> {
+        let storage = self.series_storage(key.into())?;
+        storage.write().set_metadata(metadata);
+        Ok(())
+    }
+    
+    pub fn metadata(&self, series: impl Into<SeriesId>) -> Option<SeriesMetadata> {
+        Some(self.series.get(&series.into())?.read().metadata().clone())
+    }
+    
+    fn series_storage(&self, key: SeriesKey) -> Result<Arc<RwLock<Series>>, StorageError> {
         let storage = self.series
-            .entry(key.clone())
-            .or_insert_with(|| Arc::new(RwLock::new(TimeSeriesStorage::new(key))));
+            .entry(key.id())
+            .or_insert_with(|| {
+                self.index.write().add(&key);
+                Arc::new(RwLock::new(Series::new(key.clone())))
+            })
+            .clone();
             
-        let mut storage = storage.write();
-        storage.insert_point(point)
+        if *storage.read().key() != key {
+            let existing = storage.read().key().clone();
+            return Err(StorageError::SeriesIdCollision { existing, new: key });
+        }
+        Ok(storage)
     }
     
-    pub fn get_series(&self, key: &TimeSeriesKey) -> Option<TimeSeries> {
-        let storage = self.series.get(key)?;
-        let storage = storage.read();
-        Some(storage.to_time_series())
+    /// Writes every series, with its blocks and metadata, to a checkpoint
+    /// at `path`. The file is replaced atomically, so a crash leaves either
+    /// the old checkpoint or the new one.
+    ///
+    /// `wal_position` is the [`position`](WriteAheadLog::position) of the
+    /// WAL the map's points were logged to, read before the checkpoint is
+    /// taken: every entry before it must already be in the map. A map
+    /// loaded from the checkpoint skips those entries when it
+    /// [replays](Self::replay_wal) the log, which may also be truncated
+    /// once this returns.
+    pub fn write_checkpoint(&self, path: impl AsRef<Path>, wal_position: u64) -> Result<(), StorageError> {
+        let series = self.series.iter()
+            .map(|entry| to_checkpoint(&entry.read()))
+            .collect::<Result<Vec<_>, _>>()?;
+        checkpoint::write_checkpoint(path.as_ref(), wal_position, &series)
     }
     
-    pub fn scan_range(&self, key: &TimeSeriesKey, start: u64, end: u64) -> Result<Vec<DataPoint>, StorageError> {
+    /// Rebuilds a map from a checkpoint written by
+    /// [`write_checkpoint`](Self::write_checkpoint).
+    pub fn load_checkpoint(path: impl AsRef<Path>, config: StorageConfig) -> Result<Self, StorageError> {
+        let (wal_position, checkpoint) = checkpoint::read_checkpoint(path.as_ref())?;
+        let mut tsmap = Self::with_config(config);
+        tsmap.wal_position = wal_position;
+        for series in checkpoint {
+            let storage = from_checkpoint(series, &tsmap.config)?;
+            tsmap.index.write().add(storage.key());
+            tsmap.series.insert(storage.key().id(), Arc::new(RwLock::new(storage)));
+        }
+        Ok(tsmap)
+    }
+    
+    /// Inserts every point in `wal`, in the order it was written, so each
+    /// series' duplicate policy resolves repeated timestamps as it did when
+    /// the points first arrived. Points rejected as duplicates or as older
+    /// than the out-of-order window are skipped rather than failing the
+    /// replay, since they were rejected when first written too. Entries
+    /// before the position recorded by the checkpoint the map was loaded
+    /// from are skipped, as the map already holds them.
+    /// Returns the number of entries replayed, less those rejected.
+    pub fn replay_wal(&self, wal: &WriteAheadLog) -> Result<usize, StorageError> {
+        let mut inserted = 0;
+        wal.replay_from(self.wal_position, |entry| match self.insert(entry.key, entry.point) {
+            Ok(()) => {
+                inserted += 1;
+                Ok(())
+            }
+            Err(StorageError::Core(TsdbError::DuplicateTimestamp(_) | TsdbError::OutOfOrder { .. })) => Ok(()),
+            Err(e) => Err(e),
+        })?;
+        Ok(inserted)
+    }
+    
+    /// Read access to the series as the map holds it. Writes to the series
+    /// wait until the guard is dropped, so hold it only briefly.
+    pub fn get_series(&self, series: impl Into<SeriesId>) -> Option<SeriesGuard> {
+        Some(self.series.get(&series.into())?.read_arc())
+    }
+    
+    pub fn scan_range(&self, series: impl Into<SeriesId>, start: u64, end: u64) -> Result<Vec<DataPoint>, StorageError> {
+        if start > end {
+            return Err(StorageError::InvalidTimeRange(start, end));
+        }
+        
+        let id = series.into();
+        let storage = self.series.get(&id)
+            .ok_or(StorageError::SeriesNotFound(id))?;
+        
+        let mut corrupt = Vec::new();
+        let points = scan_series(&storage.read(), start, end, &self.config, &mut corrupt)?;
+        if !corrupt.is_empty() {
+            quarantine_blocks(&mut storage.write(), &corrupt, &self.config);
+        }
+        
+        Ok(points)
+    }
+    
+    /// Like [`scan_range`](Self::scan_range), but decodes into timestamp and
+    /// value columns for the [`kernels`](compression::kernels) rather than
+    /// building a [`DataPoint`] per point. Only float series can be read
+    /// this way; others fail with [`TsdbError::TypeMismatch`].
+    pub fn scan_columns(&self, series: impl Into<SeriesId>, start: u64, end: u64) -> Result<PointColumns, StorageError> {
         if start > end {
             return Err(StorageError::InvalidTimeRange(start, end));
         }
         
-        let storage = self.series.get(key)
-            .ok_or_else(|| StorageError::KeyNotFound(key.clone()))?;
-        let storage = storage.read();
+        let id = series.into();
+        let storage = self.series.get(&id)
+            .ok_or(StorageError::SeriesNotFound(id))?;
+        
+        let mut corrupt = Vec::new();
+        let columns = scan_series_columns(&storage.read(), start, end, &self.config, &mut corrupt)?;
+        if !corrupt.is_empty() {
+            quarantine_blocks(&mut storage.write(), &corrupt, &self.config);
+        }
         
-        storage.scan_range(start, end)
+        Ok(columns)
+    }
+    
+    /// The type of value the series holds, once it has a point.
+    pub fn value_type(&self, series: impl Into<SeriesId>) -> Option<ValueType> {
+        self.series.get(&series.into())?.read().value_type()
+    }
+    
+    /// Blocks of the series that were found to be corrupt and set aside.
+    pub fn quarantined_blocks(&self, series: impl Into<SeriesId>) -> Vec<CompressedBlock> {
+        self.series.get(&series.into())
+            .map(|storage| storage.read().quarantined_blocks().to_vec())
+            .unwrap_or_default()
     }
     
-    pub fn keys(&self) -> Vec<TimeSeriesKey> {
-        self.series.iter().map(|entry| entry.key().clone()).collect()
+    /// The key of the series with id `id`.
+    pub fn series_key(&self, id: SeriesId) -> Option<SeriesKey> {
+        Some(self.series.get(&id)?.read().key().clone())
+    }
+    
+    pub fn series_ids(&self) -> Vec<SeriesId> {
+        self.series.iter().map(|entry| *entry.key()).collect()
+    }
+    
+    /// The series matching every one of `matchers`, found through the label
+    /// index. See [`LabelIndex::select`].
+    pub fn select(&self, matchers: &[LabelMatcher]) -> Vec<SeriesId> {
+        self.index.read().select(matchers)
+    }
+    
+    /// The values label `name` takes across all series, in order. The metric
+    /// name can be listed as `__name__`.
+    pub fn label_values(&self, name: &str) -> Vec<String> {
+        self.index.read().label_values(name).into_iter().map(str::to_string).collect()
+    }
+    
+    pub fn keys(&self) -> Vec<SeriesKey> {
+        self.series.iter().map(|entry| entry.read().key().clone()).collect()
     }
     
     pub fn len(&self) -> usize {
@@ -62,24 +229,36 @@ impl TSMap {
         self.series.is_empty()
     }
     
+    /// Seals open blocks whose time span has passed, and merges buffered
+    /// out-of-order points into the sealed blocks.
     pub fn seal_expired_blocks(&self) {
         for entry in self.series.iter() {
             let mut storage = entry.write();
-            storage.seal_if_expired();
+            let _ = storage.seal_if_expired(&self.config);
+            let _ = storage.flush_out_of_order(&self.config);
         }
     }
     
+    /// Totals across all series, plus each series' own stats. Gathering the
+    /// compression breakdown decodes every block, so this is meant for
+    /// diagnostics rather than frequent polling.
     pub fn get_stats(&self) -> TSMapStats {
         let mut total_points = 0;
         let mut total_blocks = 0;
         let mut total_compressed_size = 0;
+        let mut total_quarantined_blocks = 0;
+        let mut compression = BlockStats::default();
+        let mut series = HashMap::new();
         
         for entry in self.series.iter() {
             let storage = entry.read();
-            let stats = storage.get_stats();
+            let stats = series_stats(&storage, &self.config);
             total_points += stats.point_count;
             total_blocks += stats.block_count;
             total_compressed_size += stats.compressed_size;
+            total_quarantined_blocks += stats.quarantined_blocks;
+            compression.merge(&stats.compression);
+            series.insert(storage.key().clone(), stats);
         }
         
         TSMapStats {
@@ -87,6 +266,9 @@ impl TSMap {
             total_points,
             total_blocks,
             total_compressed_size,
+            total_quarantined_blocks,
+            compression,
+            series,
         }
     }
 }
@@ -103,6 +285,10 @@ pub struct TSMapStats {
     pub total_points: usize,
     pub total_blocks: usize,
     pub total_compressed_size: usize,
+    pub total_quarantined_blocks: usize,
+    /// Where the compressed bits of all series went.
+    pub compression: BlockStats,
+    pub series: HashMap<SeriesKey, TimeSeriesStats>,
 }
 
 #[derive(Debug, Clone)]
@@ -110,111 +296,221 @@ pub struct TimeSeriesStats {
     pub point_count: usize,
     pub block_count: usize,
     pub compressed_size: usize,
+    pub quarantined_blocks: usize,
+    /// Where the series' compressed bits went, over its sealed blocks and
+    /// the open block. Blocks that fail to decode are left out.
+    pub compression: BlockStats,
 }
 
-impl TimeSeriesStorage {
-    fn new(key: TimeSeriesKey) -> Self {
-        Self {
-            key,
-            sealed_blocks: Vec::new(),
-            current_block: None,
-        }
+fn to_checkpoint(series: &Series) -> Result<SeriesCheckpoint, StorageError> {
+    Ok(SeriesCheckpoint {
+        key: series.key().clone(),
+        metadata: series.metadata().clone(),
+        value_type: series.value_type(),
+        sealed_blocks: series.blocks().to_vec(),
+        quarantined_blocks: series.quarantined_blocks().to_vec(),
+        open_points: series.open_points()?,
+    })
+}
+
+fn from_checkpoint(checkpoint: SeriesCheckpoint, config: &StorageConfig) -> Result<Series, StorageError> {
+    let mut series = Series::from_blocks(
+        checkpoint.key,
+        checkpoint.metadata,
+        checkpoint.value_type,
+        checkpoint.sealed_blocks,
+        checkpoint.quarantined_blocks,
+    );
+    for point in checkpoint.open_points {
+        series.add_point(point, config)?;
     }
+    Ok(series)
+}
+
+/// Collects the points of `series` in `[start, end]`. Blocks that turn out
+/// to be corrupt are skipped and their indices pushed to `corrupt`, so one
+/// bad block cannot fail or taint the whole query.
+fn scan_series(series: &Series, start: u64, end: u64, config: &StorageConfig, corrupt: &mut Vec<usize>) -> Result<Vec<DataPoint>, StorageError> {
+    let mut points = Vec::new();
     
-    fn insert_point(&mut self, point: DataPoint) -> Result<(), StorageError> {
-        if let Some(ref mut block) = self.current_block {
-            if block.can_accept(point.timestamp) {
-                return block.add_point(point);
-            } else {
-                self.seal_current_block()?;
-            }
+    for (index, block) in series.blocks().iter().enumerate() {
+        if block.end_timestamp < start || block.start_timestamp > end {
+            continue;
         }
         
-        let mut new_block = TimeSeriesBlock::new(point.timestamp);
-        new_block.add_point(point)?;
-        self.current_block = Some(new_block);
-        
-        Ok(())
+        match scan_block(block, start, end, config) {
+            Ok(block_points) => points.extend(block_points),
+            Err(e) if is_corruption(&e) => corrupt.push(index),
+            Err(e) => return Err(e.into()),
+        }
     }
     
-    fn seal_current_block(&mut self) -> Result<(), StorageError> {
-        if let Some(mut block) = self.current_block.take() {
-            block.seal();
-            let compressed = block.compress()?;
-            if compressed.count > 0 {
-                self.sealed_blocks.push(compressed);
-            }
+    for point in series.open_points()? {
+        if point.timestamp >= start && point.timestamp <= end {
+            points.push(point);
         }
-        Ok(())
     }
     
-    fn seal_if_expired(&mut self) {
-        if let Some(ref block) = self.current_block {
-            if block.should_seal() {
-                let _ = self.seal_current_block();
-            }
+    points.sort_by_key(|p| p.timestamp);
+    Ok(points)
+}
+
+fn scan_block(block: &CompressedBlock, start: u64, end: u64, config: &StorageConfig) -> Result<Vec<DataPoint>, CompressionError> {
+    let mut points = Vec::new();
+    
+    // Sealed blocks are time-ordered, so decoding can start at the last
+    // restart point before the range and stop at the first point past it.
+    let mut decoder = BlockDecoder::with_registry(block, &config.codecs)?;
+    decoder.seek(start);
+    for point in decoder {
+        let point = point?;
+        if point.timestamp > end {
+            break;
+        }
+        if point.timestamp >= start {
+            points.push(point);
         }
     }
     
-    fn to_time_series(&self) -> TimeSeries {
-        let current_points = self.current_block
-            .as_ref()
-            .map(|block| block.points.clone())
-            .unwrap_or_default();
-            
-        TimeSeries {
-            key: self.key.clone(),
-            blocks: self.sealed_blocks.clone(),
-            current_block: if current_points.is_empty() { None } else { Some(current_points) },
-        }
+    Ok(points)
+}
+
+/// Like [`scan_series`], into columns.
+fn scan_series_columns(series: &Series, start: u64, end: u64, config: &StorageConfig, corrupt: &mut Vec<usize>) -> Result<PointColumns, StorageError> {
+    if let Some(actual) = series.value_type().filter(|&actual| actual != ValueType::Float) {
+        return Err(TsdbError::TypeMismatch { key: series.key().to_string(), expected: ValueType::Float, actual }.into());
     }
+    let mut columns = PointColumns::new();
     
-    fn scan_range(&self, start: u64, end: u64) -> Result<Vec<DataPoint>, StorageError> {
-        let mut points = Vec::new();
-        
-        for block in &self.sealed_blocks {
-            if block.end_timestamp < start || block.start_timestamp > end {
-                continue;
-            }
-            
-            // For now, we can't decompress blocks in this simple implementation
-            // In a real implementation, we'd decompress the block and filter points
+    for (index, block) in series.blocks().iter().enumerate() {
+        if block.end_timestamp < start || block.start_timestamp > end {
+            continue;
         }
         
-        if let Some(ref block) = self.current_block {
-            for point in &block.points {
-                if point.timestamp >= start && point.timestamp <= end {
-                    points.push(point.clone());
-                }
+        let before = columns.len();
+        match scan_block_columns(block, start, end, config, &mut columns) {
+            Ok(()) => {}
+            Err(e) if is_corruption(&e) => {
+                columns.truncate(before);
+                corrupt.push(index);
             }
+            Err(e) => return Err(e.into()),
         }
-        
-        points.sort_by_key(|p| p.timestamp);
-        Ok(points)
     }
     
-    fn get_stats(&self) -> TimeSeriesStats {
-        let current_points = self.current_block
-            .as_ref()
-            .map(|block| block.points.len())
-            .unwrap_or(0);
-            
-        let sealed_points: usize = self.sealed_blocks.iter().map(|b| b.count).sum();
-        let compressed_size: usize = self.sealed_blocks.iter()
-            .map(|b| b.compressed_data.len())
-            .sum();
-            
-        TimeSeriesStats {
-            point_count: current_points + sealed_points,
-            block_count: self.sealed_blocks.len() + if self.current_block.is_some() { 1 } else { 0 },
-            compressed_size,
+    for point in series.open_points()? {
+        if point.timestamp >= start && point.timestamp <= end {
+            columns.push(&point);
         }
     }
+    
+    columns.sort_by_timestamp();
+    Ok(columns)
+}
+
+/// Appends the points of `block` in `[start, end]` to `columns`.
+fn scan_block_columns(block: &CompressedBlock, start: u64, end: u64, config: &StorageConfig, columns: &mut PointColumns) -> Result<(), CompressionError> {
+    if block.start_timestamp >= start && block.end_timestamp <= end {
+        decompress_block_into(block, &config.codecs, columns)?;
+        return Ok(());
+    }
+    
+    // Decode from the last restart point before the range, then cut the
+    // points either side of it; sealed blocks are time-ordered.
+    let before = columns.len();
+    let mut decoder = BlockDecoder::with_registry(block, &config.codecs)?;
+    decoder.seek(start);
+    decoder.decode_into(&mut columns.timestamps, &mut columns.values)?;
+    
+    let decoded = &columns.timestamps[before..];
+    let first = before + decoded.partition_point(|&timestamp| timestamp < start);
+    let last = before + decoded.partition_point(|&timestamp| timestamp <= end);
+    columns.truncate(last);
+    columns.timestamps.drain(before..first);
+    columns.values.drain(before..first);
+    Ok(())
+}
+
+/// Moves the blocks of `series` at `indices` to quarantine. Each one is
+/// decoded in full first: the indices come from a scan under a read lock,
+/// and the block list may have changed since, so only blocks that really
+/// are corrupt are moved.
+fn quarantine_blocks(series: &mut Series, indices: &[usize], config: &StorageConfig) {
+    for &index in indices.iter().rev() {
+        let Some(block) = series.blocks().get(index) else {
+            continue;
+        };
+        let decoded = BlockDecoder::with_registry(block, &config.codecs)
+            .and_then(|mut decoder| decoder.try_for_each(|point| point.map(drop)));
+        if matches!(decoded, Err(ref e) if is_corruption(e)) {
+            series.quarantine_block(index);
+        }
+    }
+}
+
+fn series_stats(series: &Series, config: &StorageConfig) -> TimeSeriesStats {
+    let open_size = series.current_block()
+        .map(|block| block.compressed_size())
+        .unwrap_or(0);
+    let compressed_size: usize = series.blocks().iter()
+        .map(|b| b.compressed_data.len())
+        .sum::<usize>() + open_size;
+    
+    TimeSeriesStats {
+        point_count: series.point_count(),
+        block_count: series.blocks().len() + if series.current_block().is_some() { 1 } else { 0 },
+        compressed_size,
+        quarantined_blocks: series.quarantined_blocks().len(),
+        compression: series_compression(series, config),
+    }
+}
+
+fn series_compression(series: &Series, config: &StorageConfig) -> BlockStats {
+    let mut compression = BlockStats::default();
+    let open_block = series.current_block().and_then(|block| block.compress().ok());
+    for block in series.blocks().iter().chain(&open_block) {
+        if let Ok(stats) = analyze_block_with_registry(block, &config.codecs) {
+            compression.merge(&stats);
+        }
+    }
+    compression
+}
+
+/// Errors that mean the block's bytes are damaged, as opposed to errors such
+/// as an unregistered codec that say nothing about the data itself. Storage
+/// only holds blocks it sealed itself, so an unknown format version is
+/// damage too.
+pub(crate) fn is_corruption(error: &CompressionError) -> bool {
+    matches!(
+        error,
+        CompressionError::ChecksumMismatch { .. }
+            | CompressionError::InvalidFormat
+            | CompressionError::InsufficientData
+            | CompressionError::UnsupportedVersion(_)
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::ValueEncoding;
+    use crate::wal::WALEntry;
+    use tsdb_core::{DuplicatePolicy, MetricType, TimestampPrecision};
+    use compression::test_util::{RawCodec, RAW_CODEC_ID};
+    use compression::{CodecId, GORILLA_CODEC_ID, INTEGER_CODEC_ID};
+
+    /// Flips a bit at the end of the `index`th sealed block of `key`. The
+    /// series is rebuilt the way a checkpoint would restore it, since its
+    /// blocks cannot be changed in place.
+    fn corrupt_block(tsmap: &TSMap, key: &str, index: usize) {
+        let storage = tsmap.series.get(&SeriesId::from(key)).unwrap();
+        let mut series = storage.write();
+        let mut checkpoint = to_checkpoint(&series).unwrap();
+        let data = &mut checkpoint.sealed_blocks[index].compressed_data;
+        let last = data.len() - 1;
+        data[last] ^= 0x10;
+        *series = from_checkpoint(checkpoint, &tsmap.config).unwrap();
+    }
 
     #[test]
     fn test_tsmap_creation() {
@@ -271,14 +567,14 @@ mod tests {
         tsmap.insert(key.clone(), point.clone()).unwrap();
         
         let series = tsmap.get_series(&key).unwrap();
-        assert_eq!(series.key, key);
+        assert_eq!(*series.key(), key);
         assert_eq!(series.point_count(), 1);
     }
 
     #[test]
     fn test_tsmap_get_nonexistent_series() {
         let tsmap = TSMap::new();
-        assert!(tsmap.get_series(&"nonexistent".to_string()).is_none());
+        assert!(tsmap.get_series("nonexistent").is_none());
     }
 
     #[test]
@@ -297,6 +593,178 @@ mod tests {
         assert_eq!(points[4].timestamp, 1600);
     }
 
+    #[test]
+    fn test_tsmap_scan_range_sealed_blocks() {
+        let tsmap = TSMap::new();
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        // Five hours of points, one every 10 minutes, spread over three blocks.
+        for i in 0..30 {
+            let point = DataPoint::new(i * 10 * 60 * 1000, i as f64 * 1.5);
+            tsmap.insert(key.clone(), point).unwrap();
+        }
+        assert_eq!(tsmap.get_series(&key).unwrap().blocks().len(), 2);
+        
+        let points = tsmap.scan_range(&key, hour, 4 * hour).unwrap();
+        assert_eq!(points.len(), 19);
+        assert_eq!(points[0], DataPoint::new(hour, 9.0));
+        assert_eq!(points[18], DataPoint::new(4 * hour, 36.0));
+        assert!(points.windows(2).all(|w| w[0].timestamp < w[1].timestamp));
+        
+        let all = tsmap.scan_range(&key, 0, u64::MAX).unwrap();
+        assert_eq!(all.len(), 30);
+    }
+
+    #[test]
+    fn test_tsmap_scan_range_uses_restart_index() {
+        let tsmap = TSMap::with_config(StorageConfig::default().with_restart_interval(100));
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        // Two hours of one-second samples seal into one indexed block once
+        // the third hour starts.
+        for i in 0..7200 {
+            tsmap.insert(key.clone(), DataPoint::new(i * 1000, i as f64 * 0.25)).unwrap();
+        }
+        tsmap.insert(key.clone(), DataPoint::new(2 * hour, 0.0)).unwrap();
+        
+        let series = tsmap.get_series(&key).unwrap();
+        let header = compression::BlockHeader::read_from(&series.blocks()[0].compressed_data).unwrap();
+        assert_eq!(header.restart_interval, 100);
+        
+        let points = tsmap.scan_range(&key, 7050 * 1000, 7100 * 1000).unwrap();
+        assert_eq!(points.len(), 51);
+        assert_eq!(points[0], DataPoint::new(7050 * 1000, 7050.0 * 0.25));
+        assert_eq!(points[50], DataPoint::new(7100 * 1000, 7100.0 * 0.25));
+    }
+
+    #[test]
+    fn test_tsmap_scan_columns_matches_scan_range() {
+        let tsmap = TSMap::with_config(StorageConfig::default().with_restart_interval(64));
+        let hour = 60 * 60 * 1000;
+        
+        // One sealed block, an open one, and a late point buffered for the
+        // sealed block.
+        for i in 0..3 * 360 {
+            tsmap.insert("cpu", DataPoint::new(i * 10_000, (i as f64 * 0.1).sin())).unwrap();
+        }
+        tsmap.insert("cpu", DataPoint::new(hour + 5, 0.5)).unwrap();
+        
+        for (start, end) in [(0, u64::MAX), (hour / 2, 2 * hour + hour / 2), (hour + 5, hour + 5), (5 * hour, 6 * hour)] {
+            let points = tsmap.scan_range("cpu", start, end).unwrap();
+            assert_eq!(tsmap.scan_columns("cpu", start, end).unwrap(), PointColumns::from_points(&points));
+        }
+        
+        tsmap.insert("up", DataPoint::new(0, true)).unwrap();
+        assert!(matches!(
+            tsmap.scan_columns("up", 0, 10),
+            Err(StorageError::Core(TsdbError::TypeMismatch { expected: ValueType::Float, actual: ValueType::Boolean, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_tsmap_default_codec() {
+        let config = StorageConfig::default()
+            .with_default_codec(Arc::new(RawCodec));
+        let tsmap = TSMap::with_config(config);
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..6 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour, i as f64)).unwrap();
+        }
+        
+        let series = tsmap.get_series(&key).unwrap();
+        assert!(series.blocks().iter().all(|b| b.compressed_data[5] == RAW_CODEC_ID));
+        
+        let points = tsmap.scan_range(&key, 0, u64::MAX).unwrap();
+        let values: Vec<f64> = points.iter().map(|p| p.value.as_f64().unwrap()).collect();
+        assert_eq!(values, vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn test_tsmap_integer_series() {
+        let tsmap = TSMap::new();
+        let counter = "requests.total".to_string();
+        let gauge = "cpu.usage".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..6 {
+            tsmap.insert(counter.clone(), DataPoint::new(i * hour, (i * 1000) as f64)).unwrap();
+            tsmap.insert(gauge.clone(), DataPoint::new(i * hour, i as f64 + 0.5)).unwrap();
+        }
+        
+        let codecs = |key: &String| -> Vec<CodecId> {
+            tsmap.get_series(key).unwrap().blocks().iter().map(|b| b.compressed_data[5]).collect()
+        };
+        assert!(codecs(&counter).iter().all(|&id| id == INTEGER_CODEC_ID));
+        assert!(codecs(&gauge).iter().all(|&id| id == GORILLA_CODEC_ID));
+        
+        let values: Vec<f64> = tsmap.scan_range(&counter, 0, u64::MAX).unwrap().iter().map(|p| p.value.as_f64().unwrap()).collect();
+        assert_eq!(values, vec![0.0, 1000.0, 2000.0, 3000.0, 4000.0, 5000.0]);
+    }
+
+    #[test]
+    fn test_tsmap_quarantines_corrupt_blocks() {
+        let tsmap = TSMap::new();
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..30 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour / 5, i as f64 + 0.5)).unwrap();
+        }
+        
+        assert_eq!(tsmap.get_series(&key).unwrap().blocks().len(), 2);
+        corrupt_block(&tsmap, &key, 0);
+        
+        // The damaged first block is dropped from the results rather than
+        // failing the query or returning garbage.
+        let points = tsmap.scan_range(&key, 0, u64::MAX).unwrap();
+        assert_eq!(points.len(), 20);
+        assert_eq!(points[0], DataPoint::new(2 * hour, 10.5));
+        
+        let quarantined = tsmap.quarantined_blocks(&key);
+        assert_eq!(quarantined.len(), 1);
+        assert_eq!(quarantined[0].start_timestamp, 0);
+        assert!(matches!(
+            compression::decompress_block(&quarantined[0]),
+            Err(CompressionError::ChecksumMismatch { .. })
+        ));
+        
+        let stats = tsmap.get_stats();
+        assert_eq!(stats.total_quarantined_blocks, 1);
+        assert_eq!(stats.total_points, 20);
+        assert_eq!(tsmap.scan_range(&key, 0, u64::MAX).unwrap(), points);
+    }
+
+    #[test]
+    fn test_tsmap_unknown_codec_is_not_corruption() {
+        let config = StorageConfig::default()
+            .with_default_codec(Arc::new(RawCodec));
+        let mut tsmap = TSMap::with_config(config);
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..4 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour, i as f64)).unwrap();
+        }
+        
+        tsmap.config = StorageConfig::default().with_out_of_order_window(std::time::Duration::from_secs(4 * 60 * 60));
+        assert!(matches!(
+            tsmap.scan_range(&key, 0, u64::MAX),
+            Err(StorageError::CompressionError(CompressionError::UnsupportedCodec(RAW_CODEC_ID)))
+        ));
+        assert!(tsmap.quarantined_blocks(&key).is_empty());
+        
+        // Nor is merging a late point into one of its blocks; the point
+        // stays buffered instead.
+        tsmap.insert(key.clone(), DataPoint::new(hour / 2, 0.5)).unwrap();
+        tsmap.insert(key.clone(), DataPoint::new(4 * hour, 4.0)).unwrap();
+        assert!(tsmap.quarantined_blocks(&key).is_empty());
+        assert_eq!(tsmap.get_stats().total_points, 6);
+    }
+
     #[test]
     fn test_tsmap_scan_range_invalid() {
         let tsmap = TSMap::new();
@@ -308,7 +776,7 @@ mod tests {
     #[test]
     fn test_tsmap_scan_range_nonexistent_key() {
         let tsmap = TSMap::new();
-        assert!(tsmap.scan_range(&"nonexistent".to_string(), 0, 1000).is_err());
+        assert!(tsmap.scan_range("nonexistent", 0, 1000).is_err());
     }
 
     #[test]
@@ -343,5 +811,435 @@ mod tests {
         let stats = tsmap.get_stats();
         assert_eq!(stats.series_count, 1);
         assert_eq!(stats.total_points, 5);
+        assert_eq!(stats.series[&SeriesKey::from(key.as_str())].point_count, 5);
+        assert_eq!(stats.series[&SeriesKey::from(key.as_str())].compression.points, 5);
+    }
+
+    #[test]
+    fn test_tsmap_stats_compression_breakdown() {
+        let config = StorageConfig::default()
+            .with_value_encoding(ValueEncoding::Float)
+            .with_restart_interval(0);
+        let tsmap = TSMap::with_config(config);
+        let hour = 60 * 60 * 1000;
+        
+        // Three hours at one point per second: one sealed block, one open.
+        for i in 0..3 * 3600 {
+            tsmap.insert("regular".to_string(), DataPoint::new(i * 1000, 42.0)).unwrap();
+        }
+        tsmap.insert("jittery".to_string(), DataPoint::new(0, 1.0)).unwrap();
+        tsmap.insert("jittery".to_string(), DataPoint::new(hour / 2, 2.0)).unwrap();
+        tsmap.insert("jittery".to_string(), DataPoint::new(hour / 2 + 7, 3.5)).unwrap();
+        
+        let stats = tsmap.get_stats();
+        let regular = &stats.series[&SeriesKey::new("regular")];
+        assert_eq!(regular.compression.blocks, 2);
+        assert_eq!(regular.compression.points, 3 * 3600);
+        assert_eq!(regular.compression.values.repeats, 3 * 3600 - 2);
+        assert_eq!(regular.compression.timestamps.zero, 3 * 3600 - 4);
+        assert_eq!(regular.compression.total_bits(), regular.compressed_size * 8);
+        
+        let jittery = &stats.series[&SeriesKey::new("jittery")].compression;
+        assert_eq!(jittery.timestamps.bits_32, 2);
+        assert_eq!(jittery.values.repeats, 0);
+        
+        assert_eq!(stats.compression.points, 3 * 3600 + 3);
+        assert_eq!(stats.compression.total_bits(), stats.total_compressed_size * 8);
+    }
+
+    #[test]
+    fn test_tsmap_typed_series() {
+        let tsmap = TSMap::with_config(StorageConfig::default().with_restart_interval(4));
+        let hour = 60 * 60 * 1000;
+        
+        // Spans a sealed block and the open one.
+        let requests: Vec<DataPoint> = (0..20).map(|i| DataPoint::new(i * hour / 4, i as i64 * 1000)).collect();
+        let versions: Vec<DataPoint> = (0..20).map(|i| DataPoint::new(i * hour / 4, if i < 12 { "v1.0" } else { "v1.1" })).collect();
+        for (request, version) in requests.iter().zip(&versions) {
+            tsmap.insert("requests".to_string(), request.clone()).unwrap();
+            tsmap.insert("version".to_string(), version.clone()).unwrap();
+        }
+        
+        assert_eq!(tsmap.value_type("requests"), Some(ValueType::Integer));
+        assert_eq!(tsmap.value_type("version"), Some(ValueType::String));
+        assert_eq!(tsmap.scan_range("requests", 0, u64::MAX).unwrap(), requests);
+        assert_eq!(tsmap.scan_range("version", hour, 2 * hour).unwrap(), versions[4..=8]);
+        
+        let series = tsmap.get_series("version").unwrap();
+        assert!(series.blocks().iter().all(|block| block.value_type == ValueType::String));
+        assert_eq!(tsmap.get_stats().series[&SeriesKey::new("requests")].compression.codecs, vec![(compression::INT64_CODEC_ID, 3)]);
+    }
+
+    #[test]
+    fn test_tsmap_rejects_type_change() {
+        let tsmap = TSMap::new();
+        let key = "up".to_string();
+        tsmap.insert(key.clone(), DataPoint::new(1000, true)).unwrap();
+        
+        assert!(matches!(
+            tsmap.insert(key.clone(), DataPoint::new(2000, 1.0)),
+            Err(StorageError::Core(TsdbError::TypeMismatch { expected: ValueType::Boolean, actual: ValueType::Float, .. }))
+        ));
+        assert_eq!(tsmap.scan_range(&key, 0, u64::MAX).unwrap(), vec![DataPoint::new(1000, true)]);
+    }
+
+    #[test]
+    fn test_tsmap_failed_first_write_leaves_type_open() {
+        let tsmap = TSMap::new();
+        let too_long = "x".repeat(compression::MAX_STRING_LEN + 1);
+        
+        assert!(matches!(
+            tsmap.insert("version", DataPoint::new(1000, too_long.as_str())),
+            Err(StorageError::CompressionError(CompressionError::ValueTooLong(_)))
+        ));
+        assert_eq!(tsmap.value_type("version"), None);
+        tsmap.insert("version", DataPoint::new(1000, 2.0)).unwrap();
+        assert_eq!(tsmap.value_type("version"), Some(ValueType::Float));
+    }
+
+    #[test]
+    fn test_tsmap_rejects_unencodable_late_point() {
+        let tsmap = TSMap::new();
+        let hour = 60 * 60 * 1000;
+        tsmap.insert("version", DataPoint::new(0, "v1")).unwrap();
+        tsmap.insert("version", DataPoint::new(3 * hour, "v2")).unwrap();
+        
+        let too_long = "x".repeat(compression::MAX_STRING_LEN + 1);
+        assert!(matches!(
+            tsmap.insert("version", DataPoint::new(hour, too_long.as_str())),
+            Err(StorageError::CompressionError(CompressionError::ValueTooLong(_)))
+        ));
+        tsmap.insert("version", DataPoint::new(hour, "v1.1")).unwrap();
+        tsmap.seal_expired_blocks();
+        assert_eq!(
+            tsmap.scan_range("version", 0, u64::MAX).unwrap(),
+            vec![DataPoint::new(0, "v1"), DataPoint::new(hour, "v1.1"), DataPoint::new(3 * hour, "v2")]
+        );
+    }
+
+    #[test]
+    fn test_tsmap_labeled_series() {
+        let tsmap = TSMap::new();
+        let host_a = SeriesKey::parse(r#"cpu_usage{host="a",dc="x"}"#).unwrap();
+        let host_b = SeriesKey::parse(r#"cpu_usage{host="b",dc="x"}"#).unwrap();
+        
+        tsmap.insert(host_a.clone(), DataPoint::new(1000, 1.0)).unwrap();
+        tsmap.insert(host_b.clone(), DataPoint::new(1000, 2.0)).unwrap();
+        tsmap.insert("cpu_usage", DataPoint::new(1000, 3.0)).unwrap();
+        assert_eq!(tsmap.len(), 3);
+        
+        assert_eq!(tsmap.scan_range(&host_a, 0, 2000).unwrap(), vec![DataPoint::new(1000, 1.0)]);
+        assert_eq!(tsmap.scan_range(host_b.id(), 0, 2000).unwrap(), vec![DataPoint::new(1000, 2.0)]);
+        assert_eq!(tsmap.scan_range("cpu_usage", 0, 2000).unwrap(), vec![DataPoint::new(1000, 3.0)]);
+        assert_eq!(tsmap.series_key(host_a.id()), Some(host_a));
+        assert!(matches!(
+            tsmap.scan_range(SeriesId(7), 0, 2000),
+            Err(StorageError::SeriesNotFound(SeriesId(7)))
+        ));
+    }
+
+    #[test]
+    fn test_tsmap_rejects_series_id_collision() {
+        let tsmap = TSMap::new();
+        let key = SeriesKey::new("requests").with_label("code", "200");
+        
+        // Plant a different series under the id `key` hashes to.
+        let other = SeriesKey::new("impostor");
+        tsmap.series.insert(key.id(), Arc::new(RwLock::new(Series::new(other.clone()))));
+        
+        assert!(matches!(
+            tsmap.insert(key.clone(), DataPoint::new(1000, 1.0)),
+            Err(StorageError::SeriesIdCollision { existing, new }) if existing == other && new == key
+        ));
+    }
+
+    #[test]
+    fn test_tsmap_out_of_order_writes() {
+        let tsmap = TSMap::new();
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        // One sealed block at [0, 2h) and an open one from 2h.
+        for i in 0..15 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour / 5, i as f64)).unwrap();
+        }
+        
+        // Late points go to the buffer instead of opening blocks at their
+        // own timestamps, and are visible to scans right away.
+        tsmap.insert(key.clone(), DataPoint::new(hour + 1, 0.5)).unwrap();
+        tsmap.insert(key.clone(), DataPoint::new(2 * hour - 1, 0.25)).unwrap();
+        {
+            let series = tsmap.get_series(&key).unwrap();
+            assert_eq!(series.blocks().len(), 1);
+            assert_eq!(series.out_of_order().len(), 2);
+        }
+        let points = tsmap.scan_range(&key, hour, 2 * hour).unwrap();
+        assert_eq!(points.iter().map(|p| p.timestamp).collect::<Vec<_>>(), vec![hour, hour + 1, 6 * hour / 5, 7 * hour / 5, 8 * hour / 5, 9 * hour / 5, 2 * hour - 1, 2 * hour]);
+        
+        // Sealing the open block merges them into the sealed block they
+        // belong to, which is encoded again.
+        tsmap.insert(key.clone(), DataPoint::new(4 * hour, 20.0)).unwrap();
+        let series = tsmap.get_series(&key).unwrap();
+        assert_eq!(series.blocks().len(), 2);
+        assert!(series.out_of_order().is_empty());
+        assert_eq!(series.blocks()[0].count, 12);
+        assert_eq!(series.blocks()[0].end_timestamp, 2 * hour - 1);
+        assert!(series.blocks().windows(2).all(|w| w[0].end_timestamp < w[1].start_timestamp));
+        drop(series);
+        
+        let all = tsmap.scan_range(&key, 0, u64::MAX).unwrap();
+        assert_eq!(all.len(), 18);
+        assert!(all.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tsmap_out_of_order_into_corrupt_block() {
+        let tsmap = TSMap::new();
+        let key = "test.metric".to_string();
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..15 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour / 5, i as f64 + 0.5)).unwrap();
+        }
+        tsmap.insert(key.clone(), DataPoint::new(hour + 1, 0.25)).unwrap();
+        corrupt_block(&tsmap, &key, 0);
+        
+        // Writes that seal the open block keep succeeding; the block the
+        // buffered point belonged to is quarantined instead.
+        for i in 20..25 {
+            tsmap.insert(key.clone(), DataPoint::new(i * hour / 5, i as f64)).unwrap();
+        }
+        assert_eq!(tsmap.quarantined_blocks(&key).len(), 1);
+        let points = tsmap.scan_range(&key, 0, u64::MAX).unwrap();
+        assert_eq!(points.len(), 1 + 5 + 5);
+        assert_eq!(points[0], DataPoint::new(hour + 1, 0.25));
+        assert_eq!(points[10], DataPoint::new(24 * hour / 5, 24.0));
+    }
+
+    #[test]
+    fn test_tsmap_rejects_writes_outside_window() {
+        let config = StorageConfig::default().with_out_of_order_window(std::time::Duration::from_secs(60));
+        let tsmap = TSMap::with_config(config);
+        let key = "test.metric".to_string();
+        
+        tsmap.insert(key.clone(), DataPoint::new(120_000, 1.0)).unwrap();
+        tsmap.insert(key.clone(), DataPoint::new(60_000, 2.0)).unwrap();
+        assert!(matches!(
+            tsmap.insert(key.clone(), DataPoint::new(59_999, 3.0)),
+            Err(StorageError::Core(TsdbError::OutOfOrder { timestamp: 59_999, oldest_allowed: 60_000, .. }))
+        ));
+        assert_eq!(tsmap.scan_range(&key, 0, u64::MAX).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_tsmap_duplicate_policies() {
+        let config = StorageConfig::default()
+            .with_duplicate_policy(DuplicatePolicy::Reject)
+            .with_series_duplicate_policy("gauge", DuplicatePolicy::LastWriteWins)
+            .with_series_duplicate_policy("counter", DuplicatePolicy::FirstWriteWins);
+        let tsmap = TSMap::with_config(config);
+        let hour = 60 * 60 * 1000;
+        
+        // Each series has a sealed block and an open one, and is written
+        // twice at a timestamp in each, once late.
+        for key in ["strict", "gauge", "counter"] {
+            for i in 0..15 {
+                tsmap.insert(key, DataPoint::new(i * hour / 5, i as f64)).unwrap();
+            }
+        }
+        
+        for timestamp in [hour, 2 * hour] {
+            assert!(matches!(
+                tsmap.insert("strict", DataPoint::new(timestamp, -1.0)),
+                Err(StorageError::Core(TsdbError::DuplicateTimestamp(t))) if t == timestamp
+            ));
+            tsmap.insert("gauge", DataPoint::new(timestamp, -1.0)).unwrap();
+            tsmap.insert("counter", DataPoint::new(timestamp, -1.0)).unwrap();
+        }
+        tsmap.insert("gauge", DataPoint::new(hour, -2.0)).unwrap();
+        
+        let value_at = |key: &str, timestamp: u64| -> Vec<f64> {
+            tsmap.scan_range(key, timestamp, timestamp).unwrap().iter().map(|p| p.value.as_f64().unwrap()).collect()
+        };
+        // Late writes sit in the buffer until merged, when they are resolved.
+        tsmap.seal_expired_blocks();
+        assert_eq!(value_at("strict", hour), vec![5.0]);
+        assert_eq!(value_at("gauge", hour), vec![-2.0]);
+        assert_eq!(value_at("gauge", 2 * hour), vec![-1.0]);
+        assert_eq!(value_at("counter", hour), vec![5.0]);
+        assert_eq!(value_at("counter", 2 * hour), vec![10.0]);
+        for key in ["strict", "gauge", "counter"] {
+            assert_eq!(tsmap.scan_range(key, 0, u64::MAX).unwrap().len(), 15);
+        }
+    }
+
+    #[test]
+    fn test_tsmap_replay_wal_applies_duplicate_policy() {
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let mut wal = WriteAheadLog::create(temp_file.path()).unwrap();
+        for (timestamp, value) in [(1000, 1.0), (2000, 2.0), (1000, 3.0), (3000, 4.0), (2000, 5.0)] {
+            wal.append(WALEntry::new("cpu", DataPoint::new(timestamp, value))).unwrap();
+        }
+        
+        let replay = |policy: DuplicatePolicy| {
+            let tsmap = TSMap::with_config(StorageConfig::default().with_duplicate_policy(policy));
+            let inserted = tsmap.replay_wal(&wal).unwrap();
+            let values: Vec<f64> = tsmap.scan_range("cpu", 0, u64::MAX).unwrap().iter().map(|p| p.value.as_f64().unwrap()).collect();
+            (inserted, values)
+        };
+        assert_eq!(replay(DuplicatePolicy::Reject), (3, vec![1.0, 2.0, 4.0]));
+        assert_eq!(replay(DuplicatePolicy::FirstWriteWins), (5, vec![1.0, 2.0, 4.0]));
+        assert_eq!(replay(DuplicatePolicy::LastWriteWins), (5, vec![3.0, 5.0, 4.0]));
+        assert_eq!(replay(DuplicatePolicy::KeepAll).1.len(), 5);
+    }
+
+    #[test]
+    fn test_tsmap_replay_wal_skips_writes_outside_window() {
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        let mut wal = WriteAheadLog::create(temp_file.path()).unwrap();
+        let hour = 60 * 60 * 1000;
+        for timestamp in [1000, 4 * hour, 1500, 4 * hour + 1] {
+            wal.append(WALEntry::new("cpu", DataPoint::new(timestamp, 1.0))).unwrap();
+        }
+        
+        let tsmap = TSMap::new();
+        assert_eq!(tsmap.replay_wal(&wal).unwrap(), 3);
+        let timestamps: Vec<u64> = tsmap.scan_range("cpu", 0, u64::MAX).unwrap().iter().map(|p| p.timestamp).collect();
+        assert_eq!(timestamps, vec![1000, 4 * hour, 4 * hour + 1]);
+    }
+
+    #[test]
+    fn test_standalone_series_matches_tsmap() {
+        let config = StorageConfig::default().with_restart_interval(8);
+        let tsmap = TSMap::with_config(config.clone());
+        let mut series = Series::new("requests");
+        let hour = 60 * 60 * 1000;
+        
+        for i in 0..30 {
+            let point = DataPoint::new(i * hour / 5, (i * 10) as f64);
+            tsmap.insert("requests", point.clone()).unwrap();
+            series.add_point(point, &config).unwrap();
+        }
+        
+        let stored = tsmap.get_series("requests").unwrap();
+        assert_eq!(series.blocks().len(), 2);
+        for (own, stored) in series.blocks().iter().zip(stored.blocks()) {
+            assert_eq!(own.compressed_data, stored.compressed_data);
+        }
+        assert_eq!(series.blocks()[0].compressed_data[5], INTEGER_CODEC_ID);
+        assert_eq!(series.open_points().unwrap(), stored.open_points().unwrap());
+    }
+
+    #[test]
+    fn test_tsmap_series_metadata() {
+        let tsmap = TSMap::new();
+        let key = SeriesKey::new("rx_bytes").with_label("host", "a");
+        
+        assert_eq!(tsmap.metadata(&key), None);
+        let metadata = SeriesMetadata::new(MetricType::Counter).with_unit("bytes");
+        tsmap.set_metadata(key.clone(), metadata.clone()).unwrap();
+        assert_eq!(tsmap.metadata(&key), Some(metadata.clone()));
+        
+        // Declaring metadata creates the series, and points keep it.
+        assert_eq!(tsmap.len(), 1);
+        tsmap.insert(key.clone(), DataPoint::new(1000, 10.0)).unwrap();
+        assert_eq!(tsmap.metadata(&key), Some(metadata));
+        tsmap.insert("untyped", DataPoint::new(1000, 1.0)).unwrap();
+        assert_eq!(tsmap.metadata("untyped"), Some(SeriesMetadata::default()));
+    }
+
+    #[test]
+    fn test_tsmap_checkpoint_round_trip() {
+        let tsmap = TSMap::new();
+        let counter = SeriesKey::parse(r#"rx_bytes{host="a"}"#).unwrap();
+        let hour = 60 * 60 * 1000;
+        
+        // Two sealed blocks and an open one.
+        for i in 0..25 {
+            tsmap.insert(counter.clone(), DataPoint::new(i * hour / 5, (i * 100) as f64)).unwrap();
+        }
+        tsmap.insert("version", DataPoint::new(1000, "v2.1.0")).unwrap();
+        let metadata = SeriesMetadata::new(MetricType::Counter).with_unit("bytes").with_help("Bytes received");
+        tsmap.set_metadata(counter.clone(), metadata.clone()).unwrap();
+        
+        let temp_file = tempfile::NamedTempFile::new().unwrap();
+        tsmap.write_checkpoint(temp_file.path(), 0).unwrap();
+        let restored = TSMap::load_checkpoint(temp_file.path(), StorageConfig::default()).unwrap();
+        
+        assert_eq!(restored.len(), 2);
+        assert_eq!(restored.metadata(&counter), Some(metadata));
+        assert_eq!(restored.value_type("version"), Some(ValueType::String));
+        assert_eq!(restored.get_series(&counter).unwrap().blocks().len(), 2);
+        assert_eq!(
+            restored.scan_range(&counter, 0, u64::MAX).unwrap(),
+            tsmap.scan_range(&counter, 0, u64::MAX).unwrap()
+        );
+        assert_eq!(restored.select(&LabelMatcher::parse_selector(r#"{host="a"}"#).unwrap()), vec![counter.id()]);
+        
+        // The restored map keeps taking points in its open block.
+        restored.insert(counter.clone(), DataPoint::new(5 * hour + 1, 2500.0)).unwrap();
+        assert_eq!(restored.scan_range(&counter, 0, u64::MAX).unwrap().len(), 26);
+    }
+
+    #[test]
+    fn test_tsmap_checkpoint_skips_covered_wal_entries() {
+        let wal_file = tempfile::NamedTempFile::new().unwrap();
+        let checkpoint_file = tempfile::NamedTempFile::new().unwrap();
+        let mut wal = WriteAheadLog::create(wal_file.path()).unwrap();
+        let tsmap = TSMap::new();
+        let write = |tsmap: &TSMap, wal: &mut WriteAheadLog, timestamp: u64| {
+            let point = DataPoint::new(timestamp, timestamp as f64);
+            wal.append(WALEntry::new("cpu", point.clone())).unwrap();
+            tsmap.insert("cpu", point).unwrap();
+        };
+        for timestamp in [1000, 2000, 3000] {
+            write(&tsmap, &mut wal, timestamp);
+        }
+        tsmap.write_checkpoint(checkpoint_file.path(), wal.position()).unwrap();
+        write(&tsmap, &mut wal, 4000);
+        
+        // Only the entry logged after the checkpoint is replayed, so under
+        // KeepAll nothing is stored twice.
+        let restored = TSMap::load_checkpoint(checkpoint_file.path(), StorageConfig::default()).unwrap();
+        assert_eq!(restored.replay_wal(&wal).unwrap(), 1);
+        assert_eq!(restored.scan_range("cpu", 0, u64::MAX).unwrap(), tsmap.scan_range("cpu", 0, u64::MAX).unwrap());
+        
+        // A log truncated after a checkpoint carries on from its position,
+        // so what is written to it next is replayed in full.
+        tsmap.write_checkpoint(checkpoint_file.path(), wal.position()).unwrap();
+        wal.truncate().unwrap();
+        write(&tsmap, &mut wal, 5000);
+        write(&tsmap, &mut wal, 6000);
+        let reopened = WriteAheadLog::create(wal_file.path()).unwrap();
+        assert_eq!(reopened.position(), 6);
+        let restored = TSMap::load_checkpoint(checkpoint_file.path(), StorageConfig::default()).unwrap();
+        assert_eq!(restored.replay_wal(&reopened).unwrap(), 2);
+        assert_eq!(restored.scan_range("cpu", 0, u64::MAX).unwrap(), tsmap.scan_range("cpu", 0, u64::MAX).unwrap());
+    }
+
+    #[test]
+    fn test_tsmap_series_precision() {
+        let config = StorageConfig::default()
+            .with_precision(TimestampPrecision::Microseconds)
+            .with_series_precision("trades", TimestampPrecision::Nanoseconds);
+        let tsmap = TSMap::with_config(config);
+        let hour_ns = 3_600_000_000_000u64;
+        
+        // An hour apart: the same block in nanoseconds.
+        tsmap.insert("trades", DataPoint::new(hour_ns, 1.0)).unwrap();
+        tsmap.insert("trades", DataPoint::new(2 * hour_ns, 2.0)).unwrap();
+        tsmap.insert("trades", DataPoint::new(4 * hour_ns, 3.0)).unwrap();
+        // Half an hour apart in microseconds.
+        tsmap.insert("cpu", DataPoint::new(0, 1.0)).unwrap();
+        tsmap.insert("cpu", DataPoint::new(1_800_000_000, 2.0)).unwrap();
+        
+        let stats = tsmap.get_stats();
+        assert_eq!(stats.series[&SeriesKey::new("trades")].block_count, 2);
+        assert_eq!(stats.series[&SeriesKey::new("cpu")].block_count, 1);
+        
+        let series = tsmap.get_series("trades").unwrap();
+        let header = compression::BlockHeader::read_from(&series.blocks()[0].compressed_data).unwrap();
+        assert_eq!(header.precision, TimestampPrecision::Nanoseconds);
+        assert_eq!(tsmap.scan_range("trades", 0, u64::MAX).unwrap().len(), 3);
+    }
+}
@@ -1,124 +1,260 @@
-use tsdb_core::{TimeSeriesKey, DataPoint};
+use tsdb_core::{DataPoint, SeriesId, SeriesKey, TimestampPrecision};
 use crate::error::StorageError;
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::fs::{File, OpenOptions};
 use std::io::{Write, Read, BufReader, Seek, SeekFrom};
 use bincode;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Set in the length prefix of [`WALRecord`]s, which address series by id.
+/// Everything written now takes this form; entries written before values
+/// were typed leave it clear.
+const SERIES_RECORD_FLAG: u32 = 1 << 30;
+
+#[derive(Debug, Clone)]
 pub struct WALEntry {
-    pub key: TimeSeriesKey,
+    pub key: SeriesKey,
     pub point: DataPoint,
-    pub timestamp: u64, // WAL entry timestamp
+    /// When the entry was written, in milliseconds whatever the precision
+    /// of the point's own timestamp.
+    pub timestamp: u64,
+}
+
+/// What the log holds on disk. A series' key is written once per log, the
+/// first time it appears; its samples then carry only the id.
+#[derive(Serialize, Deserialize)]
+enum WALRecord {
+    Series { id: SeriesId, key: SeriesKey },
+    Sample { series: SeriesId, point: DataPoint, timestamp: u64 },
+    /// Starts a log that was truncated; its entries carry on from
+    /// `position` rather than from zero.
+    Truncated { position: u64 },
+}
+
+/// The layout of entries written before values were typed.
+#[derive(Deserialize)]
+struct LegacyWALEntry {
+    key: String,
+    point: LegacyDataPoint,
+    timestamp: u64,
+}
+
+#[derive(Deserialize)]
+struct LegacyDataPoint {
+    timestamp: u64,
+    value: f64,
+}
+
+impl From<LegacyWALEntry> for WALEntry {
+    fn from(entry: LegacyWALEntry) -> Self {
+        Self {
+            key: entry.key.into(),
+            point: DataPoint::new(entry.point.timestamp, entry.point.value),
+            timestamp: entry.timestamp,
+        }
+    }
 }
 
 impl WALEntry {
-    pub fn new(key: TimeSeriesKey, point: DataPoint) -> Self {
+    pub fn new(key: impl Into<SeriesKey>, point: DataPoint) -> Self {
         Self {
-            key,
+            key: key.into(),
             point,
-            timestamp: std::time::SystemTime::now()
-                .duration_since(std::time::UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as u64,
+            timestamp: TimestampPrecision::Milliseconds.now(),
         }
     }
+    
+    pub fn series_id(&self) -> SeriesId {
+        self.key.id()
+    }
 }
 
 pub struct WriteAheadLog {
     file: File,
     path: PathBuf,
+    /// The position of the log's first entry: the number of entries
+    /// truncated away before it.
+    start: u64,
     entry_count: usize,
+    /// Series whose key this handle has written to the log. A log reopened
+    /// for appending starts out empty and may write a key again, which
+    /// replay tolerates.
+    written_series: HashSet<SeriesId>,
 }
 
 impl WriteAheadLog {
+    /// Opens the log at `path` for appending, creating it if needed. The
+    /// entries already in it are read once to find where it is up to.
     pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, StorageError> {
         let path = path.as_ref().to_path_buf();
         let file = OpenOptions::new()
             .create(true)
             .append(true)
             .open(&path)?;
+        let (start, entry_count) = read_log(&path, |_, _| Ok(()))?;
             
         Ok(Self {
             file,
             path,
-            entry_count: 0,
+            start,
+            entry_count,
+            written_series: HashSet::new(),
         })
     }
     
     pub fn append(&mut self, entry: WALEntry) -> Result<(), StorageError> {
-        let encoded = bincode::serialize(&entry)
+        let id = entry.series_id();
+        if !self.written_series.contains(&id) {
+            self.write_record(&WALRecord::Series { id, key: entry.key })?;
+            self.written_series.insert(id);
+        }
+        self.write_record(&WALRecord::Sample { series: id, point: entry.point, timestamp: entry.timestamp })?;
+        self.file.flush()?;
+        
+        self.entry_count += 1;
+        Ok(())
+    }
+    
+    fn write_record(&mut self, record: &WALRecord) -> Result<(), StorageError> {
+        let encoded = bincode::serialize(record)
             .map_err(|e| StorageError::IoError(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
                 format!("Serialization error: {}", e)
             )))?;
             
         // Write length prefix
-        let len = encoded.len() as u32;
-        self.file.write_all(&len.to_le_bytes())?;
+        let len = u32::try_from(encoded.len())
+            .ok()
+            .filter(|&len| len < SERIES_RECORD_FLAG)
+            .ok_or_else(|| StorageError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "WAL entry too large"
+            )))?;
+        self.file.write_all(&(len | SERIES_RECORD_FLAG).to_le_bytes())?;
         
         // Write entry data
         self.file.write_all(&encoded)?;
-        self.file.flush()?;
-        
-        self.entry_count += 1;
         Ok(())
     }
     
-    pub fn replay<F>(&self, mut callback: F) -> Result<usize, StorageError>
+    pub fn replay<F>(&self, callback: F) -> Result<usize, StorageError>
+    where
+        F: FnMut(WALEntry) -> Result<(), StorageError>,
+    {
+        self.replay_from(0, callback)
+    }
+    
+    /// Like [`replay`](Self::replay), but skips the entries before
+    /// `position`, such as those a checkpoint taken there already holds.
+    pub fn replay_from<F>(&self, position: u64, mut callback: F) -> Result<usize, StorageError>
     where
         F: FnMut(WALEntry) -> Result<(), StorageError>,
     {
-        let file = File::open(&self.path)?;
-        let mut reader = BufReader::new(file);
         let mut count = 0;
-        
-        loop {
-            // Read length prefix
-            let mut len_bytes = [0u8; 4];
-            match reader.read_exact(&mut len_bytes) {
-                Ok(()) => {},
-                Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(StorageError::IoError(e)),
+        read_log(&self.path, |entry_position, entry| {
+            if entry_position >= position {
+                callback(entry)?;
+                count += 1;
             }
-            
-            let len = u32::from_le_bytes(len_bytes) as usize;
-            
-            // Read entry data
-            let mut entry_bytes = vec![0u8; len];
-            reader.read_exact(&mut entry_bytes)?;
-            
-            let entry: WALEntry = bincode::deserialize(&entry_bytes)
-                .map_err(|e| StorageError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Deserialization error: {}", e)
-                )))?;
-                
-            callback(entry)?;
-            count += 1;
-        }
-        
+            Ok(())
+        })?;
         Ok(count)
     }
     
+    /// Empties the log. Its [`position`](Self::position) carries on from
+    /// where it was, so a checkpoint taken before still covers only the
+    /// entries it did.
     pub fn truncate(&mut self) -> Result<(), StorageError> {
+        let position = self.position();
         self.file.set_len(0)?;
         self.file.seek(SeekFrom::Start(0))?;
+        self.start = position;
         self.entry_count = 0;
+        self.written_series.clear();
+        if position > 0 {
+            self.write_record(&WALRecord::Truncated { position })?;
+            self.file.flush()?;
+        }
         Ok(())
     }
     
+    /// The number of entries in the log.
     pub fn entry_count(&self) -> usize {
         self.entry_count
     }
     
+    /// The number of entries ever appended to the log, including those
+    /// truncated away. The next entry is appended at this position.
+    pub fn position(&self) -> u64 {
+        self.start + self.entry_count as u64
+    }
+    
     pub fn sync(&mut self) -> Result<(), StorageError> {
         self.file.sync_all()?;
         Ok(())
     }
 }
 
+/// Reads the log at `path`, passing each entry to `visit` along with its
+/// position. Returns the position of the first entry and the number of
+/// entries.
+fn read_log<F>(path: &Path, mut visit: F) -> Result<(u64, usize), StorageError>
+where
+    F: FnMut(u64, WALEntry) -> Result<(), StorageError>,
+{
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut start = 0;
+    let mut count = 0;
+    let mut series = HashMap::new();
+    
+    loop {
+        // Read length prefix
+        let mut len_bytes = [0u8; 4];
+        match reader.read_exact(&mut len_bytes) {
+            Ok(()) => {},
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(StorageError::IoError(e)),
+        }
+        
+        let prefix = u32::from_le_bytes(len_bytes);
+        let len = (prefix & !SERIES_RECORD_FLAG) as usize;
+        
+        // Read entry data
+        let mut entry_bytes = vec![0u8; len];
+        reader.read_exact(&mut entry_bytes)?;
+        
+        let invalid = |message: String| StorageError::IoError(std::io::Error::new(std::io::ErrorKind::InvalidData, message));
+        let entry = if prefix & SERIES_RECORD_FLAG != 0 {
+            match bincode::deserialize::<WALRecord>(&entry_bytes) {
+                Ok(WALRecord::Series { id, key }) => {
+                    series.insert(id, key);
+                    continue;
+                }
+                Ok(WALRecord::Sample { series: id, point, timestamp }) => {
+                    let key = series.get(&id)
+                        .ok_or_else(|| invalid(format!("Sample for unknown series {}", id)))?;
+                    Ok(WALEntry { key: key.clone(), point, timestamp })
+                }
+                Ok(WALRecord::Truncated { position }) => {
+                    start = position;
+                    continue;
+                }
+                Err(e) => Err(e),
+            }
+        } else {
+            bincode::deserialize::<LegacyWALEntry>(&entry_bytes).map(WALEntry::from)
+        };
+        let entry = entry.map_err(|e| invalid(format!("Deserialization error: {}", e)))?;
+            
+        visit(start + count as u64, entry)?;
+        count += 1;
+    }
+    
+    Ok((start, count))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,4 +324,91 @@ mod tests {
         let count = wal.replay(|_| Ok(())).unwrap();
         assert_eq!(count, 0);
     }
+
+    #[test]
+    fn test_wal_typed_values() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut wal = WriteAheadLog::create(temp_file.path()).unwrap();
+        
+        let points = vec![
+            DataPoint::new(1000, 7i64),
+            DataPoint::new(2000, true),
+            DataPoint::new(3000, "v2.1.0"),
+            DataPoint::new(4000, tsdb_core::Histogram::from_values([0.5, 12.0, 250.0])),
+        ];
+        for point in &points {
+            wal.append(WALEntry::new("metric1".to_string(), point.clone())).unwrap();
+        }
+        
+        let mut replayed = Vec::new();
+        wal.replay(|entry| {
+            replayed.push(entry.point);
+            Ok(())
+        }).unwrap();
+        assert_eq!(replayed, points);
+    }
+
+    #[test]
+    fn test_wal_replays_legacy_entries() {
+        #[derive(Serialize)]
+        struct OldEntry {
+            key: String,
+            point: (u64, f64),
+            timestamp: u64,
+        }
+        
+        let temp_file = NamedTempFile::new().unwrap();
+        {
+            let mut file = OpenOptions::new().append(true).open(temp_file.path()).unwrap();
+            let old = bincode::serialize(&OldEntry { key: "metric1".to_string(), point: (1000, 42.5), timestamp: 5 }).unwrap();
+            file.write_all(&(old.len() as u32).to_le_bytes()).unwrap();
+            file.write_all(&old).unwrap();
+        }
+        
+        let mut wal = WriteAheadLog::create(temp_file.path()).unwrap();
+        wal.append(WALEntry::new("metric1".to_string(), DataPoint::new(2000, 3i64))).unwrap();
+        
+        let mut replayed = Vec::new();
+        wal.replay(|entry| {
+            assert_eq!(entry.key, "metric1");
+            replayed.push(entry.point);
+            Ok(())
+        }).unwrap();
+        assert_eq!(replayed, vec![DataPoint::new(1000, 42.5), DataPoint::new(2000, 3i64)]);
+    }
+
+    #[test]
+    fn test_wal_labeled_series() {
+        let temp_file = NamedTempFile::new().unwrap();
+        let mut wal = WriteAheadLog::create(temp_file.path()).unwrap();
+        
+        let host_a = SeriesKey::parse(r#"cpu_usage{host="a",dc="x"}"#).unwrap();
+        let host_b = host_a.clone().with_label("host", "b");
+        for i in 0..100 {
+            wal.append(WALEntry::new(host_a.clone(), DataPoint::new(i * 1000, i as f64))).unwrap();
+            wal.append(WALEntry::new(host_b.clone(), DataPoint::new(i * 1000, 0.5))).unwrap();
+        }
+        assert_eq!(wal.entry_count(), 200);
+        
+        // Keys are written once per series; samples carry only the id.
+        let size = std::fs::metadata(temp_file.path()).unwrap().len();
+        assert!(size < 200 * 50, "{} bytes", size);
+        
+        let mut replayed = Vec::new();
+        let count = wal.replay(|entry| {
+            replayed.push(entry);
+            Ok(())
+        }).unwrap();
+        assert_eq!(count, 200);
+        assert_eq!(replayed[0].key, host_a);
+        assert_eq!(replayed[1].key, host_b);
+        assert_eq!(replayed[198].series_id(), host_a.id());
+        assert_eq!(replayed[198].point, DataPoint::new(99_000, 99.0));
+        
+        // A log reopened for appending writes the key again, which replay
+        // takes in its stride.
+        let mut reopened = WriteAheadLog::create(temp_file.path()).unwrap();
+        reopened.append(WALEntry::new(host_a.clone(), DataPoint::new(100_000, 100.0))).unwrap();
+        assert_eq!(reopened.replay(|_| Ok(())).unwrap(), 201);
+    }
 }
\ No newline at end of file
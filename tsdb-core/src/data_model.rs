@@ -1,17 +1,22 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-
-pub type TimeSeriesKey = String;
+use crate::error::TsdbError;
+use crate::metadata::SeriesMetadata;
+use crate::series::SeriesKey;
+use crate::value::{Value, ValueType};
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DataPoint {
     pub timestamp: u64,
-    pub value: f64,
+    pub value: Value,
 }
 
 impl DataPoint {
-    pub fn new(timestamp: u64, value: f64) -> Self {
-        Self { timestamp, value }
+    pub fn new(timestamp: u64, value: impl Into<Value>) -> Self {
+        Self { timestamp, value: value.into() }
+    }
+    
+    pub fn value_type(&self) -> ValueType {
+        self.value.value_type()
     }
 }
 
@@ -20,38 +25,397 @@ pub struct CompressedBlock {
     pub start_timestamp: u64,
     pub end_timestamp: u64,
     pub count: usize,
+    /// The type of every point in the block.
+    pub value_type: ValueType,
     pub compressed_data: Vec<u8>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TimeSeries {
-    pub key: TimeSeriesKey,
-    pub blocks: Vec<CompressedBlock>,
-    pub current_block: Option<Vec<DataPoint>>,
+/// What happens when a point arrives with the timestamp of one the series
+/// already holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DuplicatePolicy {
+    /// Fail the write with [`TsdbError::DuplicateTimestamp`].
+    Reject,
+    /// Replace the stored point.
+    LastWriteWins,
+    /// Keep the stored point and drop the new one.
+    FirstWriteWins,
+    /// Store both.
+    #[default]
+    KeepAll,
 }
 
-impl TimeSeries {
-    pub fn new(key: TimeSeriesKey) -> Self {
+impl DuplicatePolicy {
+    /// Collapses each run of points sharing a timestamp in `points`, which
+    /// must be sorted by timestamp with earlier writes first. A rejected
+    /// duplicate can only get here if it was let through earlier, so
+    /// [`Reject`](Self::Reject) keeps the first point.
+    pub fn dedup(self, points: &mut Vec<DataPoint>) {
+        match self {
+            DuplicatePolicy::KeepAll => {}
+            DuplicatePolicy::Reject | DuplicatePolicy::FirstWriteWins => points.dedup_by_key(|p| p.timestamp),
+            DuplicatePolicy::LastWriteWins => points.dedup_by(|later, kept| {
+                let duplicate = later.timestamp == kept.timestamp;
+                if duplicate {
+                    std::mem::swap(later, kept);
+                }
+                duplicate
+            }),
+        }
+    }
+}
+
+/// The block a series is writing to. Points go in as they arrive and come
+/// out as a [`CompressedBlock`] once a [`BlockFormat`] seals it.
+pub trait OpenBlock {
+    /// What adding a point or reading the block back can fail with. Errors
+    /// raised by the series itself, such as a point of the wrong type,
+    /// arrive as [`TsdbError`]s.
+    type Error: From<TsdbError>;
+    
+    /// The first timestamp of the block's time span.
+    fn start_time(&self) -> u64;
+    
+    /// Whether `timestamp` falls within the block's time span.
+    fn can_accept(&self, timestamp: u64) -> bool;
+    
+    fn add_point(&mut self, point: DataPoint) -> Result<(), Self::Error>;
+    
+    fn len(&self) -> usize;
+    
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    
+    /// The points added so far, in the order they arrived.
+    fn points(&self) -> Result<Vec<DataPoint>, Self::Error>;
+    
+    /// Whether the block's time span has passed.
+    fn should_seal(&self) -> bool;
+}
+
+/// Opens and seals the blocks of a series, deciding how they are encoded.
+pub trait BlockFormat {
+    type Block: OpenBlock;
+    
+    /// A block for `key` whose first point will be `first_point`.
+    fn open_block(&self, key: &SeriesKey, first_point: &DataPoint) -> Self::Block;
+    
+    /// Encodes `block` for good. Returns `None` for a block without points.
+    fn seal_block(
+        &self,
+        key: &SeriesKey,
+        block: Self::Block,
+    ) -> Result<Option<CompressedBlock>, <Self::Block as OpenBlock>::Error>;
+    
+    /// Decodes a block sealed by [`seal_block`](Self::seal_block), in time
+    /// order.
+    fn decode_block(&self, block: &CompressedBlock) -> Result<Vec<DataPoint>, <Self::Block as OpenBlock>::Error>;
+    
+    /// Whether `error`, raised decoding a sealed block, means the block's
+    /// bytes are damaged. Only such blocks are quarantined; any other
+    /// failure, such as a codec the format does not know, leaves the block
+    /// where it is.
+    fn is_corruption(&self, error: &<Self::Block as OpenBlock>::Error) -> bool;
+    
+    /// How far behind the newest point of `key` a point may be and still be
+    /// accepted, in the series' timestamp unit.
+    fn out_of_order_window(&self, key: &SeriesKey) -> u64;
+    
+    /// How points of `key` that share a timestamp are resolved. Open blocks
+    /// are expected to enforce it themselves; the series applies it to
+    /// out-of-order points.
+    fn duplicate_policy(&self, key: &SeriesKey) -> DuplicatePolicy;
+}
+
+/// Out-of-order points a series buffers before merging them into its
+/// sealed blocks.
+pub const OUT_OF_ORDER_BUFFER_CAPACITY: usize = 1024;
+
+/// A series: its identity and metadata, the blocks it has sealed, and the
+/// block it is writing to.
+///
+/// The series decides when a block is full and checks that every point
+/// holds the same type of value. How blocks are encoded is up to the
+/// [`BlockFormat`] passed in, so a series behaves the same whether it is
+/// driven through storage or used on its own with storage's format.
+///
+/// Points may arrive late, up to the format's
+/// [`out_of_order_window`](BlockFormat::out_of_order_window) behind the
+/// newest one. Those older than the open block are buffered and later
+/// merged into the sealed block whose span they fall in, which is encoded
+/// again; the blocks stay in time order and do not overlap.
+#[derive(Debug)]
+pub struct TimeSeries<B> {
+    key: SeriesKey,
+    metadata: SeriesMetadata,
+    /// Set by the first point stored; every later point must match it.
+    value_type: Option<ValueType>,
+    /// Sealed blocks, oldest first.
+    blocks: Vec<CompressedBlock>,
+    /// Sealed blocks that failed to decode. They are kept for inspection and
+    /// repair but no longer take part in reads.
+    quarantined_blocks: Vec<CompressedBlock>,
+    current_block: Option<B>,
+    /// The newest timestamp added so far, which the out-of-order window
+    /// trails.
+    newest_timestamp: Option<u64>,
+    /// Points older than the open block, waiting to be merged into the
+    /// sealed blocks.
+    out_of_order: Vec<DataPoint>,
+}
+
+impl<B: OpenBlock> TimeSeries<B> {
+    pub fn new(key: impl Into<SeriesKey>) -> Self {
         Self {
-            key,
+            key: key.into(),
+            metadata: SeriesMetadata::default(),
+            value_type: None,
             blocks: Vec::new(),
-            current_block: Some(Vec::new()),
+            quarantined_blocks: Vec::new(),
+            current_block: None,
+            newest_timestamp: None,
+            out_of_order: Vec::new(),
+        }
+    }
+    
+    /// Rebuilds a series from blocks it sealed earlier, such as those saved
+    /// in a checkpoint. Points not yet sealed are added back afterwards
+    /// with [`add_point`](Self::add_point).
+    pub fn from_blocks(
+        key: impl Into<SeriesKey>,
+        metadata: SeriesMetadata,
+        value_type: Option<ValueType>,
+        blocks: Vec<CompressedBlock>,
+        quarantined_blocks: Vec<CompressedBlock>,
+    ) -> Self {
+        Self {
+            newest_timestamp: blocks.iter().map(|block| block.end_timestamp).max(),
+            metadata,
+            value_type,
+            blocks,
+            quarantined_blocks,
+            ..Self::new(key)
+        }
+    }
+    
+    pub fn key(&self) -> &SeriesKey {
+        &self.key
+    }
+    
+    pub fn metadata(&self) -> &SeriesMetadata {
+        &self.metadata
+    }
+    
+    pub fn set_metadata(&mut self, metadata: SeriesMetadata) {
+        self.metadata = metadata;
+    }
+    
+    pub fn value_type(&self) -> Option<ValueType> {
+        self.value_type
+    }
+    
+    pub fn blocks(&self) -> &[CompressedBlock] {
+        &self.blocks
+    }
+    
+    pub fn quarantined_blocks(&self) -> &[CompressedBlock] {
+        &self.quarantined_blocks
+    }
+    
+    pub fn current_block(&self) -> Option<&B> {
+        self.current_block.as_ref()
+    }
+    
+    pub fn out_of_order(&self) -> &[DataPoint] {
+        &self.out_of_order
+    }
+    
+    /// Moves the sealed block at `index` to quarantine, for a block found to
+    /// be corrupt while reading it.
+    pub fn quarantine_block(&mut self, index: usize) {
+        let block = self.blocks.remove(index);
+        self.quarantined_blocks.push(block);
+    }
+    
+    /// Adds `point` to the open block. A point past the open block's span
+    /// seals it and opens a new one; a point before it is buffered as out
+    /// of order. Fails with [`TsdbError::OutOfOrder`] if the point is
+    /// further behind the newest one than the out-of-order window allows.
+    pub fn add_point<F: BlockFormat<Block = B>>(&mut self, point: DataPoint, format: &F) -> Result<(), B::Error> {
+        let value_type = point.value_type();
+        if let Some(expected) = self.value_type.filter(|&expected| expected != value_type) {
+            return Err(TsdbError::TypeMismatch { key: self.key.to_string(), expected, actual: value_type }.into());
+        }
+        
+        let timestamp = point.timestamp;
+        if let Some(newest) = self.newest_timestamp {
+            let oldest_allowed = newest.saturating_sub(format.out_of_order_window(&self.key));
+            if timestamp < oldest_allowed {
+                return Err(TsdbError::OutOfOrder { key: self.key.to_string(), timestamp, oldest_allowed }.into());
+            }
+        }
+        
+        let late = match self.current_block {
+            Some(ref block) => timestamp < block.start_time(),
+            None => self.blocks.last().is_some_and(|block| timestamp <= block.end_timestamp),
+        };
+        if late {
+            if format.duplicate_policy(&self.key) == DuplicatePolicy::Reject && self.holds_late_timestamp(timestamp, format)? {
+                return Err(TsdbError::DuplicateTimestamp(timestamp).into());
+            }
+            // A point the format cannot encode must fail now rather than
+            // sit in the buffer and fail every merge after it.
+            format.open_block(&self.key, &point).add_point(point.clone())?;
+            self.out_of_order.push(point);
+            if self.out_of_order.len() >= OUT_OF_ORDER_BUFFER_CAPACITY {
+                // The point is stored either way; whatever fails to merge
+                // stays buffered for the next flush.
+                let _ = self.flush_out_of_order(format);
+            }
+        } else if let Some(block) = self.current_block.as_mut().filter(|block| block.can_accept(timestamp)) {
+            block.add_point(point)?;
+        } else {
+            let mut block = format.open_block(&self.key, &point);
+            block.add_point(point)?;
+            if let Some(previous) = self.current_block.replace(block) {
+                self.push_sealed(previous, format)?;
+            }
+            let _ = self.flush_out_of_order(format);
         }
+        
+        // Only a stored point fixes the type, so a first write that fails
+        // leaves the series free to take any.
+        self.value_type = Some(value_type);
+        self.newest_timestamp = Some(self.newest_timestamp.map_or(timestamp, |newest| newest.max(timestamp)));
+        Ok(())
+    }
+    
+    /// Whether `timestamp`, which is older than the open block, is already
+    /// buffered or in the sealed block covering it.
+    fn holds_late_timestamp<F: BlockFormat<Block = B>>(&self, timestamp: u64, format: &F) -> Result<bool, B::Error> {
+        if self.out_of_order.iter().any(|p| p.timestamp == timestamp) {
+            return Ok(true);
+        }
+        let position = self.blocks.partition_point(|block| block.start_timestamp <= timestamp);
+        match position.checked_sub(1).map(|index| &self.blocks[index]) {
+            Some(block) if timestamp <= block.end_timestamp => {
+                Ok(format.decode_block(block)?.iter().any(|p| p.timestamp == timestamp))
+            }
+            _ => Ok(false),
+        }
+    }
+    
+    /// Seals the open block, if any, and appends it to the sealed blocks.
+    /// Buffered out-of-order points are merged in along with it; any that
+    /// fail to merge stay buffered rather than failing the seal.
+    pub fn seal_current_block<F: BlockFormat<Block = B>>(&mut self, format: &F) -> Result<(), B::Error> {
+        if let Some(block) = self.current_block.take() {
+            self.push_sealed(block, format)?;
+        }
+        let _ = self.flush_out_of_order(format);
+        Ok(())
+    }
+    
+    fn push_sealed<F: BlockFormat<Block = B>>(&mut self, block: B, format: &F) -> Result<(), B::Error> {
+        if let Some(sealed) = format.seal_block(&self.key, block)? {
+            self.blocks.push(sealed);
+        }
+        Ok(())
     }
     
-    pub fn add_point(&mut self, point: DataPoint) {
-        if let Some(ref mut current) = self.current_block {
-            current.push(point);
+    /// Merges the buffered out-of-order points into the sealed blocks whose
+    /// span they fall in, encoding each of those blocks again. Points that
+    /// fall in no sealed block's span get a block of their own, and points
+    /// that share a timestamp are resolved by the format's
+    /// [`duplicate_policy`](BlockFormat::duplicate_policy). A block that
+    /// turns out to be [corrupt](BlockFormat::is_corruption) is moved to
+    /// quarantine and its points are merged as if it were not there. Points
+    /// not merged because of an error stay buffered.
+    pub fn flush_out_of_order<F: BlockFormat<Block = B>>(&mut self, format: &F) -> Result<(), B::Error> {
+        let mut pending = std::mem::take(&mut self.out_of_order);
+        pending.sort_by_key(|p| p.timestamp);
+        
+        let mut merged = 0;
+        while merged < pending.len() {
+            match self.merge_into_block(&pending[merged..], format) {
+                Ok(count) => merged += count,
+                Err(e) => {
+                    self.out_of_order = pending.split_off(merged);
+                    return Err(e);
+                }
+            }
         }
+        Ok(())
     }
     
-    pub fn seal_current_block(&mut self) -> Option<Vec<DataPoint>> {
-        self.current_block.take().map(|points| {
-            if !points.is_empty() {
-                self.current_block = Some(Vec::new());
+    /// Merges the leading points of `pending`, which is sorted, into the
+    /// one block they belong to. Returns how many points were merged, which
+    /// is none only if the block they belong to was quarantined instead.
+    fn merge_into_block<F: BlockFormat<Block = B>>(&mut self, pending: &[DataPoint], format: &F) -> Result<usize, B::Error> {
+        let first = &pending[0];
+        let position = self.blocks.partition_point(|block| block.start_timestamp <= first.timestamp);
+        // Points from the start of the next block on belong to that block.
+        let next_start = self.blocks.get(position)
+            .map(|block| block.start_timestamp)
+            .or_else(|| self.current_block.as_ref().map(B::start_time))
+            .unwrap_or(u64::MAX);
+        
+        // The block starting before the first point, if its span reaches it.
+        let mut existing = None;
+        if let Some(index) = position.checked_sub(1) {
+            let points = match format.decode_block(&self.blocks[index]) {
+                Ok(points) => points,
+                Err(e) if format.is_corruption(&e) => {
+                    self.quarantine_block(index);
+                    return Ok(0);
+                }
+                Err(e) => return Err(e),
+            };
+            if let Some(block_start) = points.first() {
+                let block = format.open_block(&self.key, block_start);
+                if block.can_accept(first.timestamp) {
+                    existing = Some((index, points, block));
+                }
             }
-            points
-        })
+        }
+        let (replaced, mut points, mut block) = match existing {
+            Some((index, points, block)) => (Some(index), points, block),
+            None => (None, Vec::new(), format.open_block(&self.key, first)),
+        };
+        
+        let count = pending.iter()
+            .take_while(|p| p.timestamp < next_start && block.can_accept(p.timestamp))
+            .count();
+        points.extend_from_slice(&pending[..count]);
+        points.sort_by_key(|p| p.timestamp);
+        format.duplicate_policy(&self.key).dedup(&mut points);
+        for point in points {
+            block.add_point(point)?;
+        }
+        
+        match (format.seal_block(&self.key, block)?, replaced) {
+            (Some(sealed), Some(index)) => self.blocks[index] = sealed,
+            (Some(sealed), None) => self.blocks.insert(position, sealed),
+            (None, _) => {}
+        }
+        Ok(count)
+    }
+    
+    /// Seals the open block if its time span has passed.
+    pub fn seal_if_expired<F: BlockFormat<Block = B>>(&mut self, format: &F) -> Result<(), B::Error> {
+        match self.current_block {
+            Some(ref block) if block.should_seal() => self.seal_current_block(format),
+            _ => Ok(()),
+        }
+    }
+    
+    /// The points of the open block, followed by the buffered out-of-order
+    /// points: everything not yet in a sealed block.
+    pub fn open_points(&self) -> Result<Vec<DataPoint>, B::Error> {
+        let mut points = self.current_block.as_ref().map_or(Ok(Vec::new()), B::points)?;
+        points.extend_from_slice(&self.out_of_order);
+        Ok(points)
     }
     
     pub fn point_count(&self) -> usize {
@@ -59,7 +423,7 @@ impl TimeSeries {
         let current_points = self.current_block.as_ref()
             .map(|b| b.len())
             .unwrap_or(0);
-        block_points + current_points
+        block_points + current_points + self.out_of_order.len()
     }
 }
 
@@ -85,59 +449,282 @@ mod tests {
     }
 
     #[test]
-    fn test_timeseries_creation() {
-        let ts = TimeSeries::new("test.metric".to_string());
-        assert_eq!(ts.key, "test.metric");
-        assert!(ts.blocks.is_empty());
-        assert!(ts.current_block.is_some());
-        assert_eq!(ts.point_count(), 0);
+    fn test_datapoint_typed_values() {
+        assert_eq!(DataPoint::new(1000, 7i64).value_type(), ValueType::Integer);
+        assert_eq!(DataPoint::new(1000, false).value, Value::Boolean(false));
+        assert_eq!(DataPoint::new(1000, "v2.1.0").value.as_str(), Some("v2.1.0"));
+        assert_ne!(DataPoint::new(1000, 1i64), DataPoint::new(1000, 1.0));
     }
 
-    #[test]
-    fn test_timeseries_add_point() {
-        let mut ts = TimeSeries::new("test.metric".to_string());
-        let point = DataPoint::new(1609459200000, 42.5);
+    /// Keeps points as they are, in blocks spanning 100 time units, and
+    /// seals them into blocks of raw timestamps and float values. NaN is
+    /// refused, standing in for anything a real codec cannot encode, and
+    /// blocks of any other type cannot be decoded.
+    #[derive(Debug)]
+    struct Buffer {
+        start_time: u64,
+        points: Vec<DataPoint>,
+    }
+
+    struct BufferFormat(DuplicatePolicy);
+
+    impl OpenBlock for Buffer {
+        type Error = TsdbError;
+        
+        fn start_time(&self) -> u64 {
+            self.start_time
+        }
+        
+        fn can_accept(&self, timestamp: u64) -> bool {
+            (self.start_time..self.start_time + 100).contains(&timestamp)
+        }
         
-        ts.add_point(point.clone());
-        assert_eq!(ts.point_count(), 1);
+        fn add_point(&mut self, point: DataPoint) -> Result<(), TsdbError> {
+            match point.value.as_f64() {
+                Some(value) if value.is_nan() => return Err(TsdbError::InvalidValue(value)),
+                _ => {}
+            }
+            self.points.push(point);
+            Ok(())
+        }
+        
+        fn len(&self) -> usize {
+            self.points.len()
+        }
+        
+        fn points(&self) -> Result<Vec<DataPoint>, TsdbError> {
+            Ok(self.points.clone())
+        }
+        
+        fn should_seal(&self) -> bool {
+            self.points.len() >= 3
+        }
+    }
+
+    impl BlockFormat for BufferFormat {
+        type Block = Buffer;
+        
+        fn open_block(&self, _key: &SeriesKey, first_point: &DataPoint) -> Buffer {
+            Buffer { start_time: first_point.timestamp, points: Vec::new() }
+        }
+        
+        fn seal_block(&self, _key: &SeriesKey, mut block: Buffer) -> Result<Option<CompressedBlock>, TsdbError> {
+            block.points.sort_by_key(|p| p.timestamp);
+            let (Some(first), Some(last)) = (block.points.first(), block.points.last()) else {
+                return Ok(None);
+            };
+            let compressed_data = block.points.iter()
+                .flat_map(|p| [p.timestamp, p.value.as_f64().unwrap().to_bits()])
+                .flat_map(u64::to_le_bytes)
+                .collect();
+            Ok(Some(CompressedBlock {
+                start_timestamp: first.timestamp,
+                end_timestamp: last.timestamp,
+                count: block.points.len(),
+                value_type: ValueType::Float,
+                compressed_data,
+            }))
+        }
+        
+        fn decode_block(&self, block: &CompressedBlock) -> Result<Vec<DataPoint>, TsdbError> {
+            if block.value_type != ValueType::Float {
+                return Err(TsdbError::StorageError(format!("cannot decode {} blocks", block.value_type)));
+            }
+            if block.compressed_data.len() != block.count * 16 {
+                return Err(TsdbError::CompressionError("truncated block".to_string()));
+            }
+            let words: Vec<u64> = block.compressed_data.chunks(8)
+                .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+                .collect();
+            Ok(words.chunks(2).map(|p| DataPoint::new(p[0], f64::from_bits(p[1]))).collect())
+        }
+        
+        fn is_corruption(&self, error: &TsdbError) -> bool {
+            matches!(error, TsdbError::CompressionError(_))
+        }
+        
+        fn out_of_order_window(&self, _key: &SeriesKey) -> u64 {
+            250
+        }
         
-        if let Some(ref block) = ts.current_block {
-            assert_eq!(block.len(), 1);
-            assert_eq!(block[0], point);
+        fn duplicate_policy(&self, _key: &SeriesKey) -> DuplicatePolicy {
+            self.0
         }
     }
 
+    const BUFFER: BufferFormat = BufferFormat(DuplicatePolicy::KeepAll);
+
+    fn timestamps(points: &[DataPoint]) -> Vec<u64> {
+        points.iter().map(|p| p.timestamp).collect()
+    }
+
     #[test]
-    fn test_timeseries_multiple_points() {
-        let mut ts = TimeSeries::new("test.metric".to_string());
+    fn test_timeseries_add_point() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        assert_eq!(ts.key, "test.metric");
+        assert_eq!(ts.point_count(), 0);
         
-        for i in 0..5 {
-            let point = DataPoint::new(1609459200000 + i * 1000, i as f64);
-            ts.add_point(point);
+        for timestamp in [1000, 1050, 1100, 1150, 1300] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &BUFFER).unwrap();
         }
+        assert_eq!(ts.point_count(), 5);
+        assert_eq!(ts.blocks.iter().map(|b| (b.start_timestamp, b.count)).collect::<Vec<_>>(), vec![(1000, 2), (1100, 2)]);
+        assert_eq!(ts.open_points().unwrap(), vec![DataPoint::new(1300, 1.0)]);
         
+        assert!(matches!(
+            ts.add_point(DataPoint::new(1310, 7i64), &BUFFER),
+            Err(TsdbError::TypeMismatch { expected: ValueType::Float, actual: ValueType::Integer, .. })
+        ));
         assert_eq!(ts.point_count(), 5);
     }
 
     #[test]
     fn test_timeseries_seal_block() {
-        let mut ts = TimeSeries::new("test.metric".to_string());
-        
-        ts.add_point(DataPoint::new(1609459200000, 1.0));
-        ts.add_point(DataPoint::new(1609459201000, 2.0));
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        ts.seal_current_block(&BUFFER).unwrap();
+        assert!(ts.blocks.is_empty());
         
-        let sealed = ts.seal_current_block();
-        assert!(sealed.is_some());
-        assert_eq!(sealed.unwrap().len(), 2);
-        assert_eq!(ts.point_count(), 0);
+        ts.add_point(DataPoint::new(1000, 1.0), &BUFFER).unwrap();
+        ts.add_point(DataPoint::new(1001, 2.0), &BUFFER).unwrap();
+        ts.seal_if_expired(&BUFFER).unwrap();
         assert!(ts.current_block.is_some());
+        
+        ts.add_point(DataPoint::new(1002, 3.0), &BUFFER).unwrap();
+        ts.seal_if_expired(&BUFFER).unwrap();
+        assert!(ts.current_block.is_none());
+        assert_eq!(ts.blocks.len(), 1);
+        assert_eq!(ts.point_count(), 3);
+    }
+
+    #[test]
+    fn test_timeseries_out_of_order() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        for timestamp in [1000, 1010, 1120, 1130, 1250] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &BUFFER).unwrap();
+        }
+        assert_eq!(ts.blocks.len(), 2);
+        
+        // Late points are buffered rather than opening blocks of their own.
+        ts.add_point(DataPoint::new(1005, 2.0), &BUFFER).unwrap();
+        ts.add_point(DataPoint::new(1125, 2.0), &BUFFER).unwrap();
+        ts.add_point(DataPoint::new(1240, 2.0), &BUFFER).unwrap();
+        assert_eq!(ts.blocks.len(), 2);
+        assert_eq!(ts.point_count(), 8);
+        assert_eq!(timestamps(&ts.open_points().unwrap()), vec![1250, 1005, 1125, 1240]);
+        
+        assert!(matches!(
+            ts.add_point(DataPoint::new(999, 1.0), &BUFFER),
+            Err(TsdbError::OutOfOrder { timestamp: 999, oldest_allowed: 1000, .. })
+        ));
+        
+        // Flushing merges each point into the block its span covers; 1240 is
+        // past the span of the block at 1120, so it gets one of its own.
+        ts.flush_out_of_order(&BUFFER).unwrap();
+        let blocks: Vec<Vec<u64>> = ts.blocks.iter()
+            .map(|block| timestamps(&BUFFER.decode_block(block).unwrap()))
+            .collect();
+        assert_eq!(blocks, vec![vec![1000, 1005, 1010], vec![1120, 1125, 1130], vec![1240]]);
+        assert!(ts.out_of_order.is_empty());
+        assert_eq!(ts.point_count(), 8);
+    }
+
+    #[test]
+    fn test_duplicate_policy_dedup() {
+        let points = vec![
+            DataPoint::new(1000, 1.0),
+            DataPoint::new(1000, 2.0),
+            DataPoint::new(1010, 3.0),
+            DataPoint::new(1020, 4.0),
+            DataPoint::new(1020, 5.0),
+            DataPoint::new(1020, 6.0),
+        ];
+        let dedup = |policy: DuplicatePolicy| {
+            let mut points = points.clone();
+            policy.dedup(&mut points);
+            points.iter().map(|p| p.value.as_f64().unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(dedup(DuplicatePolicy::LastWriteWins), vec![2.0, 3.0, 6.0]);
+        assert_eq!(dedup(DuplicatePolicy::FirstWriteWins), vec![1.0, 3.0, 4.0]);
+        assert_eq!(dedup(DuplicatePolicy::Reject), vec![1.0, 3.0, 4.0]);
+        assert_eq!(dedup(DuplicatePolicy::KeepAll).len(), 6);
+    }
+
+    #[test]
+    fn test_timeseries_late_duplicates() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        let format = BufferFormat(DuplicatePolicy::Reject);
+        for timestamp in [1000, 1010, 1120] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &format).unwrap();
+        }
+        
+        // Late points are checked against the sealed block and the buffer.
+        assert!(matches!(ts.add_point(DataPoint::new(1010, 2.0), &format), Err(TsdbError::DuplicateTimestamp(1010))));
+        ts.add_point(DataPoint::new(1005, 2.0), &format).unwrap();
+        assert!(matches!(ts.add_point(DataPoint::new(1005, 3.0), &format), Err(TsdbError::DuplicateTimestamp(1005))));
+        assert_eq!(ts.point_count(), 4);
+        
+        // Other policies resolve late duplicates as they are merged.
+        let format = BufferFormat(DuplicatePolicy::LastWriteWins);
+        ts.add_point(DataPoint::new(1010, 4.0), &format).unwrap();
+        ts.flush_out_of_order(&format).unwrap();
+        assert_eq!(
+            BUFFER.decode_block(&ts.blocks[0]).unwrap(),
+            vec![DataPoint::new(1000, 1.0), DataPoint::new(1005, 2.0), DataPoint::new(1010, 4.0)]
+        );
+    }
+
+    #[test]
+    fn test_timeseries_flush_quarantines_corrupt_block() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        for timestamp in [1000, 1010, 1120] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &BUFFER).unwrap();
+        }
+        ts.add_point(DataPoint::new(1005, 2.0), &BUFFER).unwrap();
+        ts.blocks[0].compressed_data.pop();
+        
+        // Sealing the open block merges the buffer, which cannot decode the
+        // block it belongs to. The writes still succeed and nothing is lost.
+        ts.add_point(DataPoint::new(1230, 3.0), &BUFFER).unwrap();
+        ts.add_point(DataPoint::new(1240, 3.0), &BUFFER).unwrap();
+        assert_eq!(ts.quarantined_blocks.len(), 1);
+        assert_eq!(ts.quarantined_blocks[0].start_timestamp, 1000);
+        assert!(ts.out_of_order.is_empty());
+        let blocks: Vec<Vec<u64>> = ts.blocks.iter()
+            .map(|block| timestamps(&BUFFER.decode_block(block).unwrap()))
+            .collect();
+        assert_eq!(blocks, vec![vec![1005], vec![1120]]);
+        assert_eq!(timestamps(&ts.open_points().unwrap()), vec![1230, 1240]);
+    }
+
+    #[test]
+    fn test_timeseries_late_point_must_encode() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        for timestamp in [1000, 1010, 1120] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &BUFFER).unwrap();
+        }
+        
+        // Refused before it is buffered, so it cannot hold up later merges.
+        assert!(matches!(ts.add_point(DataPoint::new(1005, f64::NAN), &BUFFER), Err(TsdbError::InvalidValue(_))));
+        assert!(ts.out_of_order.is_empty());
+        ts.add_point(DataPoint::new(1005, 2.0), &BUFFER).unwrap();
+        ts.flush_out_of_order(&BUFFER).unwrap();
+        assert_eq!(timestamps(&BUFFER.decode_block(&ts.blocks[0]).unwrap()), vec![1000, 1005, 1010]);
     }
 
     #[test]
-    fn test_timeseries_seal_empty_block() {
-        let mut ts = TimeSeries::new("test.metric".to_string());
-        let sealed = ts.seal_current_block();
-        assert!(sealed.is_some());
-        assert_eq!(sealed.unwrap().len(), 0);
+    fn test_timeseries_flush_keeps_undecodable_block() {
+        let mut ts: TimeSeries<Buffer> = TimeSeries::new("test.metric");
+        for timestamp in [1000, 1010, 1120] {
+            ts.add_point(DataPoint::new(timestamp, 1.0), &BUFFER).unwrap();
+        }
+        ts.add_point(DataPoint::new(1005, 2.0), &BUFFER).unwrap();
+        ts.blocks[0].value_type = ValueType::Integer;
+        
+        // The block is intact, just not one the format can read: it stays
+        // put and so does the point that belongs to it.
+        assert!(matches!(ts.flush_out_of_order(&BUFFER), Err(TsdbError::StorageError(_))));
+        assert!(ts.quarantined_blocks.is_empty());
+        assert_eq!(ts.blocks.len(), 1);
+        assert_eq!(timestamps(&ts.out_of_order), vec![1005]);
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,4 @@
+use crate::value::ValueType;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -8,6 +9,21 @@ pub enum TsdbError {
     #[error("Invalid value: {0}")]
     InvalidValue(f64),
     
+    #[error("Invalid series key: {0}")]
+    InvalidSeriesKey(String),
+    
+    #[error("Invalid series selector: {0}")]
+    InvalidSelector(String),
+    
+    #[error("Series {key} holds {expected} values, got a {actual} value")]
+    TypeMismatch { key: String, expected: ValueType, actual: ValueType },
+    
+    #[error("Point at {timestamp} is too old for series {key}: its out-of-order window starts at {oldest_allowed}")]
+    OutOfOrder { key: String, timestamp: u64, oldest_allowed: u64 },
+    
+    #[error("A point at {0} already exists")]
+    DuplicateTimestamp(u64),
+    
     #[error("Time series not found: {0}")]
     TimeSeriesNotFound(String),
     
@@ -0,0 +1,310 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Scale new histograms start at: bucket bounds grow by 2^(1/8), about 9%,
+/// which keeps quantiles within about 4.3% of the true value.
+pub const DEFAULT_HISTOGRAM_SCALE: i8 = 3;
+
+/// Finest scale a histogram can use.
+pub const MAX_HISTOGRAM_SCALE: i8 = 8;
+
+/// Coarsest scale a histogram can use: each bucket spans a factor of 2^16.
+pub const MIN_HISTOGRAM_SCALE: i8 = -4;
+
+/// Buckets a histogram keeps before it halves its resolution to make room.
+pub const MAX_HISTOGRAM_BUCKETS: usize = 160;
+
+/// A distribution of values with exponentially sized buckets.
+///
+/// At scale `s` bucket `i` holds the values whose magnitude lies in
+/// `(base^(i-1), base^i]`, where `base = 2^(2^-s)`. Positive and negative
+/// values have their own buckets and zeros are counted apart. Sum, minimum
+/// and maximum are kept exactly.
+///
+/// Any two histograms can be merged: the finer one is brought down to the
+/// scale of the coarser, where every bucket lines up with whole buckets of
+/// the finer one. When a histogram grows past [`MAX_HISTOGRAM_BUCKETS`] it
+/// lowers its own scale the same way, so its size stays bounded whatever
+/// range of values it sees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub scale: i8,
+    pub zero_count: u64,
+    pub sum: f64,
+    /// Smallest value recorded; positive infinity while empty.
+    pub min: f64,
+    /// Largest value recorded; negative infinity while empty.
+    pub max: f64,
+    /// Counts of positive values by bucket index.
+    pub positive: BTreeMap<i32, u64>,
+    /// Counts of negative values by the bucket index of their magnitude.
+    pub negative: BTreeMap<i32, u64>,
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Self::with_scale(DEFAULT_HISTOGRAM_SCALE)
+    }
+    
+    /// An empty histogram at `scale`, clamped to the supported range.
+    pub fn with_scale(scale: i8) -> Self {
+        Self {
+            scale: scale.clamp(MIN_HISTOGRAM_SCALE, MAX_HISTOGRAM_SCALE),
+            zero_count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+        }
+    }
+    
+    pub fn from_values(values: impl IntoIterator<Item = f64>) -> Self {
+        let mut histogram = Self::new();
+        for value in values {
+            histogram.record(value);
+        }
+        histogram
+    }
+    
+    pub fn record(&mut self, value: f64) {
+        self.record_n(value, 1);
+    }
+    
+    /// Records `value` `count` times. NaN is ignored.
+    pub fn record_n(&mut self, value: f64, count: u64) {
+        if value.is_nan() || count == 0 {
+            return;
+        }
+        
+        self.sum += value * count as f64;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if value == 0.0 {
+            self.zero_count += count;
+        } else {
+            let buckets = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+            *buckets.entry(bucket_index(value.abs(), self.scale)).or_insert(0) += count;
+            self.limit_buckets();
+        }
+    }
+    
+    /// Number of values recorded.
+    pub fn count(&self) -> u64 {
+        self.zero_count + self.positive.values().sum::<u64>() + self.negative.values().sum::<u64>()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+    
+    pub fn mean(&self) -> Option<f64> {
+        match self.count() {
+            0 => None,
+            count => Some(self.sum / count as f64),
+        }
+    }
+    
+    /// Adds every value of `other` to this histogram, at the coarser of the
+    /// two scales.
+    pub fn merge(&mut self, other: &Histogram) {
+        self.downscale(other.scale);
+        let shift = other.scale.saturating_sub(self.scale).max(0) as u32;
+        for (buckets, other_buckets) in [(&mut self.positive, &other.positive), (&mut self.negative, &other.negative)] {
+            for (&index, &count) in other_buckets {
+                *buckets.entry(downscale_index(index, shift)).or_insert(0) += count;
+            }
+        }
+        
+        self.zero_count += other.zero_count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.limit_buckets();
+    }
+    
+    /// Lowers the resolution to `scale`, merging neighbouring buckets. Does
+    /// nothing if the histogram is already at or below it.
+    pub fn downscale(&mut self, scale: i8) {
+        let scale = scale.max(MIN_HISTOGRAM_SCALE);
+        if scale >= self.scale {
+            return;
+        }
+        
+        let shift = (self.scale - scale) as u32;
+        for buckets in [&mut self.positive, &mut self.negative] {
+            let mut merged = BTreeMap::new();
+            for (&index, &count) in buckets.iter() {
+                *merged.entry(downscale_index(index, shift)).or_insert(0) += count;
+            }
+            *buckets = merged;
+        }
+        self.scale = scale;
+    }
+    
+    /// Estimates the value below which a fraction `q` of the values fall.
+    /// The estimate is within the relative error of the scale, and always
+    /// between the minimum and maximum. `None` when empty or when `q` is
+    /// not in `[0, 1]`.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        
+        // Zero-based rank of the wanted value, walking from the most
+        // negative value up. The extremes are known exactly.
+        let rank = (q * (count - 1) as f64).round() as u64;
+        if rank == 0 {
+            return Some(self.min);
+        }
+        if rank == count - 1 {
+            return Some(self.max);
+        }
+        let mut seen = 0;
+        let negative = self.negative.iter().rev().map(|(&index, &count)| (-self.bucket_midpoint(index), count));
+        let zero = std::iter::once((0.0, self.zero_count));
+        let positive = self.positive.iter().map(|(&index, &count)| (self.bucket_midpoint(index), count));
+        
+        for (value, bucket_count) in negative.chain(zero).chain(positive) {
+            seen += bucket_count;
+            if seen > rank {
+                return Some(value.clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+    
+    /// The magnitudes bucket `index` holds at the current scale, as
+    /// `(lower, upper]`.
+    pub fn bucket_bounds(&self, index: i32) -> (f64, f64) {
+        (bucket_upper_bound(index.saturating_sub(1), self.scale), bucket_upper_bound(index, self.scale))
+    }
+    
+    /// The point of bucket `index` with the same relative distance to both
+    /// of its bounds.
+    fn bucket_midpoint(&self, index: i32) -> f64 {
+        let base = bucket_base(self.scale);
+        2.0 * bucket_upper_bound(index, self.scale) / (base + 1.0)
+    }
+    
+    fn limit_buckets(&mut self) {
+        while self.positive.len() + self.negative.len() > MAX_HISTOGRAM_BUCKETS && self.scale > MIN_HISTOGRAM_SCALE {
+            self.downscale(self.scale - 1);
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "histogram(count={}, sum={})", self.count(), self.sum)
+    }
+}
+
+fn bucket_base(scale: i8) -> f64 {
+    2f64.powf(2f64.powi(-(scale as i32)))
+}
+
+fn bucket_upper_bound(index: i32, scale: i8) -> f64 {
+    2f64.powf(index as f64 * 2f64.powi(-(scale as i32)))
+}
+
+fn bucket_index(magnitude: f64, scale: i8) -> i32 {
+    // Saturates for infinities and the far ends of the subnormals.
+    (magnitude.log2() * 2f64.powi(scale as i32)).ceil() as i32
+}
+
+/// The bucket that `index` falls in once the scale is lowered by `shift`:
+/// each new bucket covers `2^shift` old ones.
+fn downscale_index(index: i32, shift: u32) -> i32 {
+    // Past 33 every index already lands in bucket 0 or 1.
+    let shift = shift.min(33);
+    let index = index as i64;
+    ((index + (1 << shift) - 1) >> shift) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_record() {
+        let histogram = Histogram::from_values([0.0, 1.0, 2.0, 2.0, -3.0, f64::NAN]);
+        
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.zero_count, 1);
+        assert_eq!(histogram.sum, 2.0);
+        assert_eq!((histogram.min, histogram.max), (-3.0, 2.0));
+        assert_eq!(histogram.positive.values().sum::<u64>(), 3);
+        assert_eq!(histogram.negative.values().sum::<u64>(), 1);
+        
+        // Powers of two are upper bounds of their bucket.
+        let (lower, upper) = histogram.bucket_bounds(8);
+        assert_eq!(upper, 2.0);
+        assert!(lower < 2.0 && lower > 1.8);
+        assert_eq!(histogram.positive.get(&8), Some(&2));
+    }
+
+    #[test]
+    fn test_histogram_quantiles() {
+        let values: Vec<f64> = (1..=1000).map(|i| i as f64).collect();
+        let histogram = Histogram::from_values(values.iter().copied());
+        
+        for (q, exact) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+            let estimate = histogram.quantile(q).unwrap();
+            assert!((estimate - exact).abs() / exact < 0.05, "q{} = {}", q, estimate);
+        }
+        assert_eq!(histogram.quantile(0.0), Some(1.0));
+        assert_eq!(histogram.quantile(1.0), Some(1000.0));
+        assert_eq!(histogram.quantile(1.5), None);
+        assert_eq!(Histogram::new().quantile(0.5), None);
+        
+        let mixed = Histogram::from_values([-100.0, -10.0, 0.0, 10.0, 100.0]);
+        assert_eq!(mixed.quantile(0.5), Some(0.0));
+        assert!(mixed.quantile(0.25).unwrap() < -9.0);
+    }
+
+    #[test]
+    fn test_histogram_merge() {
+        let mut fine = Histogram::with_scale(5);
+        let mut coarse = Histogram::with_scale(1);
+        for i in 1..=100 {
+            fine.record(i as f64);
+            coarse.record(i as f64 * 10.0);
+        }
+        
+        let mut merged = fine.clone();
+        merged.merge(&coarse);
+        assert_eq!(merged.scale, 1);
+        assert_eq!(merged.count(), 200);
+        assert_eq!(merged.sum, fine.sum + coarse.sum);
+        assert_eq!((merged.min, merged.max), (1.0, 1000.0));
+        
+        // Merging is the same as recording everything at the coarser scale.
+        let mut direct = Histogram::with_scale(1);
+        for i in 1..=100 {
+            direct.record(i as f64);
+            direct.record(i as f64 * 10.0);
+        }
+        assert_eq!(merged.positive, direct.positive);
+    }
+
+    #[test]
+    fn test_histogram_bucket_limit() {
+        let histogram = Histogram::from_values((0..10_000).map(|i| 1.001f64.powi(i * 7) * 1e-6));
+        assert!(histogram.positive.len() <= MAX_HISTOGRAM_BUCKETS);
+        assert!(histogram.scale < DEFAULT_HISTOGRAM_SCALE);
+        assert_eq!(histogram.count(), 10_000);
+        
+        let extreme = Histogram::from_values([f64::MIN_POSITIVE, f64::MAX, f64::INFINITY, 5e-324]);
+        assert_eq!(extreme.count(), 4);
+        assert_eq!(extreme.quantile(1.0), Some(f64::INFINITY));
+    }
+}
@@ -1,5 +1,17 @@
 pub mod data_model;
+pub mod value;
+pub mod histogram;
+pub mod series;
+pub mod matcher;
+pub mod metadata;
+pub mod precision;
 pub mod error;
 
 pub use data_model::*;
+pub use value::*;
+pub use histogram::*;
+pub use series::*;
+pub use matcher::*;
+pub use metadata::*;
+pub use precision::*;
 pub use error::*;
\ No newline at end of file
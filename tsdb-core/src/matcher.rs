@@ -0,0 +1,176 @@
+use crate::error::TsdbError;
+use crate::series::{is_valid_name, parse_label_set, SeriesKey};
+use regex::Regex;
+use std::fmt;
+
+/// The pseudo-label matchers use to select on a series' metric name.
+pub const METRIC_NAME_LABEL: &str = "__name__";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOp {
+    /// `=`
+    Equal,
+    /// `!=`
+    NotEqual,
+    /// `=~`
+    RegexMatch,
+    /// `!~`
+    RegexNoMatch,
+}
+
+/// A condition on one label of a series.
+///
+/// A series without the label is treated as having it set to the empty
+/// string, so `dc!="x"` also selects series with no `dc` label at all.
+/// Regular expressions must match the whole value.
+#[derive(Debug, Clone)]
+pub struct LabelMatcher {
+    name: String,
+    op: MatchOp,
+    value: String,
+    regex: Option<Regex>,
+}
+
+impl LabelMatcher {
+    /// Fails with [`TsdbError::InvalidSelector`] if `op` is a regex match and
+    /// `value` is not a valid regular expression.
+    pub fn new(name: impl Into<String>, op: MatchOp, value: impl Into<String>) -> Result<Self, TsdbError> {
+        let value = value.into();
+        let regex = match op {
+            MatchOp::RegexMatch | MatchOp::RegexNoMatch => Some(
+                Regex::new(&format!("^(?:{})$", value))
+                    .map_err(|e| TsdbError::InvalidSelector(format!("invalid regex {:?}: {}", value, e)))?,
+            ),
+            MatchOp::Equal | MatchOp::NotEqual => None,
+        };
+        Ok(Self { name: name.into(), op, value, regex })
+    }
+    
+    pub fn equal(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { name: name.into(), op: MatchOp::Equal, value: value.into(), regex: None }
+    }
+    
+    pub fn not_equal(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { name: name.into(), op: MatchOp::NotEqual, value: value.into(), regex: None }
+    }
+    
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    
+    pub fn op(&self) -> MatchOp {
+        self.op
+    }
+    
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+    
+    /// Whether a label holding `value`, or the empty string when absent,
+    /// satisfies the matcher.
+    pub fn matches_value(&self, value: &str) -> bool {
+        match (self.op, &self.regex) {
+            (MatchOp::Equal, _) => value == self.value,
+            (MatchOp::NotEqual, _) => value != self.value,
+            (MatchOp::RegexMatch, Some(regex)) => regex.is_match(value),
+            (MatchOp::RegexNoMatch, Some(regex)) => !regex.is_match(value),
+            (_, None) => unreachable!("regex matchers are built with their regex"),
+        }
+    }
+    
+    pub fn matches(&self, key: &SeriesKey) -> bool {
+        let value = if self.name == METRIC_NAME_LABEL {
+            key.metric.as_str()
+        } else {
+            key.label(&self.name).unwrap_or("")
+        };
+        self.matches_value(value)
+    }
+    
+    /// Parses a selector such as `http_requests{job="api",status=~"5.."}`
+    /// into its matchers. The metric name is optional and becomes a
+    /// `__name__` equality matcher; so is the label set, but not both.
+    pub fn parse_selector(input: &str) -> Result<Vec<LabelMatcher>, TsdbError> {
+        let invalid = |reason: &str| TsdbError::InvalidSelector(format!("{} in {:?}", reason, input));
+        
+        let trimmed = input.trim();
+        let (metric, labels) = match trimmed.find('{') {
+            Some(open) => (trimmed[..open].trim_end(), Some(&trimmed[open + 1..])),
+            None => (trimmed, None),
+        };
+        
+        let mut matchers = Vec::new();
+        if !metric.is_empty() {
+            if !is_valid_name(metric, true) {
+                return Err(invalid("invalid metric name"));
+            }
+            matchers.push(LabelMatcher::equal(METRIC_NAME_LABEL, metric));
+        } else if labels.is_none() {
+            return Err(invalid("empty selector"));
+        }
+        
+        if let Some(labels) = labels {
+            let ops = [("=~", MatchOp::RegexMatch), ("!~", MatchOp::RegexNoMatch), ("!=", MatchOp::NotEqual), ("=", MatchOp::Equal)];
+            parse_label_set(labels, &ops, invalid, |name, op, value| {
+                matchers.push(LabelMatcher::new(name, op, value)?);
+                Ok(())
+            })?;
+        }
+        Ok(matchers)
+    }
+}
+
+impl fmt::Display for LabelMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self.op {
+            MatchOp::Equal => "=",
+            MatchOp::NotEqual => "!=",
+            MatchOp::RegexMatch => "=~",
+            MatchOp::RegexNoMatch => "!~",
+        };
+        write!(f, "{}{}{:?}", self.name, op, self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_selector() {
+        let matchers = LabelMatcher::parse_selector(r#"http_requests{job="api", status=~"5..", dc!="x", path!~"/health.*"}"#).unwrap();
+        let parsed: Vec<_> = matchers.iter().map(|m| (m.name(), m.op(), m.value())).collect();
+        assert_eq!(parsed, vec![
+            (METRIC_NAME_LABEL, MatchOp::Equal, "http_requests"),
+            ("job", MatchOp::Equal, "api"),
+            ("status", MatchOp::RegexMatch, "5.."),
+            ("dc", MatchOp::NotEqual, "x"),
+            ("path", MatchOp::RegexNoMatch, "/health.*"),
+        ]);
+        
+        assert_eq!(LabelMatcher::parse_selector(r#"{job="api"}"#).unwrap().len(), 1);
+        assert_eq!(LabelMatcher::parse_selector("up").unwrap().len(), 1);
+        
+        for input in ["", "{", r#"{job="api""#, r#"{job~"api"}"#, r#"{job=~"("}"#, r#"{job="api"} x"#] {
+            assert!(matches!(LabelMatcher::parse_selector(input), Err(TsdbError::InvalidSelector(_))), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_label_matcher_matches() {
+        let key = SeriesKey::new("http_requests").with_label("job", "api").with_label("status", "503");
+        let matches = |selector: &str| {
+            LabelMatcher::parse_selector(selector).unwrap().iter().all(|m| m.matches(&key))
+        };
+        
+        assert!(matches(r#"http_requests{job="api"}"#));
+        assert!(matches(r#"{status=~"5.."}"#));
+        assert!(!matches(r#"{status=~"5"}"#), "regexes are anchored");
+        assert!(matches(r#"{status!~"2.."}"#));
+        assert!(!matches(r#"{job!="api"}"#));
+        // An absent label reads as empty.
+        assert!(matches(r#"{dc!="x"}"#));
+        assert!(matches(r#"{dc=""}"#));
+        assert!(!matches(r#"{dc=~".+"}"#));
+    }
+}
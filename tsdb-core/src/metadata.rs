@@ -0,0 +1,77 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// What a series measures, which decides the functions that make sense over
+/// it: a rate only means something for a counter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum MetricType {
+    /// Nothing was declared; every function is allowed.
+    #[default]
+    Unknown,
+    /// A value that goes up and down, such as a temperature.
+    Gauge,
+    /// A running total that only increases, except when it resets to zero.
+    Counter,
+    /// A distribution per sample, stored as histogram values.
+    Histogram,
+}
+
+impl fmt::Display for MetricType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MetricType::Unknown => "unknown",
+            MetricType::Gauge => "gauge",
+            MetricType::Counter => "counter",
+            MetricType::Histogram => "histogram",
+        };
+        f.write_str(name)
+    }
+}
+
+/// What is known about a series beyond its points.
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SeriesMetadata {
+    pub metric_type: MetricType,
+    /// The unit values are in, such as `"seconds"` or `"bytes"`.
+    pub unit: Option<String>,
+    /// A description of what the series means.
+    pub help: Option<String>,
+}
+
+impl SeriesMetadata {
+    pub fn new(metric_type: MetricType) -> Self {
+        Self { metric_type, ..Self::default() }
+    }
+    
+    pub fn with_unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+    
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+    
+    /// Whether the series may be a counter: declared as one, or undeclared.
+    pub fn may_be_counter(&self) -> bool {
+        matches!(self.metric_type, MetricType::Counter | MetricType::Unknown)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_metadata() {
+        let metadata = SeriesMetadata::new(MetricType::Counter)
+            .with_unit("bytes")
+            .with_help("Bytes received on all interfaces");
+        assert_eq!(metadata.unit.as_deref(), Some("bytes"));
+        assert!(metadata.may_be_counter());
+        assert!(SeriesMetadata::default().may_be_counter());
+        assert!(!SeriesMetadata::new(MetricType::Gauge).may_be_counter());
+        assert_eq!(MetricType::Histogram.to_string(), "histogram");
+    }
+}
@@ -0,0 +1,106 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The unit a series counts its timestamps in, as time since the Unix
+/// epoch.
+///
+/// Timestamps are plain `u64`s everywhere; the precision says how to read
+/// them. It decides how long a block spans, when it is due to be sealed, how
+/// wide the delta-of-delta buckets of its compressed timestamps are, and
+/// what a query window of a given size means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default, Serialize, Deserialize)]
+pub enum TimestampPrecision {
+    #[default]
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimestampPrecision {
+    pub fn units_per_second(self) -> u64 {
+        match self {
+            TimestampPrecision::Milliseconds => 1_000,
+            TimestampPrecision::Microseconds => 1_000_000,
+            TimestampPrecision::Nanoseconds => 1_000_000_000,
+        }
+    }
+    
+    /// The number of units in `duration`, rounded down and saturating at
+    /// `u64::MAX`.
+    pub fn from_duration(self, duration: Duration) -> u64 {
+        let units = match self {
+            TimestampPrecision::Milliseconds => duration.as_millis(),
+            TimestampPrecision::Microseconds => duration.as_micros(),
+            TimestampPrecision::Nanoseconds => duration.as_nanos(),
+        };
+        u64::try_from(units).unwrap_or(u64::MAX)
+    }
+    
+    pub fn to_duration(self, units: u64) -> Duration {
+        match self {
+            TimestampPrecision::Milliseconds => Duration::from_millis(units),
+            TimestampPrecision::Microseconds => Duration::from_micros(units),
+            TimestampPrecision::Nanoseconds => Duration::from_nanos(units),
+        }
+    }
+    
+    /// The current time in this unit.
+    pub fn now(self) -> u64 {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.from_duration(since_epoch)
+    }
+    
+    /// Converts `timestamp`, counted in `from`, to this unit. Going to a
+    /// coarser unit rounds down; going to a finer one saturates.
+    pub fn convert(self, timestamp: u64, from: TimestampPrecision) -> u64 {
+        let (to_units, from_units) = (self.units_per_second(), from.units_per_second());
+        if to_units >= from_units {
+            timestamp.saturating_mul(to_units / from_units)
+        } else {
+            timestamp / (from_units / to_units)
+        }
+    }
+}
+
+impl fmt::Display for TimestampPrecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let unit = match self {
+            TimestampPrecision::Milliseconds => "ms",
+            TimestampPrecision::Microseconds => "us",
+            TimestampPrecision::Nanoseconds => "ns",
+        };
+        f.write_str(unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timestamp_precision_durations() {
+        let two_hours = Duration::from_secs(2 * 60 * 60);
+        assert_eq!(TimestampPrecision::Milliseconds.from_duration(two_hours), 7_200_000);
+        assert_eq!(TimestampPrecision::Microseconds.from_duration(two_hours), 7_200_000_000);
+        assert_eq!(TimestampPrecision::Nanoseconds.from_duration(two_hours), 7_200_000_000_000);
+        assert_eq!(TimestampPrecision::Nanoseconds.from_duration(Duration::MAX), u64::MAX);
+        assert_eq!(TimestampPrecision::Microseconds.to_duration(1_500), Duration::from_micros(1_500));
+        
+        let ms = TimestampPrecision::Milliseconds.now();
+        let ns = TimestampPrecision::Nanoseconds.now();
+        assert!(ns / 1_000_000 >= ms);
+    }
+
+    #[test]
+    fn test_timestamp_precision_convert() {
+        use TimestampPrecision::*;
+        assert_eq!(Nanoseconds.convert(1_600_000_000_123, Milliseconds), 1_600_000_000_123_000_000);
+        assert_eq!(Milliseconds.convert(1_600_000_000_123_456_789, Nanoseconds), 1_600_000_000_123);
+        assert_eq!(Microseconds.convert(42, Microseconds), 42);
+        assert_eq!(Nanoseconds.convert(u64::MAX / 10, Milliseconds), u64::MAX);
+        assert_eq!(Nanoseconds.to_string(), "ns");
+    }
+}
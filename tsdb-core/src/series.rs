@@ -0,0 +1,335 @@
+use crate::error::TsdbError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// The identity of a series: a metric name plus a set of labels, such as
+/// `cpu_usage{dc="x",host="a"}`.
+///
+/// Labels are kept sorted by name, so the same set written in any order is
+/// the same series. A label with an empty value is the same as no label at
+/// all. A plain string such as `"server.cpu.percent"` converts to a series
+/// with that metric name and no labels.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SeriesKey {
+    pub metric: String,
+    pub labels: BTreeMap<String, String>,
+}
+
+/// A fixed-size handle for a [`SeriesKey`], used to address series in
+/// storage, the WAL and queries.
+///
+/// It is a 64-bit FNV-1a hash of the key. The hash is part of the on-disk
+/// format: the same key maps to the same id in every process and release.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct SeriesId(pub u64);
+
+impl SeriesKey {
+    /// A series of `metric` without labels.
+    pub fn new(metric: impl Into<String>) -> Self {
+        Self { metric: metric.into(), labels: BTreeMap::new() }
+    }
+    
+    /// Adds or replaces a label. An empty value removes it.
+    pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let (name, value) = (name.into(), value.into());
+        if value.is_empty() {
+            self.labels.remove(&name);
+        } else {
+            self.labels.insert(name, value);
+        }
+        self
+    }
+    
+    /// Parses `metric{name="value",...}`. The label set is optional, so a
+    /// bare metric name parses as a label-less series.
+    ///
+    /// Metric names are made of ASCII letters, digits, `_`, `:` and `.` and
+    /// do not start with a digit; label names are the same without `:` and
+    /// `.`. Values are double-quoted and may escape `\\`, `\"` and `\n`.
+    pub fn parse(input: &str) -> Result<Self, TsdbError> {
+        let invalid = |reason: &str| TsdbError::InvalidSeriesKey(format!("{} in {:?}", reason, input));
+        
+        let trimmed = input.trim();
+        let (metric, labels) = match trimmed.find('{') {
+            Some(open) => (trimmed[..open].trim_end(), Some(&trimmed[open + 1..])),
+            None => (trimmed, None),
+        };
+        if !is_valid_name(metric, true) {
+            return Err(invalid("invalid metric name"));
+        }
+        
+        let mut key = SeriesKey::new(metric);
+        if let Some(labels) = labels {
+            parse_label_set(labels, &[("=", ())], invalid, |name, (), value| {
+                if key.labels.contains_key(name) {
+                    return Err(invalid("duplicate label name"));
+                }
+                // Empty values are left out, as in `with_label`.
+                if !value.is_empty() {
+                    key.labels.insert(name.to_string(), value);
+                }
+                Ok(())
+            })?;
+        }
+        Ok(key)
+    }
+    
+    pub fn label(&self, name: &str) -> Option<&str> {
+        self.labels.get(name).map(String::as_str)
+    }
+    
+    pub fn id(&self) -> SeriesId {
+        // Separators that cannot occur in UTF-8 keep the boundaries between
+        // names and values unambiguous.
+        let mut hash = FNV_OFFSET_BASIS;
+        hash = fnv1a(hash, self.metric.as_bytes());
+        for (name, value) in &self.labels {
+            hash = fnv1a(hash, &[0xff]);
+            hash = fnv1a(hash, name.as_bytes());
+            hash = fnv1a(hash, &[0xfe]);
+            hash = fnv1a(hash, value.as_bytes());
+        }
+        SeriesId(hash)
+    }
+}
+
+impl FromStr for SeriesKey {
+    type Err = TsdbError;
+    
+    fn from_str(input: &str) -> Result<Self, TsdbError> {
+        Self::parse(input)
+    }
+}
+
+/// Writes the key in the syntax [`SeriesKey::parse`] reads, with labels in
+/// name order. Label-less series print as their metric name alone.
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.metric)?;
+        if self.labels.is_empty() {
+            return Ok(());
+        }
+        
+        f.write_str("{")?;
+        for (i, (name, value)) in self.labels.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}=\"", name)?;
+            for c in value.chars() {
+                match c {
+                    '\\' => f.write_str("\\\\")?,
+                    '"' => f.write_str("\\\"")?,
+                    '\n' => f.write_str("\\n")?,
+                    c => write!(f, "{}", c)?,
+                }
+            }
+            f.write_str("\"")?;
+        }
+        f.write_str("}")
+    }
+}
+
+impl From<String> for SeriesKey {
+    fn from(metric: String) -> Self {
+        SeriesKey::new(metric)
+    }
+}
+
+impl From<&str> for SeriesKey {
+    fn from(metric: &str) -> Self {
+        SeriesKey::new(metric)
+    }
+}
+
+/// Lets label-less keys be compared against plain names.
+impl PartialEq<&str> for SeriesKey {
+    fn eq(&self, other: &&str) -> bool {
+        self.labels.is_empty() && self.metric == *other
+    }
+}
+
+impl PartialEq<String> for SeriesKey {
+    fn eq(&self, other: &String) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl fmt::Display for SeriesId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl From<&SeriesKey> for SeriesId {
+    fn from(key: &SeriesKey) -> Self {
+        key.id()
+    }
+}
+
+impl From<SeriesKey> for SeriesId {
+    fn from(key: SeriesKey) -> Self {
+        key.id()
+    }
+}
+
+/// The id of the label-less series named `metric`.
+impl From<&str> for SeriesId {
+    fn from(metric: &str) -> Self {
+        SeriesKey::new(metric).id()
+    }
+}
+
+impl From<&String> for SeriesId {
+    fn from(metric: &String) -> Self {
+        SeriesId::from(metric.as_str())
+    }
+}
+
+impl From<String> for SeriesId {
+    fn from(metric: String) -> Self {
+        SeriesId::from(metric.as_str())
+    }
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+pub(crate) fn is_valid_name(name: &str, metric: bool) -> bool {
+    let mut chars = name.chars();
+    let allowed = |c: char| c.is_ascii_alphanumeric() || c == '_' || (metric && (c == ':' || c == '.'));
+    match chars.next() {
+        Some(first) if allowed(first) && !first.is_ascii_digit() => chars.all(allowed),
+        _ => false,
+    }
+}
+
+/// Parses the labels of a series key or selector, `input` being the text
+/// after the opening `{`. Each label is a name, one of the `ops` tokens and
+/// a quoted value, which are handed to `label`. The set must be closed by a
+/// `}` with nothing after it.
+pub(crate) fn parse_label_set<T: Copy>(
+    input: &str,
+    ops: &[(&str, T)],
+    invalid: impl Fn(&str) -> TsdbError,
+    mut label: impl FnMut(&str, T, String) -> Result<(), TsdbError>,
+) -> Result<(), TsdbError> {
+    let mut rest = input;
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix('}') {
+            if !after.trim().is_empty() {
+                return Err(invalid("unexpected text after the label set"));
+            }
+            return Ok(());
+        }
+        if rest.is_empty() {
+            return Err(invalid("unterminated label set"));
+        }
+        
+        let name_len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+        let name = &rest[..name_len];
+        if !is_valid_name(name, false) {
+            return Err(invalid("invalid label name"));
+        }
+        rest = rest[name_len..].trim_start();
+        let (op, after) = ops.iter()
+            .find_map(|&(token, op)| rest.strip_prefix(token).map(|after| (op, after)))
+            .ok_or_else(|| invalid("expected an operator after label name"))?;
+        let (value, after) = parse_quoted(after.trim_start()).ok_or_else(|| invalid("invalid label value"))?;
+        label(name, op, value)?;
+        
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with('}') {
+            return Err(invalid("expected ',' or '}' after label value"));
+        }
+    }
+}
+
+/// Reads a double-quoted string from the start of `input`, returning it
+/// unescaped along with the text after the closing quote.
+pub(crate) fn parse_quoted(input: &str) -> Option<(String, &str)> {
+    let body = input.strip_prefix('"')?;
+    let mut value = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &body[i + 1..])),
+            '\\' => match chars.next()?.1 {
+                '\\' => value.push('\\'),
+                '"' => value.push('"'),
+                'n' => value.push('\n'),
+                _ => return None,
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_key_parse() {
+        let key = SeriesKey::parse(r#"cpu_usage{host="a", dc="x"}"#).unwrap();
+        assert_eq!(key.metric, "cpu_usage");
+        assert_eq!(key.label("host"), Some("a"));
+        assert_eq!(key.label("dc"), Some("x"));
+        assert_eq!(key.to_string(), r#"cpu_usage{dc="x",host="a"}"#);
+        
+        assert_eq!(SeriesKey::parse("server.cpu.percent").unwrap(), SeriesKey::new("server.cpu.percent"));
+        assert_eq!(SeriesKey::parse("up{}").unwrap(), SeriesKey::new("up"));
+        assert_eq!(SeriesKey::parse(r#"up{job="api",}"#).unwrap(), SeriesKey::new("up").with_label("job", "api"));
+        assert_eq!(SeriesKey::parse(r#"up{job=""}"#).unwrap(), SeriesKey::new("up"));
+        
+        let escaped = SeriesKey::new("log_lines").with_label("msg", "say \"hi\"\\\n");
+        assert_eq!(escaped.to_string().parse::<SeriesKey>().unwrap(), escaped);
+    }
+
+    #[test]
+    fn test_series_key_parse_errors() {
+        for input in [
+            "",
+            "9lives",
+            "cpu usage",
+            r#"cpu{host="a""#,
+            r#"cpu{host="a" dc="x"}"#,
+            r#"cpu{host=a}"#,
+            r#"cpu{host="a",host="b"}"#,
+            r#"cpu{1host="a"}"#,
+            r#"cpu{host="a\q"}"#,
+            r#"cpu{host="a"} extra"#,
+        ] {
+            assert!(matches!(SeriesKey::parse(input), Err(TsdbError::InvalidSeriesKey(_))), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_series_id() {
+        let a = SeriesKey::parse(r#"cpu{host="a",dc="x"}"#).unwrap();
+        let b = SeriesKey::new("cpu").with_label("dc", "x").with_label("host", "a");
+        assert_eq!(a.id(), b.id());
+        assert_ne!(a.id(), SeriesKey::new("cpu").with_label("host", "a").id());
+        assert_ne!(SeriesKey::new("cpu").with_label("a", "bc").id(), SeriesKey::new("cpu").with_label("ab", "c").id());
+        
+        // Plain names address their label-less series.
+        assert_eq!(SeriesId::from("server.cpu.percent"), SeriesKey::new("server.cpu.percent").id());
+        // Ids are persisted, so the hash must never change.
+        assert_eq!(SeriesId::from(""), SeriesId(0xcbf2_9ce4_8422_2325));
+        assert_eq!(SeriesId::from("a"), SeriesId(0xaf63_dc4c_8601_ec8c));
+    }
+}
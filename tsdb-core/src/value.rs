@@ -0,0 +1,200 @@
+use crate::histogram::Histogram;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// The kind of values a series holds. A series keeps one type for its whole
+/// life, and every block records the type of its points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum ValueType {
+    #[default]
+    Float,
+    Integer,
+    Boolean,
+    String,
+    Histogram,
+}
+
+impl ValueType {
+    /// Whether values of this type can be summed, averaged and compared.
+    pub fn is_numeric(self) -> bool {
+        matches!(self, ValueType::Float | ValueType::Integer)
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::Float => "float",
+            ValueType::Integer => "integer",
+            ValueType::Boolean => "boolean",
+            ValueType::String => "string",
+            ValueType::Histogram => "histogram",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A single sample. Strings are meant for short values such as versions,
+/// states and error messages; histograms for whole distributions, such as
+/// the latencies seen since the previous sample.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+    Float(f64),
+    Integer(i64),
+    Boolean(bool),
+    String(String),
+    Histogram(Box<Histogram>),
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Float(_) => ValueType::Float,
+            Value::Integer(_) => ValueType::Integer,
+            Value::Boolean(_) => ValueType::Boolean,
+            Value::String(_) => ValueType::String,
+            Value::Histogram(_) => ValueType::Histogram,
+        }
+    }
+    
+    /// The value of a float sample.
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(value) => Some(value),
+            _ => None,
+        }
+    }
+    
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            Value::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+    
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            Value::Boolean(value) => Some(value),
+            _ => None,
+        }
+    }
+    
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+    
+    pub fn as_histogram(&self) -> Option<&Histogram> {
+        match self {
+            Value::Histogram(value) => Some(value),
+            _ => None,
+        }
+    }
+    
+    /// The value as a number for arithmetic, for float and integer samples.
+    /// Integers beyond 2^53 lose precision.
+    pub fn to_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Float(value) => Some(value),
+            Value::Integer(value) => Some(value as f64),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Float(0.0)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Float(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Boolean(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_string())
+    }
+}
+
+impl From<Histogram> for Value {
+    fn from(value: Histogram) -> Self {
+        Value::Histogram(Box::new(value))
+    }
+}
+
+/// Lets float samples be compared against plain numbers.
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool {
+        self.as_f64() == Some(*other)
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Float(value) => value.fmt(f),
+            Value::Integer(value) => value.fmt(f),
+            Value::Boolean(value) => value.fmt(f),
+            Value::String(value) => value.fmt(f),
+            Value::Histogram(value) => value.fmt(f),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_types() {
+        assert_eq!(Value::from(1.5).value_type(), ValueType::Float);
+        assert_eq!(Value::from(-3i64).value_type(), ValueType::Integer);
+        assert_eq!(Value::from(true).value_type(), ValueType::Boolean);
+        assert_eq!(Value::from("v1.2.0").value_type(), ValueType::String);
+        assert!(ValueType::Integer.is_numeric());
+        assert!(!ValueType::String.is_numeric());
+        assert_eq!(Value::from(Histogram::from_values([1.0, 2.0])).value_type(), ValueType::Histogram);
+    }
+
+    #[test]
+    fn test_value_accessors() {
+        assert_eq!(Value::Integer(7).to_f64(), Some(7.0));
+        assert_eq!(Value::Integer(7).as_f64(), None);
+        assert_eq!(Value::Boolean(true).to_f64(), None);
+        assert_eq!(Value::from("ok").as_str(), Some("ok"));
+        assert_eq!(Value::Float(2.5), 2.5);
+        assert_ne!(Value::Integer(2), 2.0);
+    }
+
+    #[test]
+    fn test_value_display() {
+        assert_eq!(format!("{:.2}", Value::Float(1.0 / 3.0)), "0.33");
+        assert_eq!(Value::Integer(-4).to_string(), "-4");
+        assert_eq!(Value::from("down").to_string(), "down");
+        assert_eq!(ValueType::Boolean.to_string(), "boolean");
+    }
+}